@@ -0,0 +1,178 @@
+// Symphonia
+// Copyright (c) 2019-2022 The Project Symphonia Developers.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Interactive Keyboard Controls
+
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+
+use log::warn;
+
+/// How often the input thread checks if it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The number of seconds seeked by the left and right arrow keys.
+const SHORT_SEEK: f64 = 5.0;
+
+/// The number of seconds seeked by the up and down arrow keys.
+const LONG_SEEK: f64 = 60.0;
+
+/// The number of decibels the volume is changed by per key press.
+const VOLUME_STEP: f64 = 2.0;
+
+/// A command entered on the keyboard.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Pause or resume playback.
+    TogglePause,
+    /// Seek forwards by a number of seconds, or backwards if negative.
+    Seek(f64),
+    /// Change the volume by a number of decibels.
+    Volume(f64),
+    /// Skip forwards by a number of tracks, or backwards if negative.
+    Skip(i32),
+    /// Hide or show the visualiser.
+    ToggleDisplay,
+    /// Stop playback.
+    Quit,
+}
+
+/// Reads keys from the terminal on a separate thread while the terminal is in raw mode.
+pub struct Controls {
+    commands: Receiver<Command>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Controls {
+    /// Puts the terminal in raw mode and starts reading keys. Returns `None` if standard input is
+    /// not a terminal.
+    pub fn try_open() -> Option<Self> {
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+
+        if let Err(err) = terminal::enable_raw_mode() {
+            warn!("failed to enable raw mode: {}", err);
+            return None;
+        }
+
+        let (sender, commands) = mpsc::channel();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = Arc::clone(&stop);
+
+        let handle = thread::Builder::new()
+            .name("controls".to_string())
+            .spawn(move || run(&sender, &worker_stop));
+
+        match handle {
+            Ok(handle) => Some(Controls { commands, stop, handle: Some(handle) }),
+            Err(err) => {
+                warn!("failed to spawn controls thread: {}", err);
+                let _ = terminal::disable_raw_mode();
+                None
+            }
+        }
+    }
+
+    /// Gets the next command without blocking.
+    pub fn poll(&self) -> Option<Command> {
+        self.commands.try_recv().ok()
+    }
+
+    /// Waits up-to `timeout` for the next command.
+    pub fn wait(&self, timeout: Duration) -> Option<Command> {
+        match self.commands.recv_timeout(timeout) {
+            Ok(command) => Some(command),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                // The input thread has stopped, sleep so that callers waiting in a loop do not
+                // spin.
+                thread::sleep(timeout);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for Controls {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// The input thread. Keys are read until the thread is asked to stop, or the receiver is gone.
+fn run(sender: &Sender<Command>, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        // Poll with a timeout so that the stop flag is checked regularly.
+        match event::poll(POLL_INTERVAL) {
+            Ok(true) => (),
+            Ok(false) => continue,
+            Err(err) => {
+                warn!("failed to poll keyboard: {}", err);
+                break;
+            }
+        }
+
+        let command = match event::read() {
+            Ok(Event::Key(key)) => map_key(key),
+            Ok(_) => None,
+            Err(err) => {
+                warn!("failed to read keyboard: {}", err);
+                break;
+            }
+        };
+
+        if let Some(command) = command {
+            if sender.send(command).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Maps a key press to a command.
+fn map_key(key: KeyEvent) -> Option<Command> {
+    if key.kind != KeyEventKind::Press {
+        return None;
+    }
+
+    let command = match key.code {
+        // Raw mode disables the interrupt signal, so Ctrl-C must be handled here.
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Command::Quit,
+        KeyCode::Char(' ') | KeyCode::Char('p') => Command::TogglePause,
+        KeyCode::Left => Command::Seek(-SHORT_SEEK),
+        KeyCode::Right => Command::Seek(SHORT_SEEK),
+        KeyCode::Down => Command::Seek(-LONG_SEEK),
+        KeyCode::Up => Command::Seek(LONG_SEEK),
+        KeyCode::Char('+') | KeyCode::Char('=') | KeyCode::Char('0') => {
+            Command::Volume(VOLUME_STEP)
+        }
+        KeyCode::Char('-') | KeyCode::Char('9') => Command::Volume(-VOLUME_STEP),
+        KeyCode::Char('n') | KeyCode::Char('>') => Command::Skip(1),
+        KeyCode::Char('b') | KeyCode::Char('<') => Command::Skip(-1),
+        KeyCode::Char('v') => Command::ToggleDisplay,
+        KeyCode::Char('q') | KeyCode::Esc => Command::Quit,
+        _ => return None,
+    };
+
+    Some(command)
+}
@@ -1,100 +1,703 @@
 use symphonia::core::audio::{AudioBufferRef, SignalSpec};
-use symphonia::core::units::Duration;
+use symphonia::core::units::{Duration, TimeBase};
 
 
-pub trait Display {
-    fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()>;
-    fn flush(&mut self);
+pub trait Display: Send {
+    /// Writes a decoded buffer whose first frame has the timestamp `ts`, in the track's time base.
+    fn write(&mut self, decoded: AudioBufferRef<'_>, ts: u64) -> Result<()>;
+    /// Draws the final state of the display and restores the terminal, or writes the output file
+    /// of an offline display.
+    fn flush(&mut self) -> Result<()>;
+    /// Prepares the display for buffers with a different spec, or a larger capacity, continuing
+    /// what it shows. Returns false if the display cannot, in which case it is reopened.
+    fn reconfigure(&mut self, _spec: SignalSpec, _duration: Duration) -> Result<bool> {
+        Ok(false)
+    }
+    /// Starts the next input or track, whose timestamps are in the time base `tb` and count from
+    /// its own start.
+    fn start_input(&mut self, _tb: Option<TimeBase>) {}
 }
 
-#[allow(dead_code)]
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum DisplayError {
-    GenericError,
+    /// The display could not be opened.
+    OpenError(String),
+    /// The display failed to process a buffer or write its output.
+    WriteError(String),
+}
+
+impl fmt::Display for DisplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisplayError::OpenError(msg) => write!(f, "failed to open display: {}", msg),
+            DisplayError::WriteError(msg) => write!(f, "display error: {}", msg),
+        }
+    }
 }
 
 pub type Result<T> = result::Result<T, DisplayError>;
 
+/// The error of an offline display given audio with a different sample rate than it was opened
+/// with. The frequency axis depends on the sample rate, so the audio cannot be analysed into the
+/// same file.
+fn rate_changed(from: u32, to: u32) -> DisplayError {
+    DisplayError::WriteError(format!(
+        "the sample rate changed from {} Hz to {} Hz, which cannot be analysed into one file",
+        from, to
+    ))
+}
+
+pub use bars::Bands;
+pub use export::{ExportFormat, ExportOptions, ExportScale};
+pub use image::ImageOptions;
+pub use loudness::LoudnessHistory;
+pub use stft::{StftOptions, WindowFunction};
+
+/// The visualisers that can be shown in the terminal.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DisplayKind {
+    /// A scrolling spectrogram.
+    #[default]
+    Spectrogram,
+    /// A scrolling waveform overview.
+    Waveform,
+    /// Peak and RMS meters for each channel.
+    Meter,
+    /// An XY goniometer, or an oscilloscope for mono streams.
+    Scope,
+    /// A spectrum analyser showing octave or third-octave bands.
+    Bars,
+    /// EBU R128 loudness and true-peak meters.
+    Loudness,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DisplayOptions {
+    pub kind: DisplayKind,
+    pub stft: StftOptions,
+    /// The width of the bands shown by the spectrum analyser.
+    pub bands: Bands,
+    /// If set, the spectrogram of the whole stream is rendered to an image file instead of being
+    /// shown in the terminal.
+    pub image: Option<ImageOptions>,
+    /// If set, the STFT of the whole stream is exported to a file.
+    pub export: Option<ExportOptions>,
+}
+
+impl DisplayOptions {
+    /// Returns true if the display renders to a file, in which case nothing is played back.
+    pub fn is_offline(&self) -> bool {
+        self.image.is_some() || self.export.is_some()
+    }
+}
 
+
+use std::fmt;
 use std::result;
 
 
+/// Colour maps used to turn normalized intensities into RGB colours.
+mod colormap {
+    /// Control points of the colour map, evenly spaced over `[0, 1]`. The colours approximate the
+    /// "inferno" perceptually uniform colour map.
+    const INFERNO: [(u8, u8, u8); 9] = [
+        (0, 0, 4),
+        (31, 12, 72),
+        (85, 15, 109),
+        (136, 34, 106),
+        (186, 54, 85),
+        (227, 89, 51),
+        (249, 140, 10),
+        (249, 201, 50),
+        (252, 255, 164),
+    ];
+
+    /// Maps a normalized intensity in `[0, 1]` to an RGB colour.
+    pub fn inferno(t: f32) -> (u8, u8, u8) {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+
+        let pos = t * (INFERNO.len() - 1) as f32;
+        let idx = (pos.floor() as usize).min(INFERNO.len() - 2);
+        let frac = pos - idx as f32;
+
+        let (r0, g0, b0) = INFERNO[idx];
+        let (r1, g1, b1) = INFERNO[idx + 1];
+
+        let lerp = |a: u8, b: u8| (f32::from(a) + (f32::from(b) - f32::from(a)) * frac).round() as u8;
+
+        (lerp(r0, r1), lerp(g0, g1), lerp(b0, b1))
+    }
+}
+
+/// Primitives for drawing to a terminal with ANSI escape sequences.
+mod term {
+    use std::fmt::Write as _;
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    /// The refresh rate redraws are throttled to.
+    const REFRESH_RATE: u32 = 60;
+
+    /// Default size used when the size of the terminal cannot be queried.
+    const DEFAULT_SIZE: (usize, usize) = (80, 24);
+
+    /// Gets the size of the area a display may draw to as a `(columns, rows)` pair. The bottom
+    /// row of the terminal is reserved for the playback status line.
+    pub fn size() -> (usize, usize) {
+        let (cols, rows) = match terminal_size::terminal_size() {
+            Some((terminal_size::Width(w), terminal_size::Height(h))) => (w as usize, h as usize),
+            None => DEFAULT_SIZE,
+        };
+
+        (cols, rows.saturating_sub(1))
+    }
+
+    /// Clears the screen and hides the cursor.
+    pub fn enter() {
+        let stdout = std::io::stdout();
+        let mut output = stdout.lock();
+
+        let _ = write!(output, "\x1b[?25l\x1b[2J");
+        let _ = output.flush();
+    }
+
+    /// Clears the screen.
+    pub fn clear() {
+        let stdout = std::io::stdout();
+        let mut output = stdout.lock();
+
+        let _ = write!(output, "\x1b[0m\x1b[2J");
+        let _ = output.flush();
+    }
+
+    /// Resets all attributes, shows the cursor, and moves it to the reserved bottom row.
+    pub fn leave() {
+        let stdout = std::io::stdout();
+        let mut output = stdout.lock();
+
+        let (_, rows) = size();
+
+        let _ = write!(output, "\x1b[0m\x1b[{};1H\x1b[?25h", rows + 1);
+        let _ = output.flush();
+    }
+
+    /// Writes a string of text and escape sequences to the terminal in one go.
+    pub fn draw(out: &str) {
+        let stdout = std::io::stdout();
+        let mut output = stdout.lock();
+
+        let _ = output.write_all(out.as_bytes());
+        let _ = output.flush();
+    }
+
+    /// Limits how often a redraw may happen to the terminal refresh rate.
+    pub struct FrameLimiter {
+        interval: Duration,
+        last: Option<Instant>,
+    }
+
+    impl FrameLimiter {
+        pub fn new() -> Self {
+            FrameLimiter { interval: Duration::from_secs(1) / REFRESH_RATE, last: None }
+        }
+
+        /// Returns true if enough time has passed since the last redraw, and if so, starts a new
+        /// interval.
+        pub fn ready(&mut self) -> bool {
+            let now = Instant::now();
+
+            match self.last {
+                Some(last) if now.duration_since(last) < self.interval => false,
+                _ => {
+                    self.last = Some(now);
+                    true
+                }
+            }
+        }
+    }
+
+    /// A canvas of RGB pixels drawn with Unicode upper half-blocks. Each character cell holds two
+    /// vertically stacked pixels: the upper pixel is the foreground colour and the lower pixel is
+    /// the background colour.
+    pub struct Canvas {
+        width: usize,
+        height: usize,
+        pixels: Vec<(u8, u8, u8)>,
+        out: String,
+    }
+
+    impl Canvas {
+        /// Creates a canvas that covers `cols` columns and `rows` rows of the terminal.
+        pub fn new(cols: usize, rows: usize) -> Self {
+            let height = 2 * rows;
+            Canvas { width: cols, height, pixels: vec![(0, 0, 0); cols * height], out: String::new() }
+        }
+
+        pub fn width(&self) -> usize {
+            self.width
+        }
+
+        pub fn height(&self) -> usize {
+            self.height
+        }
+
+        /// Sets the pixel at column `x` and row `y`, where row 0 is the top of the canvas.
+        pub fn set(&mut self, x: usize, y: usize, colour: (u8, u8, u8)) {
+            if x < self.width && y < self.height {
+                self.pixels[y * self.width + x] = colour;
+            }
+        }
+
+        /// Draws the canvas to the top-left corner of the terminal.
+        pub fn draw(&mut self) {
+            self.out.clear();
+            self.out.push_str("\x1b[H");
+
+            for row in 0..self.height / 2 {
+                let mut prev = None;
+
+                for x in 0..self.width {
+                    let fg = self.pixels[2 * row * self.width + x];
+                    let bg = self.pixels[(2 * row + 1) * self.width + x];
+
+                    // Only emit colour escape sequences when the colours change.
+                    if prev != Some((fg, bg)) {
+                        let _ = write!(
+                            self.out,
+                            "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                            fg.0, fg.1, fg.2, bg.0, bg.1, bg.2
+                        );
+                        prev = Some((fg, bg));
+                    }
+
+                    self.out.push('\u{2580}');
+                }
+
+                self.out.push_str("\x1b[0m");
+
+                if row + 1 < self.height / 2 {
+                    self.out.push_str("\r\n");
+                }
+            }
+
+            draw(&self.out);
+        }
+    }
+}
+
 mod stft {
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+
     use symphonia::core::audio::*;
     use symphonia::core::units::Duration;
-    use rustfft::{FftPlanner, num_complex::Complex};
+    use rustfft::{Fft, FftPlanner, num_complex::Complex};
 
+    use super::colormap;
+    use super::term::{self, Canvas, FrameLimiter};
     use super::{Display, Result};
 
-    pub struct STFTDisplay {
-        sample_buf: RawSampleBuffer<f32>,
+    /// The lowest frequency shown on the spectrogram.
+    const MIN_FREQ: f32 = 20.0;
+
+    /// The dynamic range of the spectrogram. Magnitudes at or below this level (relative to full
+    /// scale) are drawn with the darkest colour.
+    const MIN_DB: f32 = -100.0;
+
+    /// Maps rows of the spectrogram to ranges of FFT bins such that the rows are spaced
+    /// logarithmically in frequency.
+    pub struct LogBins {
+        ranges: Vec<(usize, usize)>,
+        min_freq: f32,
+        max_freq: f32,
     }
 
-    impl STFTDisplay {
-        pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn Display>> {
-            let sample_buf = RawSampleBuffer::<f32>::new(duration, spec);
-            Ok(Box::new(STFTDisplay { sample_buf }))
-        }
+    impl LogBins {
+        /// Computes the bin ranges of `rows` rows for a FFT of size `fft_size` at sample rate
+        /// `rate`. Row 0 is the lowest frequency.
+        pub fn new(rows: usize, fft_size: usize, rate: u32) -> Self {
+            let n_bins = fft_size / 2 + 1;
+            let bin_width = rate as f32 / fft_size as f32;
 
-        fn process(&mut self) {
-            let mono: Vec<f32> = self.sample_buf.as_bytes().chunks_exact(4)
-                .map(|chunk| f32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
-                .collect::<Vec<f32>>() // Collect the f32 values
-                .windows(2) // Create an iterator over pairs of adjacent elements
-                .map(|pair| (pair[0] + pair[1]) / 2.0) // Calculate the average of each pair
+            let max_freq = rate as f32 / 2.0;
+            let min_freq = MIN_FREQ.min(max_freq / 2.0);
+
+            let ratio = max_freq / min_freq;
+
+            let ranges = (0..rows)
+                .map(|row| {
+                    let lo = min_freq * ratio.powf(row as f32 / rows as f32);
+                    let hi = min_freq * ratio.powf((row + 1) as f32 / rows as f32);
+
+                    // Rows narrower than a bin use the nearest bin, wider rows span all the bins
+                    // they cover.
+                    let start = ((lo / bin_width).round() as usize).min(n_bins - 1);
+                    let end = ((hi / bin_width).round() as usize).clamp(start + 1, n_bins);
+
+                    (start, end)
+                })
                 .collect();
 
-            let stft_result = self.stft(&mono, 256, 128);
-            // Do something with the STFT result, e.g., print it
-            // for row in &stft_result {
-            //     for &value in row {
-            //         print!("{:.2} ", value);
-            //     }
-            //     println!();
-            // }
+            LogBins { ranges, min_freq, max_freq }
         }
 
-        // Function to perform the STFT on a Vec<f32> input
-        fn stft(&self, signal: &[f32], window_size: usize, hop_size: usize) -> Vec<Vec<f32>> {
-            let fft_size = window_size.next_power_of_two();
-            let fft = FftPlanner::new().plan_fft_forward(fft_size);
+        pub fn rows(&self) -> usize {
+            self.ranges.len()
+        }
 
-            let mut stft_result: Vec<Vec<f32>> = Vec::new();
+        /// Gets the position of `freq` on the frequency axis, where 0 is the bottom of the lowest
+        /// row and 1 is the top of the highest row, or `None` if the frequency is not shown.
+        pub fn position(&self, freq: f32) -> Option<f32> {
+            if freq < self.min_freq || freq > self.max_freq {
+                return None;
+            }
 
-            for i in (0..signal.len()).step_by(hop_size) {
-                // Apply window function to the current frame
-                let window: Vec<_> = (0..window_size)
-                    .map(|j| {
-                        let value = signal.get(i + j).unwrap_or(&0.0);
-                        value * 0.5 * (1.0 - 2.0 * std::f32::consts::PI * j as f32 / window_size as f32).cos()
-                    })
-                    .collect();
+            Some((freq / self.min_freq).ln() / (self.max_freq / self.min_freq).ln())
+        }
 
-                // Zero-pad the windowed signal if necessary
-                let mut input: Vec<_> = window
+        /// Reduces a magnitude spectrum to one value per row by taking the peak of each range.
+        pub fn apply(&self, spectrum: &[f32], out: &mut Vec<f32>) {
+            out.clear();
+            out.extend(
+                self.ranges
                     .iter()
-                    .cloned()
-                    .chain(vec![0.0; fft_size - window_size])
-                    .map(|value| Complex::new(value, 0.0))
-                    .collect();
+                    .map(|&(start, end)| spectrum[start..end].iter().cloned().fold(0.0, f32::max)),
+            );
+        }
+    }
+
+    /// Downmixes interleaved samples with `channels` channels to mono by averaging the samples of
+    /// each frame, and appends the result to `out`.
+    pub fn downmix(interleaved: &[f32], channels: usize, out: &mut VecDeque<f32>) {
+        let scale = 1.0 / channels as f32;
+
+        out.extend(interleaved.chunks_exact(channels).map(|frame| frame.iter().sum::<f32>() * scale));
+    }
+
+    /// The window function applied to each frame before the FFT.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum WindowFunction {
+        Rectangular,
+        Hann,
+        Hamming,
+        BlackmanHarris,
+        Kaiser { beta: f32 },
+    }
+
+    impl WindowFunction {
+        /// The `j`th coefficient of the periodic window of length `n`.
+        fn coeff(&self, j: usize, n: usize) -> f32 {
+            let x = 2.0 * std::f32::consts::PI * j as f32 / n as f32;
+
+            match *self {
+                WindowFunction::Rectangular => 1.0,
+                WindowFunction::Hann => 0.5 * (1.0 - x.cos()),
+                WindowFunction::Hamming => 0.54 - 0.46 * x.cos(),
+                WindowFunction::BlackmanHarris => {
+                    0.35875 - 0.48829 * x.cos() + 0.14128 * (2.0 * x).cos()
+                        - 0.01168 * (3.0 * x).cos()
+                }
+                WindowFunction::Kaiser { beta } => {
+                    let r = 2.0 * j as f32 / n as f32 - 1.0;
+                    bessel_i0(beta * (1.0 - r * r).max(0.0).sqrt()) / bessel_i0(beta)
+                }
+            }
+        }
+    }
+
+    /// The zeroth-order modified Bessel function of the first kind, evaluated by its power series.
+    fn bessel_i0(x: f32) -> f32 {
+        let half_x_sq = f64::from(x / 2.0).powi(2);
+
+        let mut sum = 1.0;
+        let mut term = 1.0;
+
+        for k in 1..64 {
+            term *= half_x_sq / f64::from(k * k);
+            sum += term;
+
+            if term < sum * 1e-12 {
+                break;
+            }
+        }
+
+        sum as f32
+    }
+
+    /// The parameters of the STFT analysis.
+    #[derive(Copy, Clone, Debug)]
+    pub struct StftOptions {
+        /// The number of samples in each analysed frame.
+        pub window_size: usize,
+        /// The number of samples between the starts of consecutive frames.
+        pub hop_size: usize,
+        /// The window function applied to each frame.
+        pub window: WindowFunction,
+        /// Each frame is zero-padded to at least this many times its length before the FFT to
+        /// interpolate the spectrum.
+        pub zero_padding: usize,
+    }
+
+    impl Default for StftOptions {
+        fn default() -> Self {
+            StftOptions {
+                window_size: 256,
+                hop_size: 128,
+                window: WindowFunction::Hann,
+                zero_padding: 1,
+            }
+        }
+    }
+
+    /// A short-time Fourier transform over a continuous stream of mono samples.
+    ///
+    /// The FFT plan, window table, and all buffers are owned by the analyser and reused, so that
+    /// once the buffers have grown to their steady-state sizes, analysis does not allocate.
+    pub struct Stft {
+        window_size: usize,
+        hop_size: usize,
+        fft: Arc<dyn Fft<f32>>,
+        window: Vec<f32>,
+        /// Samples that have not yet been consumed by a hop. Frames that straddle a packet
+        /// boundary are completed with samples from the following packet.
+        pending: VecDeque<f32>,
+        buf: Vec<Complex<f32>>,
+        scratch: Vec<Complex<f32>>,
+        /// The magnitude spectra of the frames analysed by the last call to `process`. Only the
+        /// first `n_frames` spectra are valid, the remainder are kept to be reused.
+        frames: Vec<Vec<f32>>,
+        n_frames: usize,
+    }
+
+    impl Stft {
+        pub fn new(opts: &StftOptions) -> Self {
+            let window_size = opts.window_size.max(1);
+            let hop_size = opts.hop_size.max(1);
+
+            let fft_size = (window_size * opts.zero_padding.max(1)).next_power_of_two();
+            let fft = FftPlanner::new().plan_fft_forward(fft_size);
+
+            let window = (0..window_size).map(|j| opts.window.coeff(j, window_size)).collect();
+
+            let scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
+
+            Stft {
+                window_size,
+                hop_size,
+                fft,
+                window,
+                pending: VecDeque::new(),
+                buf: vec![Complex::new(0.0, 0.0); fft_size],
+                scratch,
+                frames: Vec::new(),
+                n_frames: 0,
+            }
+        }
+
+        pub fn fft_size(&self) -> usize {
+            self.buf.len()
+        }
+
+        /// The number of bins of each magnitude spectrum. Only the non-negative frequencies of the
+        /// FFT are kept.
+        pub fn n_bins(&self) -> usize {
+            self.fft_size() / 2 + 1
+        }
+
+        /// The magnitude of a full-scale sinusoid after windowing, used as the 0 dB reference.
+        pub fn reference(&self) -> f32 {
+            self.window.iter().sum::<f32>() / 2.0
+        }
+
+        /// The equivalent noise bandwidth of the window, in bins. The power of a sinusoid is spread
+        /// over this many bins of the spectrum.
+        pub fn noise_bandwidth(&self) -> f32 {
+            let sum = self.window.iter().sum::<f32>();
+            let sum_sq = self.window.iter().map(|w| w * w).sum::<f32>();
+
+            self.fft_size() as f32 * sum_sq / (sum * sum)
+        }
+
+        /// Gets the buffer of samples waiting to be analysed. New samples should be appended to
+        /// the back.
+        pub fn pending_mut(&mut self) -> &mut VecDeque<f32> {
+            &mut self.pending
+        }
+
+        /// Analyses a frame for every full window of pending samples, after which the samples of
+        /// one hop are consumed. Samples that do not yet fill a window are kept for the next call.
+        /// Returns the magnitude spectra of the analysed frames.
+        pub fn process(&mut self) -> &[Vec<f32>] {
+            let n_bins = self.n_bins();
+
+            self.n_frames = 0;
+
+            while self.pending.len() >= self.window_size {
+                // Apply the window to the current frame, and zero-pad it to the FFT size.
+                for (i, (&value, &coeff)) in self.pending.iter().zip(&self.window).enumerate() {
+                    self.buf[i] = Complex::new(value * coeff, 0.0);
+                }
+
+                for value in self.buf[self.window_size..].iter_mut() {
+                    *value = Complex::new(0.0, 0.0);
+                }
 
                 // Perform FFT in-place
-                fft.process(&mut input);
+                self.fft.process_with_scratch(&mut self.buf, &mut self.scratch);
+
+                if self.n_frames == self.frames.len() {
+                    self.frames.push(Vec::with_capacity(n_bins));
+                }
 
                 // Compute magnitude spectrum
-                let magnitude_spectrum: Vec<_> = input.iter().map(|c| c.norm()).collect();
+                let spectrum = &mut self.frames[self.n_frames];
+                spectrum.clear();
+                spectrum.extend(self.buf[..n_bins].iter().map(|c| c.norm()));
+
+                self.n_frames += 1;
+
+                self.pending.drain(..self.hop_size.min(self.pending.len()));
+            }
+
+            &self.frames[..self.n_frames]
+        }
+    }
+
+    pub struct STFTDisplay {
+        sample_buf: SampleBuffer<f32>,
+        channels: usize,
+        rate: u32,
+        stft: Stft,
+        /// The magnitude of a full-scale sinusoid after windowing, used as the 0 dB reference.
+        reference: f32,
+        bins: LogBins,
+        /// The most recent spectrogram columns, oldest first, normalized to `[0, 1]`.
+        columns: VecDeque<Vec<f32>>,
+        canvas: Canvas,
+        limiter: FrameLimiter,
+    }
+
+    impl STFTDisplay {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            opts: &StftOptions,
+        ) -> Result<Box<dyn Display>> {
+            let sample_buf = SampleBuffer::<f32>::new(duration, spec);
+
+            let stft = Stft::new(opts);
+            let reference = stft.reference();
+
+            let (cols, rows) = term::size();
+            let canvas = Canvas::new(cols, rows);
+            let bins = LogBins::new(canvas.height(), stft.fft_size(), spec.rate);
+
+            term::enter();
+
+            Ok(Box::new(STFTDisplay {
+                sample_buf,
+                channels: spec.channels.count().max(1),
+                rate: spec.rate,
+                stft,
+                reference,
+                bins,
+                columns: VecDeque::new(),
+                canvas,
+                limiter: FrameLimiter::new(),
+            }))
+        }
+
+        fn process(&mut self) {
+            downmix(self.sample_buf.samples(), self.channels, self.stft.pending_mut());
 
-                stft_result.push(magnitude_spectrum);
+            let frames = self.stft.process();
+
+            for spectrum in frames {
+                push_column(
+                    &mut self.columns,
+                    self.canvas.width(),
+                    &self.bins,
+                    self.reference,
+                    spectrum,
+                );
+            }
+        }
+
+        /// Redraws the spectrogram, resizing it first if the terminal size changed.
+        fn render(&mut self) {
+            let (cols, rows) = term::size();
+
+            if cols != self.canvas.width() || 2 * rows != self.canvas.height() {
+                self.canvas = Canvas::new(cols, rows);
+                self.bins = LogBins::new(self.canvas.height(), self.stft.fft_size(), self.rate);
+
+                // Columns computed for the old size cannot be drawn on the new canvas.
+                self.columns.clear();
+                term::enter();
+            }
+
+            let height = self.canvas.height();
+
+            // Right-align the columns so that the spectrogram scrolls from right to left.
+            let offset = self.canvas.width() - self.columns.len();
+
+            for x in 0..offset {
+                for y in 0..height {
+                    self.canvas.set(x, y, colormap::inferno(0.0));
+                }
+            }
+
+            for (i, column) in self.columns.iter().enumerate() {
+                for (row, &value) in column.iter().enumerate() {
+                    // Row 0 of a column is the lowest frequency, but row 0 of the canvas is the
+                    // top of the screen.
+                    self.canvas.set(offset + i, height - 1 - row, colormap::inferno(value));
+                }
             }
 
-            stft_result
+            self.canvas.draw();
+        }
+    }
+
+    /// Converts a magnitude spectrum into a normalized, log-frequency spectrogram column and
+    /// appends it to `columns`, recycling the oldest column if there are already `width` columns.
+    fn push_column(
+        columns: &mut VecDeque<Vec<f32>>,
+        width: usize,
+        bins: &LogBins,
+        reference: f32,
+        spectrum: &[f32],
+    ) {
+        if width == 0 {
+            return;
+        }
+
+        let mut column = if columns.len() >= width {
+            columns.pop_front().unwrap_or_default()
+        }
+        else {
+            Vec::with_capacity(bins.rows())
+        };
+
+        bins.apply(spectrum, &mut column);
+        normalize(&mut column, reference);
+
+        columns.push_back(column);
+    }
+
+    /// Converts magnitudes to decibels relative to `reference`, and maps the decibels within the
+    /// dynamic range of the spectrogram to `[0, 1]`.
+    pub fn normalize(values: &mut [f32], reference: f32) {
+        for value in values.iter_mut() {
+            let db = 20.0 * (*value / reference).max(f32::MIN_POSITIVE).log10();
+            *value = 1.0 - (db / MIN_DB).clamp(0.0, 1.0);
         }
     }
 
     impl Display for STFTDisplay {
 
-        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+        fn write(&mut self, decoded: AudioBufferRef<'_>, _ts: u64) -> Result<()> {
 
             if decoded.frames() == 0 {
                 return Ok(());
@@ -105,16 +708,2921 @@ mod stft {
 
             self.process();
 
+            // Analysis happens for every packet, but the terminal is only redrawn as often as it
+            // can be refreshed.
+            if self.limiter.ready() {
+                self.render();
+            }
+
             Ok(())
         }
 
-        fn flush(&mut self) {
+        fn flush(&mut self) -> Result<()> {
+            self.render();
+            term::leave();
+            Ok(())
+        }
+    }
+}
+
+/// A scrolling overview of the waveform.
+mod waveform {
+    use std::collections::VecDeque;
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use super::term::{self, Canvas, FrameLimiter};
+    use super::{Display, Result};
+
+    /// The number of columns drawn per second of audio.
+    const COLUMNS_PER_SEC: u32 = 50;
+
+    const BACKGROUND: (u8, u8, u8) = (0, 0, 0);
+    const PEAK_COLOUR: (u8, u8, u8) = (40, 110, 60);
+    const RMS_COLOUR: (u8, u8, u8) = (90, 230, 120);
+
+    /// The summary of the samples covered by one column.
+    #[derive(Copy, Clone, Default)]
+    struct Column {
+        min: f32,
+        max: f32,
+        rms: f32,
+    }
+
+    pub struct WaveformDisplay {
+        sample_buf: SampleBuffer<f32>,
+        channels: usize,
+        samples_per_column: usize,
+        /// The column being accumulated, as the minimum, maximum and sum of squares of the
+        /// downmixed samples, and the number of samples.
+        acc: (f32, f32, f32, usize),
+        columns: VecDeque<Column>,
+        canvas: Canvas,
+        limiter: FrameLimiter,
+    }
+
+    impl WaveformDisplay {
+        pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn Display>> {
+            let (cols, rows) = term::size();
+
+            term::enter();
+
+            Ok(Box::new(WaveformDisplay {
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                channels: spec.channels.count().max(1),
+                samples_per_column: (spec.rate / COLUMNS_PER_SEC).max(1) as usize,
+                acc: (0.0, 0.0, 0.0, 0),
+                columns: VecDeque::new(),
+                canvas: Canvas::new(cols, rows),
+                limiter: FrameLimiter::new(),
+            }))
+        }
+
+        fn push_sample(&mut self, sample: f32) {
+            let (min, max, sum_sq, n) = &mut self.acc;
+
+            *min = min.min(sample);
+            *max = max.max(sample);
+            *sum_sq += sample * sample;
+            *n += 1;
+
+            if *n == self.samples_per_column {
+                let rms = (*sum_sq / *n as f32).sqrt();
+
+                if self.columns.len() >= self.canvas.width() {
+                    self.columns.pop_front();
+                }
+
+                self.columns.push_back(Column { min: *min, max: *max, rms });
+                self.acc = (0.0, 0.0, 0.0, 0);
+            }
+        }
+
+        fn render(&mut self) {
+            let (cols, rows) = term::size();
+
+            if cols != self.canvas.width() || 2 * rows != self.canvas.height() {
+                self.canvas = Canvas::new(cols, rows);
+                self.columns.clear();
+                term::enter();
+            }
+
+            let width = self.canvas.width();
+            let height = self.canvas.height();
+
+            // Maps an amplitude in [-1, 1] to a pixel row, with +1 at the top.
+            let to_row = |value: f32| {
+                let pos = (1.0 - value.clamp(-1.0, 1.0)) / 2.0;
+                ((pos * (height - 1) as f32).round() as usize).min(height - 1)
+            };
+
+            let offset = width - self.columns.len();
+
+            for x in 0..width {
+                for y in 0..height {
+                    self.canvas.set(x, y, BACKGROUND);
+                }
+
+                if x < offset {
+                    continue;
+                }
+
+                let column = self.columns[x - offset];
+
+                for y in to_row(column.max)..=to_row(column.min) {
+                    self.canvas.set(x, y, PEAK_COLOUR);
+                }
+
+                for y in to_row(column.rms)..=to_row(-column.rms) {
+                    self.canvas.set(x, y, RMS_COLOUR);
+                }
+            }
+
+            self.canvas.draw();
+        }
+    }
+
+    impl Display for WaveformDisplay {
+        fn write(&mut self, decoded: AudioBufferRef<'_>, _ts: u64) -> Result<()> {
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            let scale = 1.0 / self.channels as f32;
+
+            for i in 0..self.sample_buf.len() / self.channels {
+                let frame = &self.sample_buf.samples()[i * self.channels..(i + 1) * self.channels];
+                self.push_sample(frame.iter().sum::<f32>() * scale);
+            }
+
+            if self.limiter.ready() {
+                self.render();
+            }
+
+            Ok(())
+        }
 
+        fn flush(&mut self) -> Result<()> {
+            self.render();
+            term::leave();
+            Ok(())
         }
     }
 }
 
-#[cfg(target_os = "linux")]
-pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn Display>> {
-    stft::STFTDisplay::try_open(spec, duration)
-}
\ No newline at end of file
+/// Per-channel peak and RMS meters with peak-hold and clip indicators.
+mod meter {
+    use std::fmt::Write;
+    use std::time::{Duration as StdDuration, Instant};
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use super::term::{self, FrameLimiter};
+    use super::{Display, Result};
+
+    /// The lowest level shown on the meters.
+    const MIN_DB: f32 = -60.0;
+
+    /// The time constant of the RMS integrator.
+    const RMS_TIME: f32 = 0.3;
+
+    /// How long the peak-hold marker stays before it starts to fall.
+    const HOLD_TIME: StdDuration = StdDuration::from_millis(1500);
+
+    /// How fast the peak-hold marker falls once released, in dB per second.
+    const HOLD_FALL: f32 = 20.0;
+
+    /// How long the clip indicator stays lit after the last clipped sample.
+    const CLIP_TIME: StdDuration = StdDuration::from_secs(3);
+
+    /// The width of the channel label and level readouts around the bars.
+    const LABEL_WIDTH: usize = 4;
+    const READOUT_WIDTH: usize = 24;
+
+    struct ChannelMeter {
+        label: String,
+        /// The peak since the last render.
+        peak: f32,
+        /// The mean square, exponentially averaged.
+        mean_sq: f32,
+        hold_db: f32,
+        hold_since: Instant,
+        clipped_at: Option<Instant>,
+    }
+
+    pub struct MeterDisplay {
+        sample_buf: SampleBuffer<f32>,
+        meters: Vec<ChannelMeter>,
+        /// The per-sample smoothing coefficient of the RMS integrator.
+        rms_coeff: f32,
+        last_render: Instant,
+        limiter: FrameLimiter,
+        out: String,
+    }
+
+    impl MeterDisplay {
+        pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn Display>> {
+            let now = Instant::now();
+
+            let meters = spec
+                .channels
+                .iter()
+                .map(|channel| ChannelMeter {
+                    label: channel_label(channel).to_string(),
+                    peak: 0.0,
+                    mean_sq: 0.0,
+                    hold_db: MIN_DB,
+                    hold_since: now,
+                    clipped_at: None,
+                })
+                .collect();
+
+            term::enter();
+
+            Ok(Box::new(MeterDisplay {
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                meters,
+                rms_coeff: (-1.0 / (RMS_TIME * spec.rate as f32)).exp(),
+                last_render: now,
+                limiter: FrameLimiter::new(),
+                out: String::new(),
+            }))
+        }
+
+        fn render(&mut self) {
+            let now = Instant::now();
+            let elapsed = now.duration_since(self.last_render).as_secs_f32();
+            self.last_render = now;
+
+            let (cols, _) = term::size();
+            let width = cols.saturating_sub(LABEL_WIDTH + READOUT_WIDTH).max(1);
+
+            self.out.clear();
+            self.out.push_str("\x1b[H");
+
+            for meter in self.meters.iter_mut() {
+                let peak_db = to_db(meter.peak);
+                let rms_db = to_db(meter.mean_sq.sqrt());
+
+                // The hold marker jumps up to new peaks, and falls once it has been held long
+                // enough.
+                if peak_db >= meter.hold_db {
+                    meter.hold_db = peak_db;
+                    meter.hold_since = now;
+                }
+                else if now.duration_since(meter.hold_since) > HOLD_TIME {
+                    meter.hold_db = (meter.hold_db - HOLD_FALL * elapsed).max(MIN_DB);
+                }
+
+                let clipping =
+                    meter.clipped_at.is_some_and(|at| now.duration_since(at) < CLIP_TIME);
+
+                let _ = write!(self.out, "{:<w$}", meter.label, w = LABEL_WIDTH);
+
+                let hold_cell = cell(meter.hold_db, width);
+
+                for i in 0..width {
+                    let level = MIN_DB * (1.0 - (i as f32 + 0.5) / width as f32);
+                    let (r, g, b) = zone_colour(level);
+
+                    let (ch, colour) = if i == hold_cell && meter.hold_db > MIN_DB {
+                        ('\u{2502}', (255, 255, 255))
+                    }
+                    else if level <= rms_db {
+                        ('\u{2588}', (r, g, b))
+                    }
+                    else if level <= peak_db {
+                        ('\u{2592}', (r, g, b))
+                    }
+                    else {
+                        ('\u{00b7}', (60, 60, 60))
+                    };
+
+                    let _ = write!(self.out, "\x1b[38;2;{};{};{}m{}", colour.0, colour.1, colour.2, ch);
+                }
+
+                let _ = write!(
+                    self.out,
+                    "\x1b[0m {:>6.1} {:>6.1} {}\x1b[K\r\n",
+                    peak_db,
+                    rms_db,
+                    if clipping { "\x1b[41;97m CLIP \x1b[0m" } else { "      " }
+                );
+
+                meter.peak = 0.0;
+            }
+
+            term::draw(&self.out);
+        }
+    }
+
+    /// Gets a short label for a channel.
+    fn channel_label(channel: Channels) -> &'static str {
+        match channel {
+            Channels::FRONT_LEFT => "L",
+            Channels::FRONT_RIGHT => "R",
+            Channels::FRONT_CENTRE => "C",
+            Channels::LFE1 => "LFE",
+            Channels::REAR_LEFT => "RL",
+            Channels::REAR_RIGHT => "RR",
+            Channels::REAR_CENTRE => "RC",
+            Channels::SIDE_LEFT => "SL",
+            Channels::SIDE_RIGHT => "SR",
+            _ => "?",
+        }
+    }
+
+    fn to_db(value: f32) -> f32 {
+        (20.0 * value.max(f32::MIN_POSITIVE).log10()).max(MIN_DB)
+    }
+
+    /// Gets the index of the cell that shows the level `db` on a meter `width` cells wide.
+    fn cell(db: f32, width: usize) -> usize {
+        let pos = 1.0 - db / MIN_DB;
+        ((pos * width as f32) as usize).min(width - 1)
+    }
+
+    /// The colour of the meter at a level: green, then yellow above -18 dBFS, and red above -6
+    /// dBFS.
+    fn zone_colour(db: f32) -> (u8, u8, u8) {
+        if db > -6.0 {
+            (230, 60, 50)
+        }
+        else if db > -18.0 {
+            (230, 200, 60)
+        }
+        else {
+            (80, 200, 90)
+        }
+    }
+
+    impl Display for MeterDisplay {
+        fn write(&mut self, decoded: AudioBufferRef<'_>, _ts: u64) -> Result<()> {
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            let channels = self.meters.len();
+            let now = Instant::now();
+
+            for frame in self.sample_buf.samples().chunks_exact(channels) {
+                for (meter, &sample) in self.meters.iter_mut().zip(frame) {
+                    let abs = sample.abs();
+
+                    meter.peak = meter.peak.max(abs);
+                    meter.mean_sq = self.rms_coeff * meter.mean_sq
+                        + (1.0 - self.rms_coeff) * sample * sample;
+
+                    if abs >= 1.0 {
+                        meter.clipped_at = Some(now);
+                    }
+                }
+            }
+
+            if self.limiter.ready() {
+                self.render();
+            }
+
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.render();
+            term::leave();
+            Ok(())
+        }
+    }
+}
+
+/// An XY goniometer showing the stereo image and phase of the first two channels, or an
+/// oscilloscope for mono streams.
+mod scope {
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use super::colormap;
+    use super::term::{self, Canvas, FrameLimiter};
+    use super::{Display, Result};
+
+    /// The fraction of the trace intensity kept on each redraw, simulating the persistence of a
+    /// phosphor screen.
+    const DECAY: f32 = 0.8;
+
+    /// The intensity added for each sample that falls on a pixel.
+    const HIT: f32 = 0.25;
+
+    pub struct ScopeDisplay {
+        sample_buf: SampleBuffer<f32>,
+        channels: usize,
+        /// The intensity of each pixel of the canvas.
+        intensity: Vec<f32>,
+        /// The mono samples since the last redraw, only used for mono streams.
+        trace: Vec<f32>,
+        canvas: Canvas,
+        limiter: FrameLimiter,
+    }
+
+    impl ScopeDisplay {
+        pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn Display>> {
+            let (cols, rows) = term::size();
+            let canvas = Canvas::new(cols, rows);
+
+            term::enter();
+
+            Ok(Box::new(ScopeDisplay {
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                channels: spec.channels.count().max(1),
+                intensity: vec![0.0; canvas.width() * canvas.height()],
+                trace: Vec::new(),
+                canvas,
+                limiter: FrameLimiter::new(),
+            }))
+        }
+
+        fn hit(&mut self, x: usize, y: usize) {
+            let width = self.canvas.width();
+
+            if x < width && y < self.canvas.height() {
+                let value = &mut self.intensity[y * width + x];
+                *value = (*value + HIT).min(1.0);
+            }
+        }
+
+        /// Plots the stereo samples of the last buffer. The mid signal is drawn on the vertical
+        /// axis, and the side signal on the horizontal axis, so a mono signal is a vertical line
+        /// and out-of-phase channels lie on the horizontal.
+        fn plot_xy(&mut self) {
+            let width = self.canvas.width();
+            let height = self.canvas.height();
+
+            // Pixels are square, so use a square plot centred on the canvas.
+            let size = width.min(height);
+            let (x0, y0) = ((width - size) / 2, (height - size) / 2);
+            let half = (size as f32 - 1.0) / 2.0;
+
+            let channels = self.channels;
+
+            for i in 0..self.sample_buf.len() / channels {
+                let left = self.sample_buf.samples()[i * channels];
+                let right = self.sample_buf.samples()[i * channels + 1];
+
+                let mid = ((left + right) * std::f32::consts::FRAC_1_SQRT_2).clamp(-1.0, 1.0);
+                let side = ((left - right) * std::f32::consts::FRAC_1_SQRT_2).clamp(-1.0, 1.0);
+
+                let x = x0 + (half * (1.0 - side)).round() as usize;
+                let y = y0 + (half * (1.0 - mid)).round() as usize;
+
+                self.hit(x, y);
+            }
+        }
+
+        /// Plots the most recent mono samples across the width of the canvas.
+        fn plot_trace(&mut self) {
+            let width = self.canvas.width();
+            let height = self.canvas.height();
+
+            let start = self.trace.len().saturating_sub(width);
+            let half = (height as f32 - 1.0) / 2.0;
+
+            for x in 0..self.trace.len() - start {
+                let value = self.trace[start + x].clamp(-1.0, 1.0);
+                self.hit(x, (half * (1.0 - value)).round() as usize);
+            }
+
+            self.trace.clear();
+        }
+
+        fn render(&mut self) {
+            let (cols, rows) = term::size();
+
+            if cols != self.canvas.width() || 2 * rows != self.canvas.height() {
+                self.canvas = Canvas::new(cols, rows);
+                self.intensity = vec![0.0; self.canvas.width() * self.canvas.height()];
+                term::enter();
+            }
+
+            if self.channels == 1 {
+                self.plot_trace();
+            }
+
+            let width = self.canvas.width();
+
+            for (i, value) in self.intensity.iter_mut().enumerate() {
+                self.canvas.set(i % width, i / width, colormap::inferno(*value));
+                *value *= DECAY;
+            }
+
+            self.canvas.draw();
+        }
+    }
+
+    impl Display for ScopeDisplay {
+        fn write(&mut self, decoded: AudioBufferRef<'_>, _ts: u64) -> Result<()> {
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            if self.channels >= 2 {
+                self.plot_xy();
+            }
+            else {
+                self.trace.extend_from_slice(self.sample_buf.samples());
+            }
+
+            if self.limiter.ready() {
+                self.render();
+            }
+
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.render();
+            term::leave();
+            Ok(())
+        }
+    }
+}
+
+/// A real-time spectrum analyser showing the levels of octave or third-octave bands as bars.
+mod bars {
+    use std::fmt::Write;
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use super::stft::{downmix, Stft, StftOptions};
+    use super::term::{self, Canvas, FrameLimiter};
+    use super::{Display, Result};
+
+    /// The width of the bands.
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+    pub enum Bands {
+        Octave,
+        #[default]
+        ThirdOctave,
+    }
+
+    /// The nominal ISO 266 centre frequencies of the third-octave bands. Every third band, starting
+    /// from 31.5 Hz, is also an octave band.
+    const THIRD_OCTAVE_CENTRES: [(f32, &str); 30] = [
+        (25.0, "25"),
+        (31.5, "31"),
+        (40.0, "40"),
+        (50.0, "50"),
+        (63.0, "63"),
+        (80.0, "80"),
+        (100.0, "100"),
+        (125.0, "125"),
+        (160.0, "160"),
+        (200.0, "200"),
+        (250.0, "250"),
+        (315.0, "315"),
+        (400.0, "400"),
+        (500.0, "500"),
+        (630.0, "630"),
+        (800.0, "800"),
+        (1000.0, "1k"),
+        (1250.0, "1k2"),
+        (1600.0, "1k6"),
+        (2000.0, "2k"),
+        (2500.0, "2k5"),
+        (3150.0, "3k1"),
+        (4000.0, "4k"),
+        (5000.0, "5k"),
+        (6300.0, "6k3"),
+        (8000.0, "8k"),
+        (10000.0, "10k"),
+        (12500.0, "12k"),
+        (16000.0, "16k"),
+        (20000.0, "20k"),
+    ];
+
+    /// The lowest level shown on the analyser.
+    const MIN_DB: f32 = -80.0;
+
+    /// The time constants of the bar ballistics, in seconds. Bars rise quickly and fall slowly.
+    const ATTACK_TIME: f32 = 0.01;
+    const RELEASE_TIME: f32 = 0.3;
+
+    /// How long a peak-hold marker stays before it starts to fall, in seconds.
+    const HOLD_TIME: f32 = 1.0;
+
+    /// How fast a peak-hold marker falls once released, in dB per second.
+    const HOLD_FALL: f32 = 15.0;
+
+    const PEAK_COLOUR: (u8, u8, u8) = (255, 255, 255);
+    const BACKGROUND: (u8, u8, u8) = (0, 0, 0);
+
+    struct Band {
+        label: &'static str,
+        /// The range of FFT bins in the band.
+        bins: (usize, usize),
+        /// The level after ballistics, in dB.
+        level: f32,
+        hold: f32,
+        /// The time since the peak-hold marker was last raised, in seconds.
+        hold_age: f32,
+    }
+
+    pub struct BarsDisplay {
+        sample_buf: SampleBuffer<f32>,
+        channels: usize,
+        stft: Stft,
+        /// The power of a full-scale sinusoid summed over all the bins it spreads into.
+        reference_power: f32,
+        bands: Vec<Band>,
+        /// The time between STFT frames, in seconds.
+        frame_time: f32,
+        canvas: Canvas,
+        limiter: FrameLimiter,
+        labels: String,
+    }
+
+    impl BarsDisplay {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            stft_opts: &StftOptions,
+            bands: Bands,
+        ) -> Result<Box<dyn Display>> {
+            let stft = Stft::new(stft_opts);
+
+            let reference = stft.reference();
+            let reference_power = reference * reference * stft.noise_bandwidth();
+
+            let bands = make_bands(bands, stft.fft_size(), spec.rate);
+
+            let (cols, rows) = term::size();
+
+            term::enter();
+
+            Ok(Box::new(BarsDisplay {
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                channels: spec.channels.count().max(1),
+                stft,
+                reference_power,
+                bands,
+                frame_time: stft_opts.hop_size.max(1) as f32 / spec.rate as f32,
+                canvas: Canvas::new(cols, rows.saturating_sub(1)),
+                limiter: FrameLimiter::new(),
+                labels: String::new(),
+            }))
+        }
+
+        /// Updates the band levels with a new magnitude spectrum.
+        fn update(bands: &mut [Band], spectrum: &[f32], reference_power: f32, dt: f32) {
+            let attack = (-dt / ATTACK_TIME).exp();
+            let release = (-dt / RELEASE_TIME).exp();
+
+            for band in bands.iter_mut() {
+                let power: f32 =
+                    spectrum[band.bins.0..band.bins.1].iter().map(|mag| mag * mag).sum();
+
+                let db = (10.0 * (power / reference_power).max(f32::MIN_POSITIVE).log10()).max(MIN_DB);
+
+                let coeff = if db > band.level { attack } else { release };
+                band.level = db + coeff * (band.level - db);
+
+                band.hold_age += dt;
+
+                if band.level >= band.hold {
+                    band.hold = band.level;
+                    band.hold_age = 0.0;
+                }
+                else if band.hold_age > HOLD_TIME {
+                    band.hold = (band.hold - HOLD_FALL * dt).max(MIN_DB);
+                }
+            }
+        }
+
+        fn render(&mut self) {
+            let (cols, rows) = term::size();
+
+            // The bottom row of the terminal is reserved for the band labels.
+            let rows = rows.saturating_sub(1);
+
+            if cols != self.canvas.width() || 2 * rows != self.canvas.height() {
+                self.canvas = Canvas::new(cols, rows);
+                term::enter();
+            }
+
+            let width = self.canvas.width();
+            let height = self.canvas.height();
+
+            let n_bands = self.bands.len().max(1);
+            let slot = (width / n_bands).max(1);
+
+            // Maps a level to the number of pixels of a bar.
+            let to_pixels = |db: f32| ((1.0 - db / MIN_DB).clamp(0.0, 1.0) * height as f32) as usize;
+
+            for x in 0..width {
+                for y in 0..height {
+                    self.canvas.set(x, y, BACKGROUND);
+                }
+            }
+
+            self.labels.clear();
+
+            for (i, band) in self.bands.iter().enumerate() {
+                let x0 = i * slot;
+
+                if x0 >= width {
+                    break;
+                }
+
+                // Leave a one pixel gap between bars if they are wide enough.
+                let bar_width = if slot > 2 { slot - 1 } else { slot };
+
+                let bar = to_pixels(band.level);
+                let hold = to_pixels(band.hold);
+
+                for x in x0..x0 + bar_width {
+                    for p in 0..bar {
+                        self.canvas.set(x, height - 1 - p, bar_colour(p as f32 / height as f32));
+                    }
+
+                    if hold > 0 {
+                        self.canvas.set(x, height - hold, PEAK_COLOUR);
+                    }
+                }
+
+                // Label each bar if there is room, otherwise only label every other bar.
+                let label_width = band.label.len() + 1;
+                let labelled = slot >= label_width || (i % 2 == 0 && 2 * slot >= label_width);
+
+                let col = self.labels.chars().count();
+
+                if labelled && col <= x0 {
+                    let _ = write!(self.labels, "{:w$}{}", "", band.label, w = x0 - col);
+                }
+            }
+
+            self.canvas.draw();
+
+            let mut out = String::new();
+            let _ = write!(out, "\r\n\x1b[0m{:.w$}\x1b[K", self.labels, w = width);
+
+            term::draw(&out);
+        }
+    }
+
+    /// Builds the bands of the given width that lie below the Nyquist frequency.
+    fn make_bands(bands: Bands, fft_size: usize, rate: u32) -> Vec<Band> {
+        let n_bins = fft_size / 2 + 1;
+        let bin_width = rate as f32 / fft_size as f32;
+        let nyquist = rate as f32 / 2.0;
+
+        // Exact centre frequencies are base-2 multiples of 1 kHz. The index of 1 kHz in the table
+        // of nominal frequencies is 16.
+        let (step, half_width) = match bands {
+            Bands::Octave => (3, 0.5),
+            Bands::ThirdOctave => (1, 1.0 / 6.0),
+        };
+
+        THIRD_OCTAVE_CENTRES
+            .iter()
+            .enumerate()
+            .skip(if bands == Bands::Octave { 1 } else { 0 })
+            .step_by(step)
+            .filter_map(|(i, &(_, label))| {
+                let centre = 1000.0 * 2f32.powf((i as f32 - 16.0) / 3.0);
+
+                let lo = centre * 2f32.powf(-half_width);
+                let hi = centre * 2f32.powf(half_width);
+
+                if hi > nyquist {
+                    return None;
+                }
+
+                // Bands narrower than a bin use the bin nearest to their centre.
+                let start = ((lo / bin_width).ceil() as usize).min(n_bins - 1);
+                let end = ((hi / bin_width).ceil() as usize).min(n_bins);
+
+                let bins = if end > start {
+                    (start, end)
+                }
+                else {
+                    let nearest = ((centre / bin_width).round() as usize).min(n_bins - 1);
+                    (nearest, nearest + 1)
+                };
+
+                Some(Band { label, bins, level: MIN_DB, hold: MIN_DB, hold_age: 0.0 })
+            })
+            .collect()
+    }
+
+    /// The colour of a bar at a height, from green at the bottom to red at the top.
+    fn bar_colour(t: f32) -> (u8, u8, u8) {
+        let t = t.clamp(0.0, 1.0);
+
+        if t < 0.5 {
+            ((80.0 + 300.0 * t) as u8, 200, 90)
+        }
+        else {
+            (230, (200.0 - 280.0 * (t - 0.5)) as u8, (90.0 - 80.0 * (t - 0.5)) as u8)
+        }
+    }
+
+    impl Display for BarsDisplay {
+        fn write(&mut self, decoded: AudioBufferRef<'_>, _ts: u64) -> Result<()> {
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            downmix(self.sample_buf.samples(), self.channels, self.stft.pending_mut());
+
+            for spectrum in self.stft.process() {
+                BarsDisplay::update(&mut self.bands, spectrum, self.reference_power, self.frame_time);
+            }
+
+            if self.limiter.ready() {
+                self.render();
+            }
+
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.render();
+            term::leave();
+            Ok(())
+        }
+    }
+}
+
+/// Loudness metering per ITU-R BS.1770 and EBU R128.
+mod loudness {
+    use std::collections::VecDeque;
+    use std::fmt::Write;
+    use std::sync::{Arc, Mutex};
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use super::term::{self, FrameLimiter};
+    use super::{Display, Result};
+
+    /// The step between gating blocks, in seconds. Momentary and short-term loudness are updated
+    /// at this rate.
+    const BLOCK_STEP: f64 = 0.1;
+
+    /// The number of steps in the momentary (400 ms) and short-term (3 s) windows.
+    const MOMENTARY_STEPS: usize = 4;
+    const SHORT_TERM_STEPS: usize = 30;
+
+    /// The absolute gate, in LUFS.
+    const ABSOLUTE_GATE: f64 = -70.0;
+
+    /// The relative gates of the integrated loudness and loudness range, in LU.
+    const INTEGRATED_GATE: f64 = -10.0;
+    const RANGE_GATE: f64 = -20.0;
+
+    /// The oversampling factor of the true-peak meter, and the number of taps of each phase of its
+    /// interpolation filter.
+    const OVERSAMPLING: usize = 4;
+    const TAPS_PER_PHASE: usize = 12;
+
+    /// A biquad filter in transposed direct form II.
+    #[derive(Clone)]
+    struct Biquad {
+        b: [f64; 3],
+        a: [f64; 2],
+        z: [f64; 2],
+    }
+
+    impl Biquad {
+        fn process(&mut self, x: f64) -> f64 {
+            let y = self.b[0] * x + self.z[0];
+            self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+            self.z[1] = self.b[2] * x - self.a[1] * y;
+            y
+        }
+    }
+
+    /// Designs the two stages of the K-weighting filter for a sample rate. The stages are a high
+    /// shelf modelling the acoustic effect of the head, and a high-pass filter. The analog
+    /// prototypes are those of BS.1770, which at 48 kHz reproduce the coefficients in the
+    /// recommendation.
+    fn k_weighting(rate: u32) -> [Biquad; 2] {
+        let rate = f64::from(rate);
+
+        let shelf = {
+            let f0 = 1681.974450955533;
+            let gain = 3.999843853973347;
+            let q = 0.7071752369554196;
+
+            let k = (std::f64::consts::PI * f0 / rate).tan();
+            let vh = 10f64.powf(gain / 20.0);
+            let vb = vh.powf(0.4996667741545416);
+
+            let a0 = 1.0 + k / q + k * k;
+
+            Biquad {
+                b: [
+                    (vh + vb * k / q + k * k) / a0,
+                    2.0 * (k * k - vh) / a0,
+                    (vh - vb * k / q + k * k) / a0,
+                ],
+                a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+                z: [0.0; 2],
+            }
+        };
+
+        let high_pass = {
+            let f0 = 38.13547087602444;
+            let q = 0.5003270373238773;
+
+            let k = (std::f64::consts::PI * f0 / rate).tan();
+            let a0 = 1.0 + k / q + k * k;
+
+            Biquad {
+                b: [1.0, -2.0, 1.0],
+                a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+                z: [0.0; 2],
+            }
+        };
+
+        [shelf, high_pass]
+    }
+
+    /// The weight of a channel when summing the channel powers. Surround channels are weighted
+    /// +1.5 dB, and the LFE channel is excluded.
+    fn channel_weight(channel: Channels) -> f64 {
+        match channel {
+            Channels::LFE1 | Channels::LFE2 => 0.0,
+            Channels::REAR_LEFT
+            | Channels::REAR_RIGHT
+            | Channels::SIDE_LEFT
+            | Channels::SIDE_RIGHT => 1.41,
+            _ => 1.0,
+        }
+    }
+
+    /// Converts a mean square power to loudness in LUFS.
+    fn to_lufs(power: f64) -> f64 {
+        -0.691 + 10.0 * power.max(f64::MIN_POSITIVE).log10()
+    }
+
+    /// The polyphase interpolation filter of the true-peak meter, from Annex 2 of ITU-R BS.1770-4.
+    /// Each phase interpolates one of the `OVERSAMPLING` samples between two input samples.
+    const TRUE_PEAK_PHASES: [[f64; TAPS_PER_PHASE]; OVERSAMPLING] = [
+        [
+            0.0017089843750,
+            0.0109863281250,
+            -0.0196533203125,
+            0.0332031250000,
+            -0.0594482421875,
+            0.1373291015625,
+            0.9721679687500,
+            -0.1022949218750,
+            0.0476074218750,
+            -0.0266113281250,
+            0.0148925781250,
+            -0.0083007812500,
+        ],
+        [
+            -0.0291748046875,
+            0.0292968750000,
+            -0.0517578125000,
+            0.0891113281250,
+            -0.1665039062500,
+            0.4650878906250,
+            0.7797851562500,
+            -0.2003173828125,
+            0.1015625000000,
+            -0.0582275390625,
+            0.0330810546875,
+            -0.0189208984375,
+        ],
+        [
+            -0.0189208984375,
+            0.0330810546875,
+            -0.0582275390625,
+            0.1015625000000,
+            -0.2003173828125,
+            0.7797851562500,
+            0.4650878906250,
+            -0.1665039062500,
+            0.0891113281250,
+            -0.0517578125000,
+            0.0292968750000,
+            -0.0291748046875,
+        ],
+        [
+            -0.0083007812500,
+            0.0148925781250,
+            -0.0266113281250,
+            0.0476074218750,
+            -0.1022949218750,
+            0.9721679687500,
+            0.1373291015625,
+            -0.0594482421875,
+            0.0332031250000,
+            -0.0196533203125,
+            0.0109863281250,
+            0.0017089843750,
+        ],
+    ];
+
+    /// The K-weighting and true-peak state of one channel.
+    struct ChannelState {
+        weight: f64,
+        filters: [Biquad; 2],
+        /// The sum of the squares of the weighted samples in the current step.
+        sum_sq: f64,
+        /// The most recent samples, newest first, for the true-peak interpolator.
+        history: [f64; TAPS_PER_PHASE],
+        true_peak: f64,
+    }
+
+    /// A loudness meter implementing the measurements of EBU R128.
+    pub struct LoudnessMeter {
+        spec: SignalSpec,
+        channels: Vec<ChannelState>,
+        step_len: usize,
+        step_pos: usize,
+        /// The weighted power of the most recent steps, newest last.
+        steps: VecDeque<f64>,
+        /// The power of every momentary block (400 ms, 75% overlap), for the integrated loudness.
+        momentary_blocks: Vec<f64>,
+        /// The power of every short-term block (3 s), for the loudness range.
+        short_term_blocks: Vec<f64>,
+    }
+
+    impl LoudnessMeter {
+        pub fn new(spec: SignalSpec) -> Self {
+            LoudnessMeter {
+                spec,
+                channels: channel_states(spec, 0.0),
+                step_len: step_len(spec.rate),
+                step_pos: 0,
+                steps: VecDeque::with_capacity(SHORT_TERM_STEPS),
+                momentary_blocks: Vec::new(),
+                short_term_blocks: Vec::new(),
+            }
+        }
+
+        /// Continues the measurement with audio of a different spec. The filters are designed for
+        /// the new spec, and the partially measured step is discarded, but the blocks measured so
+        /// far and the true peak are kept.
+        pub fn reconfigure(&mut self, spec: SignalSpec) {
+            if spec == self.spec {
+                return;
+            }
+
+            let true_peak = self.channels.iter().map(|state| state.true_peak).fold(0.0, f64::max);
+
+            self.spec = spec;
+            self.channels = channel_states(spec, true_peak);
+            self.step_len = step_len(spec.rate);
+            self.step_pos = 0;
+        }
+
+        /// Measures a buffer of interleaved samples.
+        pub fn process(&mut self, interleaved: &[f32]) {
+            let n_channels = self.channels.len();
+
+            for frame in interleaved.chunks_exact(n_channels) {
+                for (state, &sample) in self.channels.iter_mut().zip(frame) {
+                    let x = f64::from(sample);
+
+                    let y = state.filters.iter_mut().fold(x, |x, filter| filter.process(x));
+                    state.sum_sq += y * y;
+
+                    state.history.rotate_right(1);
+                    state.history[0] = x;
+
+                    for phase in &TRUE_PEAK_PHASES {
+                        let y: f64 = phase.iter().zip(&state.history).map(|(h, x)| h * x).sum();
+                        state.true_peak = state.true_peak.max(y.abs());
+                    }
+
+                    state.true_peak = state.true_peak.max(x.abs());
+                }
+
+                self.step_pos += 1;
+
+                if self.step_pos == self.step_len {
+                    self.end_step();
+                }
+            }
+        }
+
+        fn end_step(&mut self) {
+            let step_len = self.step_len as f64;
+
+            let power = self
+                .channels
+                .iter_mut()
+                .map(|state| {
+                    let power = state.weight * state.sum_sq / step_len;
+                    state.sum_sq = 0.0;
+                    power
+                })
+                .sum();
+
+            self.step_pos = 0;
+
+            if self.steps.len() == SHORT_TERM_STEPS {
+                self.steps.pop_front();
+            }
+            self.steps.push_back(power);
+
+            if let Some(power) = self.window_power(MOMENTARY_STEPS) {
+                self.momentary_blocks.push(power);
+            }
+
+            if let Some(power) = self.window_power(SHORT_TERM_STEPS) {
+                self.short_term_blocks.push(power);
+            }
+        }
+
+        /// Gets the mean power of the most recent `n` steps, if there have been that many.
+        fn window_power(&self, n: usize) -> Option<f64> {
+            if self.steps.len() < n {
+                return None;
+            }
+
+            Some(self.steps.iter().rev().take(n).sum::<f64>() / n as f64)
+        }
+
+        /// The momentary loudness, in LUFS.
+        pub fn momentary(&self) -> Option<f64> {
+            self.window_power(MOMENTARY_STEPS).map(to_lufs)
+        }
+
+        /// The short-term loudness, in LUFS.
+        pub fn short_term(&self) -> Option<f64> {
+            self.window_power(SHORT_TERM_STEPS).map(to_lufs)
+        }
+
+        /// The gated integrated loudness, in LUFS.
+        pub fn integrated(&self) -> Option<f64> {
+            let gated = gate(&self.momentary_blocks, INTEGRATED_GATE)?;
+
+            Some(to_lufs(gated.iter().sum::<f64>() / gated.len() as f64))
+        }
+
+        /// The loudness range, in LU: the spread between the 10th and 95th percentiles of the
+        /// gated short-term loudness distribution.
+        pub fn range(&self) -> Option<f64> {
+            let gated = gate(&self.short_term_blocks, RANGE_GATE)?;
+
+            let mut loudness: Vec<f64> = gated.into_iter().map(to_lufs).collect();
+            loudness.sort_by(|a, b| a.total_cmp(b));
+
+            let percentile = |p: f64| {
+                let i = (p * (loudness.len() - 1) as f64).round() as usize;
+                loudness[i]
+            };
+
+            Some(percentile(0.95) - percentile(0.10))
+        }
+
+        /// The maximum true-peak level of all channels, in dBTP.
+        pub fn true_peak(&self) -> f64 {
+            let peak = self.channels.iter().map(|state| state.true_peak).fold(0.0, f64::max);
+            20.0 * peak.max(f64::MIN_POSITIVE).log10()
+        }
+    }
+
+    fn channel_states(spec: SignalSpec, true_peak: f64) -> Vec<ChannelState> {
+        spec.channels
+            .iter()
+            .map(|channel| ChannelState {
+                weight: channel_weight(channel),
+                filters: k_weighting(spec.rate),
+                sum_sq: 0.0,
+                history: [0.0; TAPS_PER_PHASE],
+                true_peak,
+            })
+            .collect()
+    }
+
+    /// The number of frames in each step at a sample rate.
+    fn step_len(rate: u32) -> usize {
+        (f64::from(rate) * BLOCK_STEP).round().max(1.0) as usize
+    }
+
+    /// Applies the absolute gate, and a gate `relative` LU below the loudness of the blocks above
+    /// the absolute gate. Returns the blocks above both gates.
+    fn gate(blocks: &[f64], relative: f64) -> Option<Vec<f64>> {
+        let above_absolute: Vec<f64> =
+            blocks.iter().cloned().filter(|&power| to_lufs(power) > ABSOLUTE_GATE).collect();
+
+        if above_absolute.is_empty() {
+            return None;
+        }
+
+        let threshold = to_lufs(above_absolute.iter().sum::<f64>() / above_absolute.len() as f64)
+            + relative;
+
+        let gated: Vec<f64> =
+            above_absolute.into_iter().filter(|&power| to_lufs(power) > threshold).collect();
+
+        if gated.is_empty() {
+            return None;
+        }
+
+        Some(gated)
+    }
+
+    /// Formats an optional loudness value.
+    fn fmt_level(value: Option<f64>) -> String {
+        match value {
+            Some(value) if value.is_finite() => format!("{:>6.1}", value),
+            _ => format!("{:>6}", "-inf"),
+        }
+    }
+
+    /// The loudness measured over the whole of playback. The measurement is kept when the
+    /// loudness display is closed, and continued when it is reopened, such as when it is hidden
+    /// and shown again, or the signal spec changes.
+    #[derive(Clone, Default)]
+    pub struct LoudnessHistory(Arc<Mutex<Option<LoudnessMeter>>>);
+
+    impl LoudnessHistory {
+        /// Takes the meter to continue measuring audio with the given spec.
+        fn take(&self, spec: SignalSpec) -> LoudnessMeter {
+            match self.0.lock().unwrap().take() {
+                Some(mut meter) => {
+                    meter.reconfigure(spec);
+                    meter
+                }
+                None => LoudnessMeter::new(spec),
+            }
+        }
+
+        fn put(&self, meter: LoudnessMeter) {
+            *self.0.lock().unwrap() = Some(meter);
+        }
+
+        /// Prints the integrated loudness, loudness range and true peak, if any audio was measured.
+        pub fn print_summary(&self) {
+            if let Some(meter) = self.0.lock().unwrap().as_ref() {
+                println!("Loudness summary:");
+                println!("  Integrated:     {} LUFS", fmt_level(meter.integrated()));
+                println!("  Loudness range: {} LU", fmt_level(meter.range()));
+                println!("  True peak:      {} dBTP", fmt_level(Some(meter.true_peak())));
+            }
+        }
+    }
+
+    pub struct LoudnessDisplay {
+        sample_buf: SampleBuffer<f32>,
+        /// The meter is taken from the history while the display is open.
+        meter: Option<LoudnessMeter>,
+        history: LoudnessHistory,
+        limiter: FrameLimiter,
+        out: String,
+    }
+
+    impl LoudnessDisplay {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            history: &LoudnessHistory,
+        ) -> Result<Box<dyn Display>> {
+            term::enter();
+
+            Ok(Box::new(LoudnessDisplay {
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                meter: Some(history.take(spec)),
+                history: history.clone(),
+                limiter: FrameLimiter::new(),
+                out: String::new(),
+            }))
+        }
+
+        fn render(&mut self) {
+            let meter = match self.meter.as_ref() {
+                Some(meter) => meter,
+                None => return,
+            };
+
+            self.out.clear();
+            self.out.push_str("\x1b[H");
+
+            let _ = write!(
+                self.out,
+                "Momentary       {} LUFS\x1b[K\r\n\
+                 Short-term      {} LUFS\x1b[K\r\n\
+                 Integrated      {} LUFS\x1b[K\r\n\
+                 Loudness range  {} LU\x1b[K\r\n\
+                 True peak       {} dBTP\x1b[K",
+                fmt_level(meter.momentary()),
+                fmt_level(meter.short_term()),
+                fmt_level(meter.integrated()),
+                fmt_level(meter.range()),
+                fmt_level(Some(meter.true_peak())),
+            );
+
+            term::draw(&self.out);
+        }
+    }
+
+    impl Display for LoudnessDisplay {
+        fn write(&mut self, decoded: AudioBufferRef<'_>, _ts: u64) -> Result<()> {
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            if let Some(meter) = self.meter.as_mut() {
+                meter.process(self.sample_buf.samples());
+            }
+
+            if self.limiter.ready() {
+                self.render();
+            }
+
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            // The display is only flushed once. The summary is printed at the end of playback.
+            if self.meter.is_some() {
+                self.render();
+                term::leave();
+            }
+
+            if let Some(meter) = self.meter.take() {
+                self.history.put(meter);
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Renders a spectrogram of the whole stream to an image file.
+mod image {
+    use std::fs::File;
+    use std::io::BufWriter;
+    use std::path::PathBuf;
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use super::colormap;
+    use super::stft::{downmix, normalize, LogBins, Stft, StftOptions};
+    use super::{rate_changed, Display, DisplayError, Result};
+
+    /// The scale factor of the label font.
+    const SCALE: usize = 2;
+
+    /// The colour of the axis labels and ticks.
+    const LABEL_COLOUR: (u8, u8, u8) = (200, 200, 200);
+
+    /// The frequencies labelled on the frequency axis.
+    const FREQ_TICKS: [(f32, &str); 9] = [
+        (50.0, "50"),
+        (100.0, "100"),
+        (200.0, "200"),
+        (500.0, "500"),
+        (1000.0, "1k"),
+        (2000.0, "2k"),
+        (5000.0, "5k"),
+        (10000.0, "10k"),
+        (20000.0, "20k"),
+    ];
+
+    /// The candidate intervals, in seconds, between labels on the time axis.
+    const TIME_STEPS: [f64; 13] =
+        [0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+    #[derive(Clone, Debug)]
+    pub struct ImageOptions {
+        /// The path of the PNG file to write.
+        pub path: PathBuf,
+        pub width: usize,
+        pub height: usize,
+        /// Draw frequency and time axis labels.
+        pub labels: bool,
+    }
+
+    /// A 3x5 pixel glyph. Each row is stored in the 3 least significant bits, most significant
+    /// bit on the left.
+    fn glyph(c: char) -> [u8; 5] {
+        match c {
+            '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+            '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+            '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+            '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+            '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+            '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+            '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+            '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+            '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+            '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+            'k' => [0b100, 0b101, 0b110, 0b101, 0b101],
+            's' => [0b011, 0b100, 0b010, 0b001, 0b110],
+            '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+            _ => [0; 5],
+        }
+    }
+
+    /// An RGB image.
+    struct Image {
+        width: usize,
+        height: usize,
+        data: Vec<u8>,
+    }
+
+    impl Image {
+        fn new(width: usize, height: usize) -> Self {
+            Image { width, height, data: vec![0; 3 * width * height] }
+        }
+
+        fn set(&mut self, x: usize, y: usize, colour: (u8, u8, u8)) {
+            if x < self.width && y < self.height {
+                let i = 3 * (y * self.width + x);
+                self.data[i..i + 3].copy_from_slice(&[colour.0, colour.1, colour.2]);
+            }
+        }
+
+        /// Draws `text` with its top-left corner at `x`, `y`.
+        fn text(&mut self, x: usize, y: usize, text: &str) {
+            for (i, c) in text.chars().enumerate() {
+                for (row, bits) in glyph(c).iter().enumerate() {
+                    for col in 0..3 {
+                        if bits & (0b100 >> col) == 0 {
+                            continue;
+                        }
+
+                        for dy in 0..SCALE {
+                            for dx in 0..SCALE {
+                                let px = x + (4 * i + col) * SCALE + dx;
+                                self.set(px, y + row * SCALE + dy, LABEL_COLOUR);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        fn text_width(text: &str) -> usize {
+            (4 * text.chars().count()).saturating_sub(1) * SCALE
+        }
+
+        fn write_png(&self, path: &PathBuf) -> std::result::Result<(), png::EncodingError> {
+            let file = BufWriter::new(File::create(path)?);
+
+            let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&self.data)?;
+            writer.finish()
+        }
+    }
+
+    /// Accumulates spectrogram columns for the whole stream in bounded memory. Once twice as many
+    /// columns as the image is wide have been stored, adjacent columns are merged and each column
+    /// covers twice as many frames from then on.
+    struct Columns {
+        max_columns: usize,
+        columns: Vec<Vec<f32>>,
+        frames_per_column: usize,
+        partial: Vec<f32>,
+        partial_frames: usize,
+        scratch: Vec<f32>,
+    }
+
+    impl Columns {
+        fn new(width: usize) -> Self {
+            Columns {
+                max_columns: 2 * width.max(1),
+                columns: Vec::new(),
+                frames_per_column: 1,
+                partial: Vec::new(),
+                partial_frames: 0,
+                scratch: Vec::new(),
+            }
+        }
+
+        fn push(&mut self, spectrum: &[f32], bins: &LogBins, reference: f32) {
+            bins.apply(spectrum, &mut self.scratch);
+            normalize(&mut self.scratch, reference);
+
+            if self.partial_frames == 0 {
+                self.partial.clear();
+                self.partial.extend_from_slice(&self.scratch);
+            }
+            else {
+                merge(&mut self.partial, &self.scratch);
+            }
+
+            self.partial_frames += 1;
+
+            if self.partial_frames == self.frames_per_column {
+                self.columns.push(self.partial.clone());
+                self.partial_frames = 0;
+
+                if self.columns.len() >= self.max_columns {
+                    let merged = self
+                        .columns
+                        .chunks_exact(2)
+                        .map(|pair| {
+                            let mut column = pair[0].clone();
+                            merge(&mut column, &pair[1]);
+                            column
+                        })
+                        .collect();
+
+                    self.columns = merged;
+                    self.frames_per_column *= 2;
+                }
+            }
+        }
+
+        /// Gets all complete columns, and the partially accumulated column, if any.
+        fn finish(mut self) -> Vec<Vec<f32>> {
+            if self.partial_frames > 0 {
+                self.columns.push(self.partial);
+            }
+            self.columns
+        }
+    }
+
+    /// Merges two columns by taking the peak of each row.
+    fn merge(column: &mut [f32], other: &[f32]) {
+        for (a, &b) in column.iter_mut().zip(other) {
+            *a = a.max(b);
+        }
+    }
+
+    pub struct ImageDisplay {
+        sample_buf: SampleBuffer<f32>,
+        channels: usize,
+        rate: u32,
+        hop_size: usize,
+        stft: Stft,
+        reference: f32,
+        bins: LogBins,
+        columns: Option<Columns>,
+        n_frames: u64,
+        opts: ImageOptions,
+        /// The margins to the left of, and below, the plot.
+        margins: (usize, usize),
+    }
+
+    impl ImageDisplay {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            stft_opts: &StftOptions,
+            opts: &ImageOptions,
+        ) -> Result<Box<dyn Display>> {
+            let sample_buf = SampleBuffer::<f32>::new(duration, spec);
+
+            let stft = Stft::new(stft_opts);
+            let reference = stft.reference();
+
+            let margins = if opts.labels {
+                (Image::text_width("20k") + 3 * SCALE, 8 * SCALE)
+            }
+            else {
+                (0, 0)
+            };
+
+            let plot_width = opts.width.saturating_sub(margins.0).max(1);
+            let plot_height = opts.height.saturating_sub(margins.1).max(1);
+
+            let bins = LogBins::new(plot_height, stft.fft_size(), spec.rate);
+
+            Ok(Box::new(ImageDisplay {
+                sample_buf,
+                channels: spec.channels.count().max(1),
+                rate: spec.rate,
+                hop_size: stft_opts.hop_size.max(1),
+                stft,
+                reference,
+                bins,
+                columns: Some(Columns::new(plot_width)),
+                n_frames: 0,
+                opts: opts.clone(),
+                margins,
+            }))
+        }
+
+        fn render(&self, columns: &[Vec<f32>]) -> Image {
+            let (left, bottom) = self.margins;
+
+            let width = self.opts.width.max(left + 1);
+            let height = self.opts.height.max(bottom + 1);
+
+            let plot_width = width - left;
+            let plot_height = height - bottom;
+
+            let mut image = Image::new(width, height);
+
+            let n = columns.len();
+
+            for x in 0..plot_width {
+                // Each pixel column shows the peak of the spectrogram columns it covers, or the
+                // nearest column if there are fewer columns than pixels.
+                let start = x * n / plot_width;
+                let end = ((x + 1) * n / plot_width).max(start + 1).min(n);
+
+                for row in 0..plot_height {
+                    let value = columns
+                        .get(start..end)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|column| column[row])
+                        .fold(0.0, f32::max);
+
+                    image.set(left + x, plot_height - 1 - row, colormap::inferno(value));
+                }
+            }
+
+            if self.opts.labels {
+                self.draw_labels(&mut image, plot_width, plot_height);
+            }
+
+            image
+        }
+
+        fn draw_labels(&self, image: &mut Image, plot_width: usize, plot_height: usize) {
+            let (left, _) = self.margins;
+            let glyph_height = 5 * SCALE;
+
+            // Frequency axis.
+            for &(freq, label) in FREQ_TICKS.iter() {
+                if let Some(pos) = self.bins.position(freq) {
+                    let y = plot_height - 1 - (pos * (plot_height - 1) as f32).round() as usize;
+
+                    for x in left.saturating_sub(SCALE)..left {
+                        image.set(x, y, LABEL_COLOUR);
+                    }
+
+                    // Labels that do not fit beside the plot are skipped.
+                    let text_x = left.checked_sub(2 * SCALE + Image::text_width(label));
+                    let max_y = plot_height.checked_sub(glyph_height);
+
+                    if let (Some(text_x), Some(max_y)) = (text_x, max_y) {
+                        image.text(text_x, y.saturating_sub(glyph_height / 2).min(max_y), label);
+                    }
+                }
+            }
+
+            // Time axis. Use the smallest interval that leaves room for the longest label.
+            let total = (self.n_frames * self.hop_size as u64) as f64 / f64::from(self.rate);
+
+            if total <= 0.0 {
+                return;
+            }
+
+            let min_spacing = (Image::text_width("0000s") + 4 * SCALE) as f64;
+
+            let step = TIME_STEPS
+                .iter()
+                .cloned()
+                .find(|step| step / total * plot_width as f64 >= min_spacing)
+                .unwrap_or(TIME_STEPS[TIME_STEPS.len() - 1]);
+
+            let mut i = 0;
+
+            loop {
+                let time = i as f64 * step;
+
+                if time > total {
+                    break;
+                }
+
+                let x = left + (time / total * (plot_width - 1) as f64).round() as usize;
+
+                for y in plot_height..plot_height + SCALE {
+                    image.set(x, y, LABEL_COLOUR);
+                }
+
+                let label =
+                    if step < 1.0 { format!("{:.1}s", time) } else { format!("{}s", time as u64) };
+
+                if let Some(max_x) = (left + plot_width).checked_sub(Image::text_width(&label)) {
+                    image.text(x.min(max_x), plot_height + 2 * SCALE, &label);
+                }
+
+                i += 1;
+            }
+        }
+    }
+
+    impl Display for ImageDisplay {
+        fn write(&mut self, decoded: AudioBufferRef<'_>, _ts: u64) -> Result<()> {
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            downmix(self.sample_buf.samples(), self.channels, self.stft.pending_mut());
+
+            if let Some(columns) = self.columns.as_mut() {
+                for spectrum in self.stft.process() {
+                    columns.push(spectrum, &self.bins, self.reference);
+                    self.n_frames += 1;
+                }
+            }
+
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            // The image is only written once.
+            let columns = match self.columns.take() {
+                Some(columns) => columns.finish(),
+                None => return Ok(()),
+            };
+
+            let image = self.render(&columns);
+
+            image.write_png(&self.opts.path).map_err(|err| {
+                let msg = format!("failed to write {}: {}", self.opts.path.display(), err);
+                DisplayError::WriteError(msg)
+            })
+        }
+
+        fn reconfigure(&mut self, spec: SignalSpec, duration: Duration) -> Result<bool> {
+            // Reopening the display would overwrite the image, so the spectrogram is continued.
+            // The downmix does not depend on the channels, but the frequency axis does depend on
+            // the sample rate.
+            if spec.rate != self.rate {
+                return Err(rate_changed(self.rate, spec.rate));
+            }
+
+            self.channels = spec.channels.count().max(1);
+            self.sample_buf = SampleBuffer::<f32>::new(duration, spec);
+
+            Ok(true)
+        }
+    }
+}
+
+/// Exports the raw STFT of the whole stream to a file for analysis with other tools.
+mod export {
+    use std::fs::File;
+    use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+    use std::path::{Path, PathBuf};
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::{Duration, TimeBase};
+
+    use super::stft::{downmix, Stft, StftOptions};
+    use super::{rate_changed, Display, DisplayError, Result};
+
+    /// The scale of the exported values. All scales are relative to a full-scale sinusoid.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum ExportScale {
+        Magnitude,
+        Power,
+        Decibels,
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum ExportFormat {
+        Csv,
+        Npy,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct ExportOptions {
+        pub path: PathBuf,
+        pub format: ExportFormat,
+        pub scale: ExportScale,
+        /// Analyse each channel separately instead of the downmix of all channels.
+        pub per_channel: bool,
+    }
+
+    /// The size of a `.npy` header, including the magic string. The header is written with a
+    /// placeholder shape when the file is created, and rewritten in place once the number of frames
+    /// is known, so it is padded to a fixed size that fits any shape.
+    const NPY_HEADER_LEN: usize = 128;
+
+    /// Writes a `.npy` file whose first dimension grows as rows are written.
+    struct NpyWriter {
+        file: BufWriter<File>,
+        descr: &'static str,
+        /// The shape of each row.
+        row_shape: Vec<usize>,
+        rows: usize,
+    }
+
+    impl NpyWriter {
+        fn create(path: &Path, descr: &'static str, row_shape: &[usize]) -> io::Result<Self> {
+            let mut writer = NpyWriter {
+                file: BufWriter::new(File::create(path)?),
+                descr,
+                row_shape: row_shape.to_vec(),
+                rows: 0,
+            };
+
+            writer.write_header()?;
+
+            Ok(writer)
+        }
+
+        fn write_header(&mut self) -> io::Result<()> {
+            let shape = std::iter::once(self.rows)
+                .chain(self.row_shape.iter().cloned())
+                .map(|dim| format!("{}, ", dim))
+                .collect::<String>();
+
+            let mut header = format!(
+                "{{'descr': '{}', 'fortran_order': False, 'shape': ({}), }}",
+                self.descr,
+                shape.trim_end_matches(' ')
+            );
+
+            // The header is padded with spaces and terminated by a newline.
+            let len = NPY_HEADER_LEN - 10;
+            header.push_str(&" ".repeat(len.saturating_sub(header.len() + 1)));
+            header.push('\n');
+
+            self.file.write_all(b"\x93NUMPY\x01\x00")?;
+            self.file.write_all(&(header.len() as u16).to_le_bytes())?;
+            self.file.write_all(header.as_bytes())
+        }
+
+        fn write_f32(&mut self, values: &[f32]) -> io::Result<()> {
+            for value in values {
+                self.file.write_all(&value.to_le_bytes())?;
+            }
+            Ok(())
+        }
+
+        fn write_f64(&mut self, values: &[f64]) -> io::Result<()> {
+            for value in values {
+                self.file.write_all(&value.to_le_bytes())?;
+            }
+            Ok(())
+        }
+
+        /// Rewrites the header with the final shape, and flushes the file.
+        fn finish(&mut self) -> io::Result<()> {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.write_header()?;
+            self.file.flush()
+        }
+    }
+
+    /// Gets the path of a file written alongside the main `.npy` file, for example,
+    /// `out.npy` becomes `out_times.npy`.
+    fn sidecar_path(path: &Path, suffix: &str) -> PathBuf {
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+        path.with_file_name(format!("{}_{}.npy", stem, suffix))
+    }
+
+    enum Sink {
+        Csv(BufWriter<File>),
+        Npy { values: NpyWriter, times: NpyWriter },
+    }
+
+    pub struct ExportDisplay {
+        sample_buf: SampleBuffer<f32>,
+        channels: usize,
+        rate: u32,
+        tb: TimeBase,
+        hop_size: usize,
+        /// One analyser per channel, or one for the downmix.
+        stfts: Vec<Stft>,
+        reference: f32,
+        /// The time, in seconds, of the first sample waiting to be analysed.
+        pending_time: f64,
+        /// The time, in seconds, at which the current input starts. The times of each input
+        /// continue from the end of the previous input, so that the exported times increase.
+        offset: f64,
+        /// The time, in seconds, just after the last sample written.
+        end_time: f64,
+        scale: ExportScale,
+        sink: Option<Sink>,
+        values: Vec<f32>,
+    }
+
+    impl ExportDisplay {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            tb: Option<TimeBase>,
+            stft_opts: &StftOptions,
+            opts: &ExportOptions,
+        ) -> Result<Box<dyn Display>> {
+            let sample_buf = SampleBuffer::<f32>::new(duration, spec);
+
+            let channels = spec.channels.count().max(1);
+            let n_stfts = if opts.per_channel { channels } else { 1 };
+
+            let stfts: Vec<_> = (0..n_stfts).map(|_| Stft::new(stft_opts)).collect();
+
+            let fft_size = stfts[0].fft_size();
+            let n_bins = stfts[0].n_bins();
+            let reference = stfts[0].reference();
+
+            let freqs: Vec<f64> =
+                (0..n_bins).map(|bin| bin as f64 * f64::from(spec.rate) / fft_size as f64).collect();
+
+            let sink = match open_sink(opts, n_stfts, &freqs) {
+                Ok(sink) => sink,
+                Err(err) => {
+                    let msg = format!("failed to create {}: {}", opts.path.display(), err);
+                    return Err(DisplayError::OpenError(msg));
+                }
+            };
+
+            Ok(Box::new(ExportDisplay {
+                sample_buf,
+                channels,
+                rate: spec.rate,
+                tb: tb.unwrap_or_else(|| TimeBase::new(1, spec.rate)),
+                hop_size: stft_opts.hop_size.max(1),
+                stfts,
+                reference,
+                pending_time: 0.0,
+                offset: 0.0,
+                end_time: 0.0,
+                scale: opts.scale,
+                sink: Some(sink),
+                values: Vec::with_capacity(n_bins),
+            }))
+        }
+
+        /// Analyses the pending samples of every analyser and writes the frames.
+        fn process(&mut self) -> io::Result<()> {
+            let sink = match self.sink.as_mut() {
+                Some(sink) => sink,
+                None => return Ok(()),
+            };
+
+            let hop_time = self.hop_size as f64 / f64::from(self.rate);
+
+            // Every analyser is fed the same number of samples, so they produce the same number of
+            // frames.
+            let mut n_frames = 0;
+
+            for (channel, stft) in self.stfts.iter_mut().enumerate() {
+                let frames = stft.process();
+                n_frames = frames.len();
+
+                for (i, spectrum) in frames.iter().enumerate() {
+                    let time = self.pending_time + i as f64 * hop_time;
+
+                    self.values.clear();
+                    self.values.extend(
+                        spectrum.iter().map(|&mag| scale(mag / self.reference, self.scale)),
+                    );
+
+                    match sink {
+                        Sink::Csv(file) => {
+                            write!(file, "{:.6},{}", time, channel)?;
+                            for value in &self.values {
+                                write!(file, ",{}", value)?;
+                            }
+                            writeln!(file)?;
+                        }
+                        Sink::Npy { values, times } => {
+                            values.write_f32(&self.values)?;
+
+                            // Frames of all channels are written interleaved, so each frame has
+                            // one timestamp.
+                            if channel == 0 {
+                                times.write_f64(&[time])?;
+                                times.rows += 1;
+                                values.rows += 1;
+                            }
+                        }
+                    }
+                }
+            }
+
+            self.pending_time += n_frames as f64 * hop_time;
+
+            Ok(())
+        }
+    }
+
+    fn open_sink(opts: &ExportOptions, n_channels: usize, freqs: &[f64]) -> io::Result<Sink> {
+        match opts.format {
+            ExportFormat::Csv => {
+                let mut file = BufWriter::new(File::create(&opts.path)?);
+
+                // The header holds the centre frequency of each bin.
+                write!(file, "time,channel")?;
+                for freq in freqs {
+                    write!(file, ",{}", freq)?;
+                }
+                writeln!(file)?;
+
+                Ok(Sink::Csv(file))
+            }
+            ExportFormat::Npy => {
+                // The values have the shape (frames, channels, bins), and the times and
+                // frequencies are written to separate files.
+                let values = NpyWriter::create(&opts.path, "<f4", &[n_channels, freqs.len()])?;
+                let times = NpyWriter::create(&sidecar_path(&opts.path, "times"), "<f8", &[])?;
+
+                let mut freqs_file =
+                    NpyWriter::create(&sidecar_path(&opts.path, "freqs"), "<f8", &[])?;
+                freqs_file.write_f64(freqs)?;
+                freqs_file.rows = freqs.len();
+                freqs_file.finish()?;
+
+                Ok(Sink::Npy { values, times })
+            }
+        }
+    }
+
+    /// Scales a magnitude relative to full scale.
+    fn scale(mag: f32, scale: ExportScale) -> f32 {
+        match scale {
+            ExportScale::Magnitude => mag,
+            ExportScale::Power => mag * mag,
+            ExportScale::Decibels => 20.0 * mag.max(f32::MIN_POSITIVE).log10(),
+        }
+    }
+
+    impl Display for ExportDisplay {
+        fn write(&mut self, decoded: AudioBufferRef<'_>, ts: u64) -> Result<()> {
+            let frames = decoded.frames();
+
+            if frames == 0 {
+                return Ok(());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            // Re-synchronize to the timestamp of the packet. Samples left over from the previous
+            // packet come before it.
+            let time = self.tb.calc_time(ts);
+            let time = self.offset + time.seconds as f64 + time.frac;
+            let leftover = self.stfts[0].pending_mut().len();
+
+            self.pending_time = time - leftover as f64 / f64::from(self.rate);
+            self.end_time = time + frames as f64 / f64::from(self.rate);
+
+            let samples = self.sample_buf.samples();
+
+            if self.stfts.len() == 1 {
+                downmix(samples, self.channels, self.stfts[0].pending_mut());
+            }
+            else {
+                for (channel, stft) in self.stfts.iter_mut().enumerate() {
+                    stft.pending_mut()
+                        .extend(samples.iter().skip(channel).step_by(self.channels).cloned());
+                }
+            }
+
+            if let Err(err) = self.process() {
+                // Stop exporting on the first error rather than writing a truncated file.
+                self.sink = None;
+                return Err(DisplayError::WriteError(format!("failed to export stft: {}", err)));
+            }
+
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            let result = match self.sink.take() {
+                Some(Sink::Csv(mut file)) => file.flush(),
+                Some(Sink::Npy { mut values, mut times }) => {
+                    values.finish().and_then(|_| times.finish())
+                }
+                None => Ok(()),
+            };
+
+            result
+                .map_err(|err| DisplayError::WriteError(format!("failed to export stft: {}", err)))
+        }
+
+        fn reconfigure(&mut self, spec: SignalSpec, duration: Duration) -> Result<bool> {
+            // Reopening the display would overwrite the file, so the export is continued. The
+            // bin frequencies, and the channels of a per-channel export, are fixed by the header.
+            if spec.rate != self.rate {
+                return Err(rate_changed(self.rate, spec.rate));
+            }
+
+            let channels = spec.channels.count().max(1);
+
+            if self.stfts.len() > 1 && channels != self.channels {
+                return Err(DisplayError::WriteError(format!(
+                    "the number of channels changed from {} to {}, which cannot be exported \
+                     per channel into one file",
+                    self.channels, channels
+                )));
+            }
+
+            self.channels = channels;
+            self.sample_buf = SampleBuffer::<f32>::new(duration, spec);
+
+            Ok(true)
+        }
+
+        fn start_input(&mut self, tb: Option<TimeBase>) {
+            self.offset = self.end_time;
+            self.tb = tb.unwrap_or_else(|| TimeBase::new(1, self.rate));
+        }
+    }
+}
+
+/// Runs a display on a dedicated thread so that analysis and rendering never block playback.
+mod threaded {
+    use std::borrow::Cow;
+    use std::collections::VecDeque;
+    use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+    use std::thread::{self, JoinHandle};
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use log::{error, warn};
+
+    use super::{Display, DisplayError, Result};
+
+    /// The maximum number of buffers waiting to be displayed. If the display thread falls behind
+    /// by more than this, the oldest buffers are dropped.
+    const QUEUE_CAPACITY: usize = 32;
+
+    /// The number of buffers in flight to the display thread. Buffers beyond this wait on the
+    /// writer's side, where the oldest can still be dropped.
+    const CHANNEL_CAPACITY: usize = 2;
+
+    enum Message {
+        Buffer(AudioBuffer<f32>, u64),
+        Flush,
+    }
+
+    pub struct ThreadedDisplay {
+        tx: SyncSender<Message>,
+        /// Buffers that have been displayed and may be reused by the writer.
+        spare_rx: Receiver<AudioBuffer<f32>>,
+        /// A buffer that was dropped from the queue, kept to be reused.
+        spare: Option<AudioBuffer<f32>>,
+        /// Buffers waiting for room in the channel, oldest first.
+        queue: VecDeque<(AudioBuffer<f32>, u64)>,
+        /// If true, the writer waits for the display thread instead of dropping buffers.
+        lossless: bool,
+        /// The number of audio frames dropped because the display thread fell behind.
+        dropped: u64,
+        handle: Option<JoinHandle<Result<()>>>,
+    }
+
+    impl ThreadedDisplay {
+        /// Runs `inner` on a new thread. If `lossless` is false, the oldest buffers are dropped
+        /// when the display falls behind. Otherwise, the writer waits once `QUEUE_CAPACITY`
+        /// buffers are queued, which bounds memory use but may stall playback, so it is only
+        /// suitable for displays that are much faster than real time.
+        pub fn try_open(inner: Box<dyn Display>, lossless: bool) -> Result<Box<dyn Display>> {
+            let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+            let (spare_tx, spare_rx) = mpsc::sync_channel(QUEUE_CAPACITY);
+
+            let handle = thread::Builder::new()
+                .name("display".to_string())
+                .spawn(move || run(inner, rx, spare_tx))
+                .map_err(|err| {
+                    DisplayError::OpenError(format!("failed to spawn display thread: {}", err))
+                })?;
+
+            Ok(Box::new(ThreadedDisplay {
+                tx,
+                spare_rx,
+                spare: None,
+                queue: VecDeque::with_capacity(QUEUE_CAPACITY + 1),
+                lossless,
+                dropped: 0,
+                handle: Some(handle),
+            }))
+        }
+
+        /// Gets a buffer to copy into, reusing a spare buffer if possible.
+        fn take_spare(&mut self, spec: SignalSpec, duration: Duration) -> AudioBuffer<f32> {
+            let spare = self.spare.take().or_else(|| self.spare_rx.try_recv().ok());
+
+            match spare {
+                Some(buf) if *buf.spec() == spec && buf.capacity() as u64 >= duration => buf,
+                _ => AudioBuffer::new(duration, spec),
+            }
+        }
+
+        /// Moves as many queued buffers to the display thread as the channel has room for.
+        fn send_queued(&mut self) {
+            while let Some((buf, ts)) = self.queue.pop_front() {
+                match self.tx.try_send(Message::Buffer(buf, ts)) {
+                    Ok(()) => (),
+                    Err(TrySendError::Full(msg)) => {
+                        if let Message::Buffer(buf, ts) = msg {
+                            self.queue.push_front((buf, ts));
+                        }
+                        break;
+                    }
+                    // The display thread has exited, its error is reported when it is flushed.
+                    Err(TrySendError::Disconnected(_)) => {
+                        self.queue.clear();
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The display thread. Buffers are written to the inner display until it is flushed.
+    fn run(
+        mut inner: Box<dyn Display>,
+        rx: Receiver<Message>,
+        spare_tx: SyncSender<AudioBuffer<f32>>,
+    ) -> Result<()> {
+        // If the writer is dropped without flushing, the display is still flushed.
+        while let Ok(Message::Buffer(buf, ts)) = rx.recv() {
+            if let Err(err) = inner.write(AudioBufferRef::F32(Cow::Borrowed(&buf)), ts) {
+                warn!("{}", err);
+            }
+
+            // If the writer already has enough spare buffers, this one is freed.
+            let _ = spare_tx.try_send(buf);
+        }
+
+        inner.flush()
+    }
+
+    impl Display for ThreadedDisplay {
+        fn write(&mut self, decoded: AudioBufferRef<'_>, ts: u64) -> Result<()> {
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            // The decoded buffer is borrowed from the decoder, so copy it to send it to the
+            // display thread.
+            let mut buf = self.take_spare(*decoded.spec(), decoded.capacity() as Duration);
+            decoded.convert(&mut buf);
+
+            self.queue.push_back((buf, ts));
+            self.send_queued();
+
+            if self.queue.len() > QUEUE_CAPACITY {
+                let (buf, ts) = self.queue.pop_front().unwrap();
+
+                if self.lossless {
+                    // Ignore a disconnected display thread, as in send_queued.
+                    let _ = self.tx.send(Message::Buffer(buf, ts));
+                }
+                else {
+                    self.dropped += buf.frames() as u64;
+                    self.spare = Some(buf);
+                }
+            }
+
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            let handle = match self.handle.take() {
+                Some(handle) => handle,
+                None => return Ok(()),
+            };
+
+            // Display everything still queued before flushing. Errors mean the display thread
+            // has already exited, which is reported by joining it.
+            for (buf, ts) in self.queue.drain(..) {
+                let _ = self.tx.send(Message::Buffer(buf, ts));
+            }
+
+            let _ = self.tx.send(Message::Flush);
+
+            let result = match handle.join() {
+                Ok(result) => result,
+                Err(_) => Err(DisplayError::WriteError("display thread panicked".to_string())),
+            };
+
+            if self.dropped > 0 {
+                warn!("display fell behind and dropped {} frames", self.dropped);
+            }
+
+            result
+        }
+    }
+
+    impl Drop for ThreadedDisplay {
+        fn drop(&mut self) {
+            if let Err(err) = self.flush() {
+                error!("{}", err);
+            }
+        }
+    }
+}
+
+/// Clears the terminal after a visualiser has been flushed, so that it can be hidden.
+pub fn clear() {
+    term::clear();
+}
+
+#[cfg(target_os = "linux")]
+pub fn try_open(
+    spec: SignalSpec,
+    duration: Duration,
+    tb: Option<TimeBase>,
+    opts: &DisplayOptions,
+    loudness: &LoudnessHistory,
+) -> Result<Box<dyn Display>> {
+    // Offline displays must see every buffer, so they run on the calling thread.
+    if let Some(image_opts) = &opts.image {
+        return image::ImageDisplay::try_open(spec, duration, &opts.stft, image_opts);
+    }
+
+    if let Some(export_opts) = &opts.export {
+        return export::ExportDisplay::try_open(spec, duration, tb, &opts.stft, export_opts);
+    }
+
+    let display = match opts.kind {
+        DisplayKind::Spectrogram => stft::STFTDisplay::try_open(spec, duration, &opts.stft)?,
+        DisplayKind::Waveform => waveform::WaveformDisplay::try_open(spec, duration)?,
+        DisplayKind::Meter => meter::MeterDisplay::try_open(spec, duration)?,
+        DisplayKind::Scope => scope::ScopeDisplay::try_open(spec, duration)?,
+        DisplayKind::Bars => bars::BarsDisplay::try_open(spec, duration, &opts.stft, opts.bands)?,
+        DisplayKind::Loudness => {
+            // Loudness is measured over the whole stream, so no buffers may be dropped. Its
+            // analysis is far faster than real time, so waiting on it does not stall playback.
+            let display = loudness::LoudnessDisplay::try_open(spec, duration, loudness)?;
+            return threaded::ThreadedDisplay::try_open(display, true);
+        }
+    };
+
+    // Analysis and rendering happen on their own thread so they can never stall the audio output.
+    threaded::ThreadedDisplay::try_open(display, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::VecDeque;
+    use std::f64::consts::PI;
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use rustfft::{num_complex::Complex, FftPlanner};
+    use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Channels, Signal, SignalSpec};
+
+    use super::image::{ImageDisplay, ImageOptions};
+    use super::loudness::LoudnessMeter;
+    use super::stft::{downmix, LogBins, Stft, StftOptions, WindowFunction};
+    use super::threaded::ThreadedDisplay;
+    use super::{Display, Result};
+    use crate::testutil::temp_path;
+
+    const RATE: f32 = 48000.0;
+
+    /// Generates `len` samples of a sinusoid of amplitude `amplitude` at `freq` Hz. The phase is
+    /// computed in double precision so that it does not add noise above the leakage being tested.
+    fn sine(freq: f32, amplitude: f32, len: usize) -> Vec<f32> {
+        let step = 2.0 * PI * f64::from(freq) / f64::from(RATE);
+
+        (0..len).map(|i| amplitude * (step * i as f64).sin() as f32).collect()
+    }
+
+    fn to_db(ratio: f32) -> f32 {
+        20.0 * ratio.max(1e-20).log10()
+    }
+
+    /// Analyses `samples` in one call, returning every frame's spectrum normalized so that a
+    /// full-scale sinusoid is 1.
+    fn analyse(opts: &StftOptions, samples: &[f32]) -> Vec<Vec<f32>> {
+        let mut stft = Stft::new(opts);
+        let reference = stft.reference();
+
+        stft.pending_mut().extend(samples);
+
+        stft.process()
+            .iter()
+            .map(|frame| frame.iter().map(|mag| mag / reference).collect())
+            .collect()
+    }
+
+    fn peak_bin(spectrum: &[f32]) -> usize {
+        (0..spectrum.len()).max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b])).unwrap()
+    }
+
+    /// Each window with its worst-case scalloping loss, highest sidelobe level, and main lobe
+    /// half-width in bins.
+    const WINDOWS: [(WindowFunction, f32, f32, f32); 5] = [
+        (WindowFunction::Rectangular, 3.93, -13.2, 1.0),
+        (WindowFunction::Hann, 1.43, -31.4, 2.0),
+        (WindowFunction::Hamming, 1.76, -42.6, 2.0),
+        (WindowFunction::BlackmanHarris, 0.83, -92.0, 4.0),
+        (WindowFunction::Kaiser { beta: 8.6 }, 1.15, -61.0, 3.0),
+    ];
+
+    fn options(window: WindowFunction) -> StftOptions {
+        StftOptions { window_size: 1024, hop_size: 512, window, zero_padding: 1 }
+    }
+
+    #[test]
+    fn stft_bin_centred_sine() {
+        // A sinusoid exactly on bin 100 must peak on that bin at its own amplitude.
+        let freq = 100.0 * RATE / 1024.0;
+        let samples = sine(freq, 0.5, 8192);
+
+        for &(window, ..) in &WINDOWS {
+            let frames = analyse(&options(window), &samples);
+
+            assert_eq!(frames.len(), (8192 - 1024) / 512 + 1);
+
+            for frame in &frames {
+                assert_eq!(frame.len(), 513);
+                assert_eq!(peak_bin(frame), 100, "{:?}", window);
+                assert!((frame[100] - 0.5).abs() < 1e-3, "{:?}: {}", window, frame[100]);
+            }
+        }
+    }
+
+    #[test]
+    fn stft_off_bin_sine_leakage() {
+        // Half-way between bins is the worst case for both scalloping and leakage.
+        let bin = 200.5;
+        let samples = sine(bin * RATE / 1024.0, 1.0, 4096);
+
+        for &(window, scalloping, sidelobe, main_lobe) in &WINDOWS {
+            for frame in analyse(&options(window), &samples) {
+                let peak = peak_bin(&frame);
+
+                assert!(peak == 200 || peak == 201, "{:?}: peak at {}", window, peak);
+
+                let level = to_db(frame[peak]);
+                assert!(level <= 0.05 && level >= -scalloping, "{:?}: {} dB", window, level);
+
+                for (i, &mag) in frame.iter().enumerate() {
+                    if (i as f32 - bin).abs() > main_lobe {
+                        let leakage = to_db(mag);
+                        assert!(leakage < sidelobe, "{:?}: bin {} at {} dB", window, i, leakage);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stft_zero_padding_interpolates() {
+        let samples = sine(1000.0, 1.0, 4096);
+
+        let opts = StftOptions { zero_padding: 4, ..options(WindowFunction::Hann) };
+        let mut stft = Stft::new(&opts);
+
+        assert_eq!(stft.fft_size(), 4096);
+        assert_eq!(stft.n_bins(), 2049);
+
+        stft.pending_mut().extend(&samples);
+
+        for frame in stft.process() {
+            // 1 kHz falls between bins 85 and 86 of the 4096-point FFT.
+            let peak = peak_bin(frame);
+            assert!(peak == 85 || peak == 86, "peak at {}", peak);
+        }
+    }
+
+    #[test]
+    fn stft_carries_samples_across_packets() {
+        let opts = options(WindowFunction::Hann);
+        let samples = sine(3000.0, 0.25, 10000);
+
+        let whole = analyse(&opts, &samples);
+
+        // Feed the same samples in packets that do not line up with the window or the hop.
+        let mut stft = Stft::new(&opts);
+        let reference = stft.reference();
+        let mut frames = Vec::new();
+
+        for packet in samples.chunks(333) {
+            stft.pending_mut().extend(packet);
+
+            for frame in stft.process() {
+                frames.push(frame.iter().map(|mag| mag / reference).collect::<Vec<_>>());
+            }
+        }
+
+        assert_eq!(frames.len(), whole.len());
+
+        for (a, b) in frames.iter().zip(&whole) {
+            assert!(a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-6));
+        }
+    }
+
+    #[test]
+    fn stft_downmix_averages_each_frame() {
+        let mut out = VecDeque::new();
+
+        downmix(&[1.0, 0.0, 0.5, 0.5, -1.0, 1.0], 2, &mut out);
+        assert_eq!(out, [0.5, 0.5, 0.0]);
+
+        out.clear();
+        downmix(&[0.3, 0.6, 0.9], 3, &mut out);
+        assert_eq!(out.len(), 1);
+        assert!((out[0] - 0.6).abs() < 1e-6);
+
+        // Out-of-phase channels cancel.
+        let left = sine(440.0, 1.0, 256);
+        let interleaved: Vec<f32> = left.iter().flat_map(|&s| [s, -s]).collect();
+
+        out.clear();
+        downmix(&interleaved, 2, &mut out);
+        assert_eq!(out.len(), 256);
+        assert!(out.iter().all(|&s| s == 0.0));
+    }
+
+    /// The STFT as it was before the plan and buffers were reused: a new FFT is planned for every
+    /// packet, and every frame allocates its input and spectrum.
+    fn stft_unplanned(pending: &mut VecDeque<f32>, window_size: usize, hop_size: usize) -> usize {
+        let fft_size = window_size.next_power_of_two();
+        let fft = FftPlanner::new().plan_fft_forward(fft_size);
+
+        let hann = |j: usize| {
+            0.5 * (1.0 - (2.0 * std::f32::consts::PI * j as f32 / window_size as f32).cos())
+        };
+
+        let mut result: Vec<Vec<f32>> = Vec::new();
+
+        while pending.len() >= window_size {
+            let mut input: Vec<_> = pending
+                .iter()
+                .take(window_size)
+                .enumerate()
+                .map(|(j, &value)| value * hann(j))
+                .chain(std::iter::repeat(0.0).take(fft_size - window_size))
+                .map(|value| Complex::new(value, 0.0))
+                .collect();
+
+            fft.process(&mut input);
+
+            result.push(input.iter().map(|c| c.norm()).collect());
+
+            pending.drain(..hop_size.min(pending.len()));
+        }
+
+        result.len()
+    }
+
+    /// Compares the throughput of the reused STFT against planning and allocating per packet.
+    /// Run with `cargo test --release -- --ignored --nocapture stft_throughput`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn stft_throughput() {
+        const PACKETS: usize = 20000;
+        const PACKET_LEN: usize = 1152;
+
+        let opts = StftOptions::default();
+        let packet = sine(1000.0, 0.5, PACKET_LEN);
+
+        let start = Instant::now();
+        let mut pending = VecDeque::new();
+        let mut before_frames = 0;
+
+        for _ in 0..PACKETS {
+            pending.extend(&packet);
+            before_frames += stft_unplanned(&mut pending, opts.window_size, opts.hop_size);
+        }
+
+        let before = start.elapsed();
+
+        let start = Instant::now();
+        let mut stft = Stft::new(&opts);
+        let mut after_frames = 0;
+
+        for _ in 0..PACKETS {
+            stft.pending_mut().extend(&packet);
+            after_frames += stft.process().len();
+        }
+
+        let after = start.elapsed();
+
+        assert_eq!(before_frames, after_frames);
+
+        let audio = (PACKETS * PACKET_LEN) as f64 / 44100.0;
+
+        println!(
+            "{} packets of {} samples: {:.2?} ({:.0}x realtime) before, {:.2?} ({:.0}x) after",
+            PACKETS,
+            PACKET_LEN,
+            before,
+            audio / before.as_secs_f64(),
+            after,
+            audio / after.as_secs_f64(),
+        );
+
+        assert!(after < before);
+    }
+
+    /// Records the timestamps it is written, after waiting for `gate` to open on the first write.
+    struct SlowDisplay {
+        seen: Arc<Mutex<Vec<u64>>>,
+        gate: Option<mpsc::Receiver<()>>,
+    }
+
+    impl Display for SlowDisplay {
+        fn write(&mut self, _: AudioBufferRef<'_>, ts: u64) -> Result<()> {
+            if let Some(gate) = self.gate.take() {
+                gate.recv().unwrap();
+            }
+            self.seen.lock().unwrap().push(ts);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Writes 100 buffers to a threaded display that is stalled until they have all been written,
+    /// and returns the timestamps it displayed.
+    fn write_stalled(lossless: bool) -> Vec<u64> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (open, gate) = mpsc::channel();
+
+        let inner = SlowDisplay { seen: Arc::clone(&seen), gate: Some(gate) };
+        let mut display = ThreadedDisplay::try_open(Box::new(inner), lossless).unwrap();
+
+        let spec = SignalSpec::new(48000, Channels::FRONT_LEFT);
+        let mut buf = AudioBuffer::<f32>::new(64, spec);
+        buf.render_reserved(Some(64));
+
+        // A lossless writer blocks once the queue is full, so open the gate from another thread.
+        let open = if lossless {
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                open.send(()).unwrap();
+            });
+            None
+        }
+        else {
+            Some(open)
+        };
+
+        for ts in 0..100 {
+            display.write(AudioBufferRef::F32(Cow::Borrowed(&buf)), ts).unwrap();
+        }
+
+        if let Some(open) = open {
+            open.send(()).unwrap();
+        }
+
+        display.flush().unwrap();
+
+        let seen = seen.lock().unwrap().clone();
+        seen
+    }
+
+    #[test]
+    fn threaded_drops_oldest_when_behind() {
+        let seen = write_stalled(false);
+
+        // Only the buffers already in flight and the newest are displayed, in order.
+        assert!(seen.len() < 100);
+        assert!(seen.windows(2).all(|w| w[0] < w[1]));
+        assert!(seen.ends_with(&(68..100).collect::<Vec<_>>()), "{:?}", seen);
+    }
+
+    #[test]
+    fn threaded_lossless_sees_every_buffer() {
+        assert_eq!(write_stalled(true), (0..100).collect::<Vec<_>>());
+    }
+
+    /// Writes mono `samples` to `display` in packets of 1152 frames, and flushes it.
+    fn write_packets(display: &mut dyn Display, samples: &[f32]) {
+        let spec = SignalSpec::new(RATE as u32, Channels::FRONT_LEFT);
+
+        for (i, packet) in samples.chunks(1152).enumerate() {
+            let mut buf = AudioBuffer::<f32>::new(1152, spec);
+            buf.render_reserved(Some(packet.len()));
+            buf.chan_mut(0).copy_from_slice(packet);
+
+            display.write(AudioBufferRef::F32(Cow::Borrowed(&buf)), 1152 * i as u64).unwrap();
+        }
+
+        display.flush().unwrap();
+    }
+
+    /// Renders the spectrogram of `samples` to a PNG file, and returns its contents.
+    fn render_png(name: &str, samples: &[f32], width: usize, height: usize) -> Vec<u8> {
+        let path = temp_path(&format!("{}.png", name));
+
+        let opts = ImageOptions { path: path.clone(), width, height, labels: true };
+        let spec = SignalSpec::new(RATE as u32, Channels::FRONT_LEFT);
+
+        let mut display =
+            ImageDisplay::try_open(spec, 1152, &StftOptions::default(), &opts).unwrap();
+        write_packets(display.as_mut(), samples);
+
+        let png = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        png
+    }
+
+    #[test]
+    fn image_is_deterministic() {
+        let samples = sine(1000.0, 0.5, 3 * 48000);
+
+        let png = render_png("a", &samples, 400, 200);
+        assert_eq!(png, render_png("b", &samples, 400, 200));
+
+        let decoder = png::Decoder::new(png.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+
+        assert_eq!((info.width, info.height), (400, 200));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+
+        // The brightest row of every column of the plot is the row of the sinusoid. The plot is
+        // to the right of a 28 pixel margin, and above a 16 pixel margin.
+        let plot_height = 200 - 16;
+        let bins = LogBins::new(plot_height, 256, RATE as u32);
+        let pos = bins.position(1000.0).unwrap();
+        let expected = plot_height - 1 - (pos * (plot_height - 1) as f32) as usize;
+
+        for x in (28..400).step_by(37) {
+            let brightness = |y: usize| {
+                let i = 3 * (y * 400 + x);
+                data[i..i + 3].iter().map(|&c| u32::from(c)).sum::<u32>()
+            };
+
+            let peak = (0..plot_height).max_by_key(|&y| brightness(y)).unwrap();
+            assert!(peak.abs_diff(expected) <= 2, "column {}: peak at {}", x, peak);
+        }
+    }
+
+    #[test]
+    fn image_small_sizes_skip_labels() {
+        let samples = sine(1000.0, 0.5, 48000);
+
+        for &(width, height) in &[(1, 1), (20, 8), (8, 40), (40, 20), (29, 17)] {
+            let png = render_png(&format!("{}x{}", width, height), &samples, width, height);
+            assert!(!png.is_empty());
+        }
+    }
+
+    const STEREO: Channels = Channels::FRONT_LEFT.union(Channels::FRONT_RIGHT);
+
+    /// Measures consecutive segments of a sinusoid at `freq` Hz, each lasting the given number of
+    /// seconds with the given level in dBFS on each channel, at 48 kHz. The sinusoid is faded in
+    /// over 10 ms, so that the response of the true-peak interpolator to an abrupt start does not
+    /// add to the peak.
+    fn measure(
+        channels: Channels,
+        freq: f64,
+        phase: f64,
+        segments: &[(f64, &[f64])],
+    ) -> LoudnessMeter {
+        let mut meter = LoudnessMeter::new(SignalSpec::new(48000, channels));
+
+        let step = 2.0 * PI * freq / 48000.0;
+        let mut n = 0u64;
+
+        for &(secs, levels) in segments {
+            assert_eq!(levels.len(), channels.count());
+
+            let amplitudes: Vec<f64> = levels.iter().map(|db| 10f64.powf(db / 20.0)).collect();
+            let frames = (secs * 48000.0).round() as u64;
+
+            let mut interleaved = Vec::with_capacity(4800 * amplitudes.len());
+
+            for _ in 0..frames {
+                let fade = (n as f64 / 480.0).min(1.0);
+                let value = fade * (step * n as f64 + phase).sin();
+                interleaved.extend(amplitudes.iter().map(|a| (a * value) as f32));
+                n += 1;
+
+                if interleaved.len() == interleaved.capacity() {
+                    meter.process(&interleaved);
+                    interleaved.clear();
+                }
+            }
+
+            meter.process(&interleaved);
+        }
+
+        meter
+    }
+
+    fn assert_near(value: Option<f64>, expected: f64, tolerance: f64) {
+        let value = value.expect("no measurement");
+        assert!((value - expected).abs() <= tolerance, "{} is not {}", value, expected);
+    }
+
+    /// EBU Tech 3341 minimum requirements test signals 1 to 5: stereo 1 kHz sinusoids.
+    #[test]
+    fn loudness_tech_3341_stereo() {
+        let meter = measure(STEREO, 1000.0, 0.0, &[(20.0, &[-23.0, -23.0])]);
+        assert_near(meter.momentary(), -23.0, 0.1);
+        assert_near(meter.short_term(), -23.0, 0.1);
+        assert_near(meter.integrated(), -23.0, 0.1);
+
+        let meter = measure(STEREO, 1000.0, 0.0, &[(20.0, &[-33.0, -33.0])]);
+        assert_near(meter.momentary(), -33.0, 0.1);
+        assert_near(meter.short_term(), -33.0, 0.1);
+        assert_near(meter.integrated(), -33.0, 0.1);
+
+        // The quiet parts are below the relative gate.
+        let meter = measure(
+            STEREO,
+            1000.0,
+            0.0,
+            &[(10.0, &[-36.0, -36.0]), (60.0, &[-23.0, -23.0]), (10.0, &[-36.0, -36.0])],
+        );
+        assert_near(meter.integrated(), -23.0, 0.1);
+
+        // The silent parts are below the absolute gate.
+        let meter = measure(
+            STEREO,
+            1000.0,
+            0.0,
+            &[
+                (10.0, &[-72.0, -72.0]),
+                (10.0, &[-36.0, -36.0]),
+                (60.0, &[-23.0, -23.0]),
+                (10.0, &[-36.0, -36.0]),
+                (10.0, &[-72.0, -72.0]),
+            ],
+        );
+        assert_near(meter.integrated(), -23.0, 0.1);
+
+        let meter = measure(
+            STEREO,
+            1000.0,
+            0.0,
+            &[(20.0, &[-26.0, -26.0]), (20.1, &[-20.0, -20.0]), (20.0, &[-26.0, -26.0])],
+        );
+        assert_near(meter.integrated(), -23.0, 0.1);
+    }
+
+    /// EBU Tech 3341 test signal 6: a 5 channel 1 kHz sinusoid, with the surround channels
+    /// weighted.
+    #[test]
+    fn loudness_tech_3341_surround() {
+        let channels = Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::REAR_LEFT
+            | Channels::REAR_RIGHT;
+
+        let levels = [-28.0, -28.0, -24.0, -30.0, -30.0];
+
+        let meter = measure(channels, 1000.0, 0.0, &[(20.0, &levels)]);
+        assert_near(meter.integrated(), -23.0, 0.1);
+    }
+
+    /// EBU Tech 3342 loudness range test signals 1 to 4.
+    #[test]
+    fn loudness_tech_3342_range() {
+        let cases: [(&[f64], f64); 4] = [
+            (&[-20.0, -30.0], 10.0),
+            (&[-20.0, -15.0], 5.0),
+            (&[-40.0, -20.0], 20.0),
+            (&[-50.0, -35.0, -20.0, -35.0, -50.0], 15.0),
+        ];
+
+        for (levels, expected) in cases {
+            let levels: Vec<[f64; 2]> = levels.iter().map(|&db| [db, db]).collect();
+            let segments: Vec<(f64, &[f64])> = levels.iter().map(|db| (20.0, &db[..])).collect();
+
+            let meter = measure(STEREO, 1000.0, 0.0, &segments);
+            assert_near(meter.range(), expected, 1.0);
+        }
+    }
+
+    /// EBU Tech 3341 true-peak test signals 15 to 19: sinusoids at a quarter of the sample rate
+    /// with peaks between the samples. The specified tolerance is +0.2/-0.4 dB, but the BS.1770-4
+    /// interpolator has a gain of +0.22 dB at a quarter of the sample rate, so up to that much is
+    /// allowed above.
+    #[test]
+    fn loudness_tech_3341_true_peak() {
+        // The phase in degrees, the level of the sinusoid, and its true peak.
+        let cases: [(f64, f64, f64); 5] = [
+            (0.0, -6.0, -6.0),
+            (45.0, -6.0, -6.0),
+            (60.0, -6.0, -6.0),
+            (67.5, -6.0, -6.0),
+            (45.0, 3.0, 3.0),
+        ];
+
+        for (phase, level, expected) in cases {
+            let meter = measure(STEREO, 12000.0, phase.to_radians(), &[(1.0, &[level, level])]);
+
+            let true_peak = meter.true_peak();
+            assert!(
+                true_peak >= expected - 0.4 && true_peak <= expected + 0.23,
+                "{} degrees: {} dBTP",
+                phase,
+                true_peak
+            );
+        }
+    }
+}
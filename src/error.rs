@@ -0,0 +1,76 @@
+// Symphonia
+// Copyright (c) 2019-2022 The Project Symphonia Developers.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Player Errors
+
+use std::fmt;
+use std::result;
+
+use symphonia::core::errors::Error;
+
+use crate::display::DisplayError;
+use crate::output::AudioOutputError;
+
+/// An error that stops playback.
+#[derive(Debug)]
+pub enum PlayerError {
+    /// An input could not be read or decoded.
+    Symphonia(Error),
+    /// The audio output could not be opened, or could not be reopened after it failed.
+    Output(AudioOutputError),
+    /// An offline display could not write its output file. Errors of the visualisers shown
+    /// during playback are only warnings.
+    Display(DisplayError),
+}
+
+impl PlayerError {
+    /// Gets the process exit code for the error. The exit codes are:
+    ///
+    /// * 0 if all inputs were played,
+    /// * 1 if the decoded audio failed verification,
+    /// * 2 if the command line was invalid,
+    /// * 3 if an input could not be read or decoded,
+    /// * 4 if the audio output failed,
+    /// * 5 if a spectrogram or STFT could not be written.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PlayerError::Symphonia(_) => 3,
+            PlayerError::Output(_) => 4,
+            PlayerError::Display(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayerError::Symphonia(err) => write!(f, "{}", err.to_string().to_lowercase()),
+            PlayerError::Output(err) => write!(f, "audio output: {}", err),
+            PlayerError::Display(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<Error> for PlayerError {
+    fn from(err: Error) -> Self {
+        PlayerError::Symphonia(err)
+    }
+}
+
+impl From<AudioOutputError> for PlayerError {
+    fn from(err: AudioOutputError) -> Self {
+        PlayerError::Output(err)
+    }
+}
+
+impl From<DisplayError> for PlayerError {
+    fn from(err: DisplayError) -> Self {
+        PlayerError::Display(err)
+    }
+}
+
+pub type Result<T> = result::Result<T, PlayerError>;
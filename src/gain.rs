@@ -0,0 +1,214 @@
+// Symphonia
+// Copyright (c) 2019-2022 The Project Symphonia Developers.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Gain Stage
+
+use symphonia::core::meta::{StandardTagKey, Tag};
+
+/// The lowest user volume in decibels.
+pub const MIN_VOLUME: f64 = -60.0;
+
+/// The highest user volume in decibels.
+pub const MAX_VOLUME: f64 = 0.0;
+
+/// The ReplayGain values that are applied.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReplayGainMode {
+    /// The gain of each track, which evens out the loudness between tracks.
+    Track,
+    /// The gain of each album, which keeps the loudness differences between tracks of an album.
+    /// Falls back to the track gain if there is no album gain.
+    Album,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GainOptions {
+    /// The initial user volume in decibels.
+    pub volume: f64,
+    /// If set, the ReplayGain of each input is applied.
+    pub replaygain: Option<ReplayGainMode>,
+    /// The gain added to the ReplayGain of each input, in decibels.
+    pub preamp: f64,
+    /// The gain applied to inputs without ReplayGain tags, in decibels.
+    pub fallback: f64,
+    /// If true, the ReplayGain is reduced so that the stored peak does not clip.
+    pub clip_prevention: bool,
+}
+
+/// The ReplayGain values read from the tags of an input. Gains are in decibels, and peaks are
+/// linear with 1.0 being full scale.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ReplayGain {
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+impl ReplayGain {
+    /// Reads the ReplayGain values from a set of tags. R128 gains, as used by Opus, are converted
+    /// to the ReplayGain reference level of -18 LUFS.
+    pub fn read_tags(&mut self, tags: &[Tag]) {
+        for tag in tags {
+            let value = tag.value.to_string();
+
+            match tag.std_key {
+                Some(StandardTagKey::ReplayGainTrackGain) => self.track_gain = parse_db(&value),
+                Some(StandardTagKey::ReplayGainTrackPeak) => self.track_peak = parse_peak(&value),
+                Some(StandardTagKey::ReplayGainAlbumGain) => self.album_gain = parse_db(&value),
+                Some(StandardTagKey::ReplayGainAlbumPeak) => self.album_peak = parse_peak(&value),
+                _ if tag.key.eq_ignore_ascii_case("R128_TRACK_GAIN") => {
+                    self.track_gain = parse_r128(&value)
+                }
+                _ if tag.key.eq_ignore_ascii_case("R128_ALBUM_GAIN") => {
+                    self.album_gain = parse_r128(&value)
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Gets the gain, in decibels, that is applied to the input.
+    pub fn gain(&self, opts: &GainOptions) -> f64 {
+        let (gain, peak) = match opts.replaygain {
+            Some(ReplayGainMode::Album) if self.album_gain.is_some() => {
+                (self.album_gain, self.album_peak)
+            }
+            Some(_) => (self.track_gain, self.track_peak),
+            None => return 0.0,
+        };
+
+        let gain = match gain {
+            Some(gain) => gain + opts.preamp,
+            None => return opts.fallback,
+        };
+
+        match peak {
+            // Limit the gain so that the peak is not amplified above full scale.
+            Some(peak) if opts.clip_prevention && peak > 0.0 => gain.min(-20.0 * peak.log10()),
+            _ => gain,
+        }
+    }
+}
+
+/// Converts a gain in decibels to a linear factor.
+pub fn db_to_linear(db: f64) -> f32 {
+    10f64.powf(db / 20.0) as f32
+}
+
+/// Parses a gain such as "-6.54 dB".
+fn parse_db(value: &str) -> Option<f64> {
+    // Strip the unit.
+    let value = value.trim_end_matches(|c: char| c.is_ascii_alphabetic() || c.is_whitespace());
+
+    value.trim_start().parse().ok()
+}
+
+/// Parses a peak such as "0.988547".
+fn parse_peak(value: &str) -> Option<f64> {
+    value.trim().parse().ok()
+}
+
+/// Parses an R128 gain, which is a Q7.8 fixed-point number relative to -23 LUFS.
+fn parse_r128(value: &str) -> Option<f64> {
+    value.trim().parse::<i32>().ok().map(|gain| f64::from(gain) / 256.0 + 5.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use symphonia::core::meta::Value;
+
+    fn opts(replaygain: ReplayGainMode, preamp: f64, clip_prevention: bool) -> GainOptions {
+        let replaygain = Some(replaygain);
+        GainOptions { replaygain, preamp, fallback: -3.0, clip_prevention, ..Default::default() }
+    }
+
+    fn tag(std_key: Option<StandardTagKey>, key: &str, value: &str) -> Tag {
+        Tag::new(std_key, key, Value::String(value.to_string()))
+    }
+
+    #[test]
+    fn parse_db_strips_unit() {
+        assert_eq!(parse_db("-6.54 dB"), Some(-6.54));
+        assert_eq!(parse_db("+1.5dB"), Some(1.5));
+        assert_eq!(parse_db(" 3 DB "), Some(3.0));
+        assert_eq!(parse_db("-0.25"), Some(-0.25));
+        assert_eq!(parse_db("dB"), None);
+        assert_eq!(parse_db("loud"), None);
+    }
+
+    #[test]
+    fn parse_r128_converts_to_replaygain_reference() {
+        // R128 gains are Q7.8 relative to -23 LUFS, and ReplayGain is relative to -18 LUFS.
+        assert_eq!(parse_r128("0"), Some(5.0));
+        assert_eq!(parse_r128("-1280"), Some(0.0));
+        assert_eq!(parse_r128(" 384 "), Some(6.5));
+        assert_eq!(parse_r128("-32768"), Some(-123.0));
+        assert_eq!(parse_r128("-5.0"), None);
+        assert_eq!(parse_r128(""), None);
+    }
+
+    #[test]
+    fn read_tags_and_r128() {
+        let mut rg = ReplayGain::default();
+        rg.read_tags(&[
+            tag(Some(StandardTagKey::ReplayGainTrackGain), "REPLAYGAIN_TRACK_GAIN", "-7.2 dB"),
+            tag(Some(StandardTagKey::ReplayGainTrackPeak), "REPLAYGAIN_TRACK_PEAK", "0.95"),
+            tag(None, "r128_album_gain", "-512"),
+            tag(None, "COMMENT", "-1 dB"),
+        ]);
+
+        assert_eq!(rg.track_gain, Some(-7.2));
+        assert_eq!(rg.track_peak, Some(0.95));
+        assert_eq!(rg.album_gain, Some(3.0));
+        assert_eq!(rg.album_peak, None);
+    }
+
+    #[test]
+    fn gain_falls_back_to_track() {
+        let track =
+            ReplayGain { track_gain: Some(-6.0), track_peak: Some(0.5), ..Default::default() };
+        let album = ReplayGain { album_gain: Some(-4.0), album_peak: Some(0.5), ..track };
+
+        assert_eq!(track.gain(&opts(ReplayGainMode::Track, 0.0, false)), -6.0);
+        assert_eq!(album.gain(&opts(ReplayGainMode::Track, 0.0, false)), -6.0);
+        assert_eq!(album.gain(&opts(ReplayGainMode::Album, 0.0, false)), -4.0);
+
+        // Without an album gain, the track gain is used.
+        assert_eq!(track.gain(&opts(ReplayGainMode::Album, 1.5, false)), -4.5);
+
+        // Without any gain, the fallback is used, and without ReplayGain nothing is applied.
+        let none = ReplayGain::default();
+        assert_eq!(none.gain(&opts(ReplayGainMode::Album, 1.5, false)), -3.0);
+        assert_eq!(album.gain(&GainOptions::default()), 0.0);
+    }
+
+    #[test]
+    fn gain_prevents_clipping() {
+        // A peak of 0.5 may be amplified by up to 6.02 dB.
+        let rg = ReplayGain { track_gain: Some(4.0), track_peak: Some(0.5), ..Default::default() };
+        let limit = -20.0 * 0.5f64.log10();
+
+        assert_eq!(rg.gain(&opts(ReplayGainMode::Track, 0.0, true)), 4.0);
+        assert_eq!(rg.gain(&opts(ReplayGainMode::Track, 4.0, true)), limit);
+        assert_eq!(rg.gain(&opts(ReplayGainMode::Track, 4.0, false)), 8.0);
+
+        // The album peak limits the album gain.
+        let rg = ReplayGain { album_gain: Some(15.0), album_peak: Some(0.25), ..rg };
+        assert_eq!(rg.gain(&opts(ReplayGainMode::Album, 0.0, true)), -20.0 * 0.25f64.log10());
+
+        // A peak above full scale is attenuated to full scale, and a zero peak does not limit the
+        // gain.
+        let rg = ReplayGain { track_gain: Some(-2.0), track_peak: Some(1.5), ..Default::default() };
+        assert_eq!(rg.gain(&opts(ReplayGainMode::Track, 0.0, true)), -20.0 * 1.5f64.log10());
+
+        let rg = ReplayGain { track_gain: Some(12.0), track_peak: Some(0.0), ..Default::default() };
+        assert_eq!(rg.gain(&opts(ReplayGainMode::Track, 0.0, true)), 12.0);
+    }
+}
@@ -0,0 +1,359 @@
+// Symphonia
+// Copyright (c) 2019-2022 The Project Symphonia Developers.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Repeat and A-B Loop Playback
+
+use std::f32::consts::PI;
+
+use symphonia::core::audio::{AudioBuffer, Signal};
+
+/// The length of the crossfade at the loop point, in seconds.
+const CROSSFADE: f64 = 0.01;
+
+/// How many more times an input is played after it ends.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Repeat {
+    /// The input is played a number of more times.
+    Count(u32),
+    /// The input is repeated until playback is stopped.
+    Forever,
+}
+
+impl Default for Repeat {
+    fn default() -> Self {
+        Repeat::Count(0)
+    }
+}
+
+impl Repeat {
+    /// Takes one repetition. Returns false if there are none left.
+    pub fn take(&mut self) -> bool {
+        match self {
+            Repeat::Count(0) => false,
+            Repeat::Count(n) => {
+                *n -= 1;
+                true
+            }
+            Repeat::Forever => true,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LoopOptions {
+    /// The number of times each input is repeated.
+    pub repeat: Repeat,
+    /// The start and end times, in seconds, of a region that is looped.
+    pub region: Option<(f64, f64)>,
+}
+
+/// Loops a region of a track by splicing the end of the region onto its start.
+///
+/// The last few milliseconds before the end of the region are held back. Once the reader has been
+/// seeked back to the start of the region, they are crossfaded with the first frames after the
+/// start so that the loop point does not click.
+pub struct AbLoop {
+    /// The frame at the end of the region, counted from the start of the track.
+    end: u64,
+    /// The number of frames crossfaded.
+    fade_len: usize,
+    /// The frames held back from before the end of the region, for each channel.
+    tail: Vec<Vec<f32>>,
+    /// The position in the crossfade while the held back frames are being mixed in.
+    fade_pos: Option<usize>,
+}
+
+impl AbLoop {
+    /// Creates a loop of the region between `start` and `end` seconds from the start of a track
+    /// with the given sample rate and number of channels. Returns `None` if the region is empty.
+    pub fn new(start: f64, end: f64, rate: u32, channels: usize) -> Option<Self> {
+        let start_frame = (start.max(0.0) * f64::from(rate)).round() as u64;
+        let end_frame = (end.max(0.0) * f64::from(rate)).round() as u64;
+
+        if end_frame <= start_frame || channels == 0 {
+            return None;
+        }
+
+        // The fade in after the start must not overlap the frames held back before the end.
+        let fade_len = ((CROSSFADE * f64::from(rate)) as u64).min((end_frame - start_frame) / 2);
+
+        Some(AbLoop {
+            end: end_frame,
+            fade_len: fade_len as usize,
+            tail: vec![Vec::new(); channels],
+            fade_pos: None,
+        })
+    }
+
+    /// Processes a buffer whose first frame is `frame`, counted from the start of the track.
+    /// Returns true if the buffer reaches the end of the region, in which case the reader should be
+    /// seeked back to its start and `restart` called.
+    ///
+    /// Frames after the end of the region are discarded, and the frames held back for the
+    /// crossfade are removed from the buffer.
+    pub fn process(&mut self, buf: &mut AudioBuffer<f32>, frame: u64) -> bool {
+        let n_frames = buf.frames();
+
+        // Mix the held back frames into the frames after the start of the region.
+        if let Some(pos) = self.fade_pos {
+            let tail_len = self.tail[0].len();
+            let n = n_frames.min(tail_len - pos);
+
+            for (c, tail) in self.tail.iter().enumerate() {
+                let chan = buf.chan_mut(c);
+
+                for i in 0..n {
+                    // A raised-cosine fade keeps the sum of the gains constant.
+                    let x = (pos + i) as f32 + 0.5;
+                    let gain = 0.5 - 0.5 * (PI * x / tail_len as f32).cos();
+
+                    chan[i] = tail[pos + i] * (1.0 - gain) + chan[i] * gain;
+                }
+            }
+
+            if pos + n >= tail_len {
+                self.fade_pos = None;
+                self.tail.iter_mut().for_each(Vec::clear);
+            }
+            else {
+                self.fade_pos = Some(pos + n);
+            }
+        }
+
+        // Nothing is done once playback has moved past the end of the region.
+        if frame >= self.end {
+            return false;
+        }
+
+        // Hold back the frames before the end of the region that are crossfaded.
+        let fade_start = self.end - self.fade_len as u64;
+
+        if frame + n_frames as u64 > fade_start {
+            let begin = fade_start.saturating_sub(frame) as usize;
+            let end = (self.end - frame).min(n_frames as u64) as usize;
+
+            for (c, tail) in self.tail.iter_mut().enumerate() {
+                tail.extend_from_slice(&buf.chan(c)[begin..end]);
+            }
+
+            buf.truncate(begin);
+        }
+
+        frame + n_frames as u64 >= self.end
+    }
+
+    /// Starts the crossfade after the reader has been seeked back to the start of the region.
+    pub fn restart(&mut self) {
+        if !self.tail[0].is_empty() {
+            self.fade_pos = Some(0);
+        }
+    }
+
+    /// Drops the held back frames if the reader could not be seeked back.
+    pub fn cancel(&mut self) {
+        self.fade_pos = None;
+        self.tail.iter_mut().for_each(Vec::clear);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use symphonia::core::audio::{Channels, SignalSpec};
+
+    const RATE: u32 = 1000;
+
+    /// Creates a stereo buffer of the given frames. The samples of the left channel are the frame
+    /// numbers, and those of the right channel their negation.
+    fn frames(first: u64, last: u64) -> AudioBuffer<f32> {
+        let spec = SignalSpec::new(RATE, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut buf = AudioBuffer::new(last - first, spec);
+
+        buf.render_reserved(Some((last - first) as usize));
+
+        for (i, frame) in (first..last).enumerate() {
+            buf.chan_mut(0)[i] = frame as f32;
+            buf.chan_mut(1)[i] = -(frame as f32);
+        }
+
+        buf
+    }
+
+    /// Creates a stereo buffer of `len` frames whose samples are all `value`.
+    fn constant(len: usize, value: f32) -> AudioBuffer<f32> {
+        let spec = SignalSpec::new(RATE, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut buf = AudioBuffer::new(len as u64, spec);
+
+        buf.render_reserved(Some(len));
+        buf.chan_mut(0).fill(value);
+        buf.chan_mut(1).fill(value);
+
+        buf
+    }
+
+    /// Creates a loop of the frames from 100 to 500, which crossfades 10 frames.
+    fn region() -> AbLoop {
+        let ab_loop = AbLoop::new(0.1, 0.5, RATE, 2).unwrap();
+        assert_eq!((ab_loop.end, ab_loop.fade_len), (500, 10));
+        ab_loop
+    }
+
+    /// Holds back the 10 frames before the end of the region, all set to `value`, and restarts
+    /// the loop.
+    fn restarted(value: f32) -> AbLoop {
+        let mut ab_loop = region();
+
+        assert!(ab_loop.process(&mut constant(20, value), 480));
+
+        ab_loop.restart();
+        ab_loop
+    }
+
+    #[test]
+    fn repeat_take_counts_down() {
+        let mut repeat = Repeat::Count(0);
+        assert!(!repeat.take());
+        assert_eq!(repeat, Repeat::Count(0));
+
+        let mut repeat = Repeat::Count(2);
+        assert!(repeat.take());
+        assert!(repeat.take());
+        assert!(!repeat.take());
+        assert_eq!(repeat, Repeat::Count(0));
+
+        let mut repeat = Repeat::Forever;
+        assert!((0..1000).all(|_| repeat.take()));
+        assert_eq!(repeat, Repeat::Forever);
+    }
+
+    #[test]
+    fn new_rejects_empty_regions() {
+        assert!(AbLoop::new(0.5, 0.5, RATE, 2).is_none());
+        assert!(AbLoop::new(0.5, 0.1, RATE, 2).is_none());
+        assert!(AbLoop::new(-1.0, 0.0, RATE, 2).is_none());
+        assert!(AbLoop::new(0.1, 0.5, RATE, 0).is_none());
+
+        // The crossfade of a short region is at most half of the region.
+        let ab_loop = AbLoop::new(0.1, 0.104, RATE, 1).unwrap();
+        assert_eq!((ab_loop.end, ab_loop.fade_len), (104, 2));
+    }
+
+    #[test]
+    fn process_holds_back_fade_and_truncates_at_end() {
+        let mut ab_loop = region();
+
+        // The region ends inside the buffer. The frames before the crossfade are played, and the
+        // frames from the end of the region are discarded.
+        let mut buf = frames(480, 530);
+
+        assert!(ab_loop.process(&mut buf, 480));
+        assert_eq!(buf.chan(0), frames(480, 490).chan(0));
+        assert_eq!(buf.chan(1), frames(480, 490).chan(1));
+
+        let tail = frames(490, 500);
+        assert_eq!(ab_loop.tail, [tail.chan(0), tail.chan(1)]);
+    }
+
+    #[test]
+    fn process_holds_back_fade_across_buffers() {
+        let mut ab_loop = region();
+
+        // A buffer before the crossfade is left alone.
+        let mut buf = frames(0, 485);
+        assert!(!ab_loop.process(&mut buf, 0));
+        assert_eq!(buf.frames(), 485);
+        assert!(ab_loop.tail[0].is_empty());
+
+        // A buffer that starts the crossfade, but does not reach the end of the region.
+        let mut buf = frames(485, 495);
+        assert!(!ab_loop.process(&mut buf, 485));
+        assert_eq!(buf.chan(0), frames(485, 490).chan(0));
+
+        // The region ends exactly at the end of the buffer.
+        let mut buf = frames(495, 500);
+        assert!(ab_loop.process(&mut buf, 495));
+        assert_eq!(buf.frames(), 0);
+
+        let tail = frames(490, 500);
+        assert_eq!(ab_loop.tail, [tail.chan(0), tail.chan(1)]);
+
+        // Buffers after the end of the region are left alone.
+        let mut buf = frames(500, 600);
+        assert!(!ab_loop.process(&mut buf, 500));
+        assert_eq!(buf.frames(), 100);
+    }
+
+    #[test]
+    fn restart_crossfade_gains_sum_to_one() {
+        // Crossfading a constant into itself leaves it unchanged.
+        let mut ab_loop = restarted(1.0);
+        let mut buf = constant(20, 1.0);
+
+        assert!(!ab_loop.process(&mut buf, 100));
+        assert!(buf.chan(0).iter().all(|&sample| (sample - 1.0).abs() < 1e-6));
+
+        // Fading the held back frames out into silence gives the gain of the fade out, which is
+        // symmetric with the gain of the fade in.
+        let mut ab_loop = restarted(1.0);
+        let mut buf = constant(20, 0.0);
+
+        assert!(!ab_loop.process(&mut buf, 100));
+
+        let fade = &buf.chan(0)[..10];
+
+        for i in 0..10 {
+            assert!((fade[i] + fade[9 - i] - 1.0).abs() < 1e-6, "{:?}", fade);
+        }
+
+        assert!(fade.windows(2).all(|pair| pair[1] < pair[0]), "{:?}", fade);
+        assert!(fade[0] > 0.9 && fade[9] < 0.1, "{:?}", fade);
+
+        // The crossfade only covers the first frames after the start of the region.
+        assert_eq!(&buf.chan(0)[10..], &[0.0; 10]);
+        assert!(ab_loop.fade_pos.is_none());
+        assert!(ab_loop.tail[0].is_empty());
+    }
+
+    #[test]
+    fn restart_crossfade_continues_across_buffers() {
+        let mut ab_loop = restarted(1.0);
+        let mut expected = constant(20, 0.0);
+        ab_loop.process(&mut expected, 100);
+
+        // The same crossfade, with the frames after the start split into short buffers.
+        let mut ab_loop = restarted(1.0);
+        let mut faded = Vec::new();
+
+        for (start, len) in [(100, 3), (103, 3), (106, 3), (109, 3), (112, 8)] {
+            let mut buf = constant(len, 0.0);
+            ab_loop.process(&mut buf, start);
+            faded.extend_from_slice(buf.chan(0));
+        }
+
+        assert_eq!(faded, expected.chan(0));
+    }
+
+    #[test]
+    fn cancel_drops_held_back_frames() {
+        let mut ab_loop = restarted(1.0);
+        ab_loop.cancel();
+
+        let mut buf = constant(20, 0.0);
+        assert!(!ab_loop.process(&mut buf, 100));
+        assert_eq!(buf.chan(0), &[0.0; 20]);
+
+        // Restarting after cancelling does not start a crossfade either.
+        let mut ab_loop = region();
+        ab_loop.process(&mut constant(20, 1.0), 480);
+        ab_loop.cancel();
+        ab_loop.restart();
+
+        assert!(ab_loop.fade_pos.is_none());
+        assert!(ab_loop.tail.iter().all(Vec::is_empty));
+    }
+}
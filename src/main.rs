@@ -6,23 +6,25 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 #![warn(rust_2018_idioms)]
-#![forbid(unsafe_code)]
+// Justification: the PipeWire output calls the PipeWire library, which it loads at run time. It is
+// the only module that allows unsafe code.
+#![deny(unsafe_code)]
 // Justification: Fields on DecoderOptions and FormatOptions may change at any time, but
 // symphonia-play doesn't want to be updated every time those fields change, therefore always fill
 // in the remaining fields with default values.
 #![allow(clippy::needless_update)]
 
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::Write;
 use std::path::Path;
 
-use lazy_static::lazy_static;
-use symphonia::core::codecs::{DecoderOptions, FinalizeResult, CODEC_TYPE_NULL};
-use symphonia::core::errors::{Error, Result};
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Channels, Signal, SignalSpec};
+use symphonia::core::codecs::{CodecParameters, DecoderOptions, FinalizeResult, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error;
 use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo, Track};
 use symphonia::core::io::{MediaSource, MediaSourceStream, ReadOnlySource};
 use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
+use symphonia::core::probe::{Hint, ProbeResult};
 use symphonia::core::units::{Time, TimeBase};
 
 use clap::{Arg, ArgMatches};
@@ -30,6 +32,20 @@ use log::{error, info, warn};
 
 mod output;
 mod display;
+mod progress;
+mod controls;
+mod playlist;
+mod looper;
+mod gain;
+mod resample;
+mod mixer;
+mod error;
+
+#[cfg(test)]
+mod testutil;
+
+use controls::Command;
+use error::{PlayerError, Result};
 
 fn main() {
     pretty_env_logger::init();
@@ -38,15 +54,38 @@ fn main() {
         .version("1.0")
         .author("Philip Deljanov <philip.deljanov@gmail.com>")
         .about("Play audio with Symphonia")
+        .after_help(
+            "KEYS:\n    \
+             space, p    Pause or resume\n    \
+             left/right  Seek backwards or forwards 5 seconds\n    \
+             down/up     Seek backwards or forwards 60 seconds\n    \
+             -, +        Decrease or increase the volume\n    \
+             b, n        Skip to the previous or next track\n    \
+             v           Hide or show the visualiser\n    \
+             q           Quit\n\n\
+             EXIT STATUS:\n    \
+             0  All inputs were played\n    \
+             1  The decoded audio failed verification\n    \
+             2  The command line was invalid\n    \
+             3  An input could not be read or decoded\n    \
+             4  The audio output could not be opened, or failed during playback\n    \
+             5  The spectrogram or STFT could not be written",
+        )
         .arg(
             Arg::new("seek")
                 .long("seek")
                 .short('s')
                 .value_name("TIME")
+                .validator(is_number::<f64>)
                 .help("Seek to the given time in seconds"),
         )
         .arg(
-            Arg::new("track").long("track").short('t').value_name("TRACK").help("The track to use"),
+            Arg::new("track")
+                .long("track")
+                .short('t')
+                .value_name("TRACK")
+                .validator(is_number::<usize>)
+                .help("The track to use"),
         )
         .arg(
             Arg::new("verify")
@@ -58,93 +97,1133 @@ fn main() {
         .arg(
             Arg::new("no-gapless").long("no-gapless").help("Disable gapless decoding and playback"),
         )
+        .arg(
+            Arg::new("display")
+                .long("display")
+                .short('d')
+                .value_name("DISPLAY")
+                .possible_values(["spectrogram", "waveform", "meter", "scope", "bars", "loudness"])
+                .help("The visualiser shown during playback"),
+        )
+        .arg(
+            Arg::new("bands")
+                .long("bands")
+                .value_name("BANDS")
+                .possible_values(["octave", "third-octave"])
+                .help("The width of the bands shown by the spectrum analyser"),
+        )
+        .arg(
+            Arg::new("fft-size")
+                .long("fft-size")
+                .value_name("SIZE")
+                .validator(is_positive)
+                .help("The number of samples analysed per spectrogram frame"),
+        )
+        .arg(
+            Arg::new("hop-size")
+                .long("hop-size")
+                .value_name("SIZE")
+                .validator(is_number::<usize>)
+                .conflicts_with("overlap")
+                .help("The number of samples between consecutive spectrogram frames"),
+        )
+        .arg(
+            Arg::new("overlap")
+                .long("overlap")
+                .value_name("PERCENT")
+                .validator(is_overlap)
+                .help("The overlap between consecutive spectrogram frames in percent"),
+        )
+        .arg(
+            Arg::new("window")
+                .long("window")
+                .value_name("WINDOW")
+                .possible_values(["rectangular", "hann", "hamming", "blackman-harris", "kaiser"])
+                .help("The window function applied to spectrogram frames"),
+        )
+        .arg(
+            Arg::new("kaiser-beta")
+                .long("kaiser-beta")
+                .value_name("BETA")
+                .validator(is_number::<f32>)
+                .help("The shape parameter of the Kaiser window"),
+        )
+        .arg(
+            Arg::new("zero-padding")
+                .long("zero-padding")
+                .value_name("FACTOR")
+                .validator(is_number::<usize>)
+                .help("Zero-pad spectrogram frames to this many times their length"),
+        )
+        .arg(
+            Arg::new("render-spectrogram")
+                .long("render-spectrogram")
+                .value_name("FILE")
+                .help("Render a spectrogram of the input to a PNG file instead of playing it"),
+        )
+        .arg(
+            Arg::new("width")
+                .long("width")
+                .value_name("PIXELS")
+                .validator(is_number::<usize>)
+                .requires("render-spectrogram")
+                .help("The width of the rendered spectrogram"),
+        )
+        .arg(
+            Arg::new("height")
+                .long("height")
+                .value_name("PIXELS")
+                .validator(is_number::<usize>)
+                .requires("render-spectrogram")
+                .help("The height of the rendered spectrogram"),
+        )
+        .arg(
+            Arg::new("no-labels")
+                .long("no-labels")
+                .requires("render-spectrogram")
+                .help("Do not draw axis labels on the rendered spectrogram"),
+        )
+        .arg(
+            Arg::new("export-stft")
+                .long("export-stft")
+                .value_name("FILE")
+                .conflicts_with("render-spectrogram")
+                .help(
+                    "Export the STFT of the inputs to a .csv or .npy file instead of playing them. \
+                     The times of each input continue from the end of the previous input",
+                ),
+        )
+        .arg(
+            Arg::new("export-scale")
+                .long("export-scale")
+                .value_name("SCALE")
+                .possible_values(["magnitude", "power", "db"])
+                .requires("export-stft")
+                .help("The scale of the exported STFT values"),
+        )
+        .arg(
+            Arg::new("export-per-channel")
+                .long("export-per-channel")
+                .requires("export-stft")
+                .help("Export the STFT of each channel instead of the downmix"),
+        )
+        .arg(
+            Arg::new("volume")
+                .long("volume")
+                .value_name("DB")
+                .validator(is_number::<f64>)
+                .allow_hyphen_values(true)
+                .help("The initial volume in decibels, at most 0"),
+        )
+        .arg(
+            Arg::new("replaygain")
+                .long("replaygain")
+                .value_name("MODE")
+                .possible_values(["off", "track", "album"])
+                .help("Apply the ReplayGain of each input from its tags"),
+        )
+        .arg(
+            Arg::new("preamp")
+                .long("preamp")
+                .value_name("DB")
+                .validator(is_number::<f64>)
+                .allow_hyphen_values(true)
+                .help("The gain in decibels added to the ReplayGain of each input"),
+        )
+        .arg(
+            Arg::new("replaygain-fallback")
+                .long("replaygain-fallback")
+                .value_name("DB")
+                .validator(is_number::<f64>)
+                .allow_hyphen_values(true)
+                .help("The gain in decibels applied to inputs without ReplayGain tags"),
+        )
+        .arg(
+            Arg::new("no-clip-prevention")
+                .long("no-clip-prevention")
+                .help("Do not reduce the ReplayGain to keep the tagged peak from clipping"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("OUTPUT")
+                .validator(|value| output::OutputTarget::parse(value).ok_or("unknown output"))
+                .allow_hyphen_values(true)
+                .help(
+                    "Play the audio on the given output: default, alsa[:DEVICE] to play on an ALSA \
+                     device such as hw:0,0 or plughw:1, jack, pipewire, file:PATH to \
+                     write a WAV or FLAC file, - to write raw samples to standard output, or \
+                     null[:realtime] to discard the audio and print the decoding speed",
+                ),
+        )
+        .arg(
+            Arg::new("device")
+                .long("device")
+                .value_name("DEVICE")
+                .help(
+                    "The device to play on: a PulseAudio sink, an ALSA device, a pattern of the \
+                     JACK ports to connect to, or a PipeWire node",
+                ),
+        )
+        .arg(
+            Arg::new("server")
+                .long("server")
+                .value_name("SERVER")
+                .help("The PulseAudio or PipeWire server to connect to"),
+        )
+        .arg(
+            Arg::new("stream-name")
+                .long("stream-name")
+                .value_name("NAME")
+                .help("The name of the stream shown by the sound server [default: Music]"),
+        )
+        .arg(
+            Arg::new("list-devices")
+                .long("list-devices")
+                .help("List the devices of every audio output, and exit"),
+        )
+        .arg(
+            Arg::new("sample-format")
+                .long("sample-format")
+                .value_name("FORMAT")
+                .possible_values(["f32", "s16", "s24", "s32"])
+                .help(
+                    "The sample format of file and raw outputs, and the preferred sample format \
+                     of ALSA devices [default: f32, or s24 for FLAC]",
+                ),
+        )
+        .arg(
+            Arg::new("endian")
+                .long("endian")
+                .value_name("ORDER")
+                .possible_values(["little", "big"])
+                .help("The byte order of raw outputs [default: little]"),
+        )
+        .arg(
+            Arg::new("period-size")
+                .long("period-size")
+                .value_name("FRAMES")
+                .validator(is_positive)
+                .help(
+                    "The period size of ALSA devices, and the latency asked of PipeWire \
+                     [default: a quarter of the buffer]",
+                ),
+        )
+        .arg(
+            Arg::new("buffer-size")
+                .long("buffer-size")
+                .value_name("FRAMES")
+                .validator(is_positive)
+                .help(
+                    "The buffer size of ALSA devices, and the audio queued for JACK and \
+                     PipeWire [default: 200 ms of audio]",
+                ),
+        )
+        .arg(
+            Arg::new("no-dither")
+                .long("no-dither")
+                .help("Do not dither samples converted to integers"),
+        )
+        .arg(
+            Arg::new("output-rate")
+                .long("output-rate")
+                .value_name("HZ")
+                .validator(is_positive)
+                .help("Resample the audio to the given sample rate for playback"),
+        )
+        .arg(
+            Arg::new("output-channels")
+                .long("output-channels")
+                .value_name("LAYOUT")
+                .possible_values(["mono", "stereo", "quad", "5.1", "7.1"])
+                .help("Mix the audio to the given channel layout for playback"),
+        )
+        .arg(
+            Arg::new("mix-matrix")
+                .long("mix-matrix")
+                .value_name("MATRIX")
+                .validator(is_matrix)
+                .allow_hyphen_values(true)
+                .help(
+                    "Mix the audio with a custom matrix of gains, one row per output channel \
+                     separated by ';', and one gain per input channel separated by ','",
+                ),
+        )
+        .arg(
+            Arg::new("repeat")
+                .long("repeat")
+                .value_name("N|inf")
+                .validator(is_repeat)
+                .conflicts_with_all(&["render-spectrogram", "export-stft"])
+                .help("Play each input N more times, or repeat it forever with inf"),
+        )
+        .arg(
+            Arg::new("loop-a")
+                .long("loop-a")
+                .value_name("TIME")
+                .validator(is_number::<f64>)
+                .requires("loop-b")
+                .conflicts_with_all(&["render-spectrogram", "export-stft"])
+                .help("Loop the region of each input starting at the given time in seconds"),
+        )
+        .arg(
+            Arg::new("loop-b")
+                .long("loop-b")
+                .value_name("TIME")
+                .validator(is_number::<f64>)
+                .requires("loop-a")
+                .help("Loop the region of each input ending at the given time in seconds"),
+        )
         .arg(
             Arg::new("INPUT")
-                .help("The input file path, or - to use standard input")
-                .required(true)
+                .help("The input files, directories or playlists, or - to use standard input")
+                .required_unless_present("list-devices")
+                .multiple_values(true)
                 .index(1),
         )
         .get_matches();
 
-    // For any error, return an exit code -1. Otherwise return the exit code provided.
+    // For any error, return the exit code of the error. Otherwise return the exit code provided.
     let code = match run(&args) {
         Ok(code) => code,
         Err(err) => {
-            error!("{}", err.to_string().to_lowercase());
-            -1
+            error!("{}", err);
+            err.exit_code()
+        }
+    };
+
+    std::process::exit(code)
+}
+
+fn run(args: &ArgMatches) -> Result<i32> {
+    if args.is_present("list-devices") {
+        list_devices(&output_options(args));
+        return Ok(0);
+    }
+
+    let inputs: Vec<&str> = args.values_of("INPUT").unwrap().collect();
+
+    // Expand directories and playlists into the list of files to play.
+    let paths = playlist::expand(&inputs);
+
+    // Use the default options for format readers other than for gapless playback.
+    let format_opts =
+        FormatOptions { enable_gapless: !args.is_present("no-gapless"), ..Default::default() };
+
+    // Get the value of the track option, if provided.
+    let track = match args.value_of("track") {
+        Some(track_str) => track_str.parse::<usize>().ok(),
+        _ => None,
+    };
+
+    let no_progress = args.is_present("no-progress");
+
+    let display_opts = display_options(args);
+
+    // If present, parse the seek argument. Only the first input is seeked.
+    let mut seek_time = args.value_of("seek").map(|p| p.parse::<f64>().unwrap_or(0.0));
+
+    // Set the decoder options.
+    let decode_opts = DecoderOptions { verify: args.is_present("verify"), ..Default::default() };
+
+    let loop_opts = loop_options(args);
+
+    let gain_opts = gain_options(args);
+
+    // If present, parse the output rate argument.
+    let output_rate = args.value_of("output-rate").and_then(|r| r.parse::<u32>().ok());
+
+    let output_opts = output_options(args);
+
+    // If the audio is written to standard output, the status line and visualiser cannot be.
+    let to_stdout = output_opts.target.is_stdout();
+
+    // The audio output, display and status line are shared by all inputs.
+    let mut state = PlayerState::new(&display_opts, &gain_opts, no_progress || to_stdout);
+    state.display_hidden = to_stdout && !display_opts.is_offline();
+    state.output_rate = output_rate;
+    state.output_channels = output_channels(args);
+    state.mix_matrix = mix_matrix(args);
+    state.output_opts = output_opts;
+
+    let mut code = 0;
+    let mut idx = 0;
+
+    let result = loop {
+        let path = match paths.get(idx) {
+            Some(path) => path,
+            None => break Ok(code),
+        };
+
+        // Errors are fatal when playing a single input. Otherwise, the input is skipped, unless the
+        // audio output failed.
+        let result = open_input(path, &format_opts).and_then(|mut probed| {
+            state.replaygain = read_replaygain(&mut probed).gain(&gain_opts);
+
+            // Play it!
+            let reader = probed.format;
+            let seek_time = seek_time.take();
+            play(reader, track, seek_time, &decode_opts, &display_opts, &loop_opts, &mut state)
+        });
+
+        match result {
+            Ok(TrackEnd::Finished(track_code)) => {
+                code = code.max(track_code);
+                idx += 1;
+            }
+            Ok(TrackEnd::Skip(offset)) if offset < 0 => idx = idx.saturating_sub(1),
+            Ok(TrackEnd::Skip(_)) => idx += 1,
+            Ok(TrackEnd::Quit) => break Ok(code),
+            Err(err @ PlayerError::Symphonia(_)) if paths.len() > 1 => {
+                error!("{}: {}", path.display(), err);
+                code = code.max(err.exit_code());
+                idx += 1;
+            }
+            Err(err) => break Err(err),
+        }
+    };
+
+    // Finishing writes the output file of an offline display, which can also fail.
+    let finished = state.finish();
+
+    result.and_then(|code| finished.map(|_| code))
+}
+
+/// Opens an input file, or standard input if the path is -, and probes it.
+fn open_input(path: &Path, format_opts: &FormatOptions) -> Result<ProbeResult> {
+    // Create a hint to help the format registry guess what format reader is appropriate.
+    let mut hint = Hint::new();
+
+    // If the path string is '-' then read from standard input.
+    let source = if path == Path::new("-") {
+        Box::new(ReadOnlySource::new(std::io::stdin())) as Box<dyn MediaSource>
+    }
+    else {
+        // Provide the file extension as a hint.
+        if let Some(extension) = path.extension() {
+            if let Some(extension_str) = extension.to_str() {
+                hint.with_extension(extension_str);
+            }
+        }
+
+        Box::new(File::open(path).map_err(Error::from)?)
+    };
+
+    // Create the media source stream using the boxed media source from above.
+    let mss = MediaSourceStream::new(source, Default::default());
+
+    // Use the default options for metadata readers.
+    let metadata_opts: MetadataOptions = Default::default();
+
+    // Probe the media source stream for metadata and get the format reader.
+    match symphonia::default::get_probe().format(&hint, mss, format_opts, &metadata_opts) {
+        Ok(probed) => Ok(probed),
+        Err(err) => {
+            // The input was not supported by any format reader.
+            info!("the input is not supported");
+            Err(err.into())
+        }
+    }
+}
+
+/// Reads the ReplayGain values of an input from the metadata found while probing it, and the
+/// metadata of its container.
+fn read_replaygain(probed: &mut ProbeResult) -> gain::ReplayGain {
+    let mut replaygain = gain::ReplayGain::default();
+
+    if let Some(rev) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        replaygain.read_tags(rev.tags());
+    }
+
+    if let Some(rev) = probed.format.metadata().current() {
+        replaygain.read_tags(rev.tags());
+    }
+
+    replaygain
+}
+
+/// Validates a command line value that must be a number of type `T`.
+fn is_number<T: std::str::FromStr>(value: &str) -> std::result::Result<(), String> {
+    value.parse::<T>().map(|_| ()).map_err(|_| "expected a number".to_string())
+}
+
+/// Validates a command line value that must be a positive integer.
+fn is_positive(value: &str) -> std::result::Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err("expected a positive integer".to_string()),
+    }
+}
+
+/// Validates an overlap percentage, from 0 up to but excluding 100.
+fn is_overlap(value: &str) -> std::result::Result<(), String> {
+    match value.parse::<f64>() {
+        Ok(overlap) if (0.0..100.0).contains(&overlap) => Ok(()),
+        _ => Err("expected a percentage from 0 up to 100".to_string()),
+    }
+}
+
+/// Validates a repeat count, or inf.
+fn is_repeat(value: &str) -> std::result::Result<(), String> {
+    if value.eq_ignore_ascii_case("inf") {
+        return Ok(());
+    }
+
+    is_number::<u32>(value).map_err(|_| "expected a count or inf".to_string())
+}
+
+/// Validates a mixing matrix, which must mix to a standard layout.
+fn is_matrix(value: &str) -> std::result::Result<(), String> {
+    match mixer::parse_matrix(value) {
+        Some(matrix) if mixer::layout(matrix.len()).is_some() => Ok(()),
+        Some(_) => Err("expected 1 to 8 rows".to_string()),
+        None => Err("expected rows of the same number of gains".to_string()),
+    }
+}
+
+/// Builds the gain options from the command line arguments.
+fn gain_options(args: &ArgMatches) -> gain::GainOptions {
+    let db = |name| args.value_of(name).and_then(|s| s.parse::<f64>().ok()).unwrap_or(0.0);
+
+    let replaygain = match args.value_of("replaygain") {
+        Some("track") => Some(gain::ReplayGainMode::Track),
+        Some("album") => Some(gain::ReplayGainMode::Album),
+        _ => None,
+    };
+
+    gain::GainOptions {
+        volume: db("volume").clamp(gain::MIN_VOLUME, gain::MAX_VOLUME),
+        replaygain,
+        preamp: db("preamp"),
+        fallback: db("replaygain-fallback"),
+        clip_prevention: !args.is_present("no-clip-prevention"),
+    }
+}
+
+/// Builds the audio output options from the command line arguments.
+fn output_options(args: &ArgMatches) -> output::OutputOptions {
+    // The values are validated when the command line is parsed.
+    let target = match args.value_of("output") {
+        Some(value) => output::OutputTarget::parse(value).unwrap_or_default(),
+        None => output::OutputTarget::Default,
+    };
+
+    let format = match args.value_of("sample-format") {
+        Some("f32") => Some(output::SampleFormat::F32),
+        Some("s16") => Some(output::SampleFormat::S16),
+        Some("s24") => Some(output::SampleFormat::S24),
+        Some("s32") => Some(output::SampleFormat::S32),
+        _ => None,
+    };
+
+    let frames = |name: &str| args.value_of(name).and_then(|value| value.parse::<u32>().ok());
+
+    output::OutputOptions {
+        target,
+        format,
+        dither: !args.is_present("no-dither"),
+        big_endian: args.value_of("endian") == Some("big"),
+        period_size: frames("period-size"),
+        buffer_size: frames("buffer-size"),
+        device: args.value_of("device").map(String::from),
+        server: args.value_of("server").map(String::from),
+        stream_name: args.value_of("stream-name").map(String::from),
+    }
+}
+
+/// Prints the devices of every audio output. For outputs that cannot list their devices, such as
+/// a sound server that is not running, the reason is printed instead.
+fn list_devices(opts: &output::OutputOptions) {
+    for (output, devices) in output::list_devices(opts) {
+        println!("{}:", output);
+
+        let devices = match devices {
+            Ok(devices) => devices,
+            Err(err) => {
+                println!("    unavailable: {}", err);
+                continue;
+            }
+        };
+
+        let width = devices.iter().map(|device| device.name.len()).max().unwrap_or(0);
+
+        for device in &devices {
+            println!("    {:<width$}  {}", device.name, device.description, width = width);
+        }
+
+        if devices.is_empty() {
+            println!("    no devices");
+        }
+    }
+}
+
+/// Gets the channel layout requested for the audio output, if any.
+fn output_channels(args: &ArgMatches) -> Option<Channels> {
+    let count = match args.value_of("output-channels")? {
+        "mono" => 1,
+        "stereo" => 2,
+        "quad" => 4,
+        "5.1" => 6,
+        "7.1" => 8,
+        _ => return None,
+    };
+
+    mixer::layout(count)
+}
+
+/// Parses the custom mixing matrix, if any. The number of rows sets the output channels.
+fn mix_matrix(args: &ArgMatches) -> Option<(Channels, mixer::Matrix)> {
+    let matrix = mixer::parse_matrix(args.value_of("mix-matrix")?)?;
+
+    Some((mixer::layout(matrix.len())?, matrix))
+}
+
+/// Builds the repeat and loop options from the command line arguments.
+fn loop_options(args: &ArgMatches) -> looper::LoopOptions {
+    let repeat = match args.value_of("repeat") {
+        Some(n) if n.eq_ignore_ascii_case("inf") => looper::Repeat::Forever,
+        Some(n) => looper::Repeat::Count(n.parse::<u32>().unwrap_or(0)),
+        None => looper::Repeat::Count(0),
+    };
+
+    let a = args.value_of("loop-a").and_then(|s| s.parse::<f64>().ok());
+    let b = args.value_of("loop-b").and_then(|s| s.parse::<f64>().ok());
+
+    let region = match (a, b) {
+        (Some(a), Some(b)) if b > a => Some((a, b)),
+        (Some(_), Some(_)) => {
+            warn!("ignoring the loop region because it ends before it starts");
+            None
+        }
+        _ => None,
+    };
+
+    looper::LoopOptions { repeat, region }
+}
+
+/// Builds the display options from the command line arguments.
+fn display_options(args: &ArgMatches) -> display::DisplayOptions {
+    let image = args.value_of("render-spectrogram").map(|path| {
+        let width = args.value_of("width").and_then(|s| s.parse::<usize>().ok()).unwrap_or(1024);
+        let height = args.value_of("height").and_then(|s| s.parse::<usize>().ok()).unwrap_or(512);
+
+        display::ImageOptions {
+            path: path.into(),
+            width: width.max(1),
+            height: height.max(1),
+            labels: !args.is_present("no-labels"),
+        }
+    });
+
+    let export = args.value_of("export-stft").map(|path| {
+        let path = Path::new(path);
+
+        // Use the NumPy format if the file has its extension, otherwise use CSV.
+        let format = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("npy") => display::ExportFormat::Npy,
+            _ => display::ExportFormat::Csv,
+        };
+
+        let scale = match args.value_of("export-scale") {
+            Some("power") => display::ExportScale::Power,
+            Some("db") => display::ExportScale::Decibels,
+            _ => display::ExportScale::Magnitude,
+        };
+
+        display::ExportOptions {
+            path: path.to_path_buf(),
+            format,
+            scale,
+            per_channel: args.is_present("export-per-channel"),
+        }
+    });
+
+    let kind = match args.value_of("display") {
+        Some("waveform") => display::DisplayKind::Waveform,
+        Some("meter") => display::DisplayKind::Meter,
+        Some("scope") => display::DisplayKind::Scope,
+        Some("bars") => display::DisplayKind::Bars,
+        Some("loudness") => display::DisplayKind::Loudness,
+        _ => display::DisplayKind::Spectrogram,
+    };
+
+    let bands = match args.value_of("bands") {
+        Some("octave") => display::Bands::Octave,
+        _ => display::Bands::ThirdOctave,
+    };
+
+    display::DisplayOptions { kind, stft: stft_options(args), bands, image, export }
+}
+
+/// Builds the spectrogram analysis options from the command line arguments. Missing values fall
+/// back to the defaults.
+fn stft_options(args: &ArgMatches) -> display::StftOptions {
+    let defaults = display::StftOptions::default();
+
+    let window_size = args
+        .value_of("fft-size")
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|&size| size > 0)
+        .unwrap_or(defaults.window_size);
+
+    // The hop size may be given directly, or as an overlap percentage of the window size.
+    let hop_size = match (args.value_of("hop-size"), args.value_of("overlap")) {
+        (Some(hop), _) => hop.parse::<usize>().ok(),
+        (_, Some(overlap)) => overlap
+            .parse::<f64>()
+            .ok()
+            .filter(|overlap| (0.0..100.0).contains(overlap))
+            .map(|overlap| (window_size as f64 * (1.0 - overlap / 100.0)).round() as usize),
+        _ => Some(window_size / 2),
+    };
+
+    let beta = args.value_of("kaiser-beta").and_then(|s| s.parse::<f32>().ok()).unwrap_or(8.6);
+
+    let window = match args.value_of("window") {
+        Some("rectangular") => display::WindowFunction::Rectangular,
+        Some("hamming") => display::WindowFunction::Hamming,
+        Some("blackman-harris") => display::WindowFunction::BlackmanHarris,
+        Some("kaiser") => display::WindowFunction::Kaiser { beta },
+        _ => defaults.window,
+    };
+
+    let zero_padding = args
+        .value_of("zero-padding")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(defaults.zero_padding);
+
+    display::StftOptions {
+        window_size,
+        hop_size: hop_size.filter(|&hop| hop > 0).unwrap_or(window_size / 2).max(1),
+        window,
+        zero_padding: zero_padding.max(1),
+    }
+}
+
+#[derive(Copy, Clone)]
+struct PlayTrackOptions {
+    track_id: u32,
+    seek_ts: u64,
+}
+
+/// The sample rate the audio is resampled to if the audio output cannot be opened at another.
+const FALLBACK_RATE: u32 = 48_000;
+
+/// How long to wait for a key press between checks while paused.
+const PAUSE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// The number of times a failed write to the audio output is retried before it is reopened.
+const WRITE_RETRIES: usize = 3;
+
+/// How long to wait before retrying a failed write to the audio output.
+const WRITE_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// The state of the player that persists across tracks and inputs.
+struct PlayerState {
+    audio_output: Option<Box<dyn output::AudioOutput>>,
+    /// The audio output to play on, and its options.
+    output_opts: output::OutputOptions,
+    display: Option<Box<dyn display::Display>>,
+    /// The signal spec of the audio the audio output is configured for.
+    output_spec: Option<SignalSpec>,
+    /// The signal spec the audio output stream was opened with.
+    stream_spec: Option<SignalSpec>,
+    /// The sample rate requested for the audio output.
+    output_rate: Option<u32>,
+    /// Converts the audio to the sample rate of the audio output, if they differ.
+    resampler: Option<resample::Resampler>,
+    /// The channel layout requested for the audio output.
+    output_channels: Option<Channels>,
+    /// The custom mixing matrix, and the channels it mixes to.
+    mix_matrix: Option<(Channels, mixer::Matrix)>,
+    /// Mixes the audio to the channels of the audio output, if they differ.
+    mixer: Option<mixer::ChannelMixer>,
+    /// The signal spec and buffer capacity the display was opened with.
+    display_spec: Option<(SignalSpec, u64)>,
+    /// If true, the display writes a file rather than drawing to the terminal. Its errors are
+    /// fatal, whereas a visualiser that fails is only hidden.
+    display_offline: bool,
+    /// The loudness measured by the loudness display, kept when the display is reopened.
+    loudness: display::LoudnessHistory,
+    progress: Option<progress::Progress>,
+    controls: Option<controls::Controls>,
+    paused: bool,
+    /// The user volume in decibels.
+    volume: f64,
+    /// The ReplayGain of the current input in decibels.
+    replaygain: f64,
+    display_hidden: bool,
+}
+
+impl PlayerState {
+    fn new(
+        display_opts: &display::DisplayOptions,
+        gain_opts: &gain::GainOptions,
+        no_progress: bool,
+    ) -> Self {
+        // The status line is drawn below the visualiser, so a row is reserved for it unless the
+        // display renders offline.
+        let progress = if no_progress {
+            None
+        }
+        else {
+            Some(progress::Progress::new(!display_opts.is_offline()))
+        };
+
+        // Keyboard controls are only available during playback.
+        let controls =
+            if display_opts.is_offline() { None } else { controls::Controls::try_open() };
+
+        PlayerState {
+            audio_output: None,
+            output_opts: Default::default(),
+            display: None,
+            output_spec: None,
+            stream_spec: None,
+            output_rate: None,
+            resampler: None,
+            output_channels: None,
+            mix_matrix: None,
+            mixer: None,
+            display_spec: None,
+            display_offline: display_opts.is_offline(),
+            loudness: Default::default(),
+            progress,
+            controls,
+            paused: false,
+            volume: gain_opts.volume,
+            replaygain: 0.0,
+            display_hidden: false,
+        }
+    }
+
+    /// Checks that buffers with the given spec and capacity can be written to the audio output
+    /// and display. This is done for every buffer, since the spec can change between any two
+    /// packets, such as at the links of a chained stream.
+    ///
+    /// If the spec changed, the audio output is reconfigured to play the new spec on the same
+    /// stream, so that there is no gap. If the stream cannot play it, the audio output is closed so
+    /// that it is reopened with the next buffer, unless it is an output with a fixed spec. The
+    /// audio output grows its buffers as required, but the display must also be reconfigured if
+    /// the capacity grows.
+    fn check_sinks(&mut self, spec: SignalSpec, duration: u64) -> Result<()> {
+        if self.output_spec.is_some_and(|output_spec| output_spec != spec) {
+            let (rate, channels) = self.preferred_output(spec);
+            let is_fixed = self.output_opts.target.has_fixed_spec();
+
+            match self.stream_spec {
+                Some(stream_spec)
+                    if is_fixed || stream_spec == SignalSpec::new(rate, channels) =>
+                {
+                    self.drain_resampler();
+                    self.configure_output(spec, stream_spec.rate, stream_spec.channels);
+                }
+                _ => self.close_output(),
+            }
+        }
+
+        if self.display_spec.is_some_and(|(display_spec, display_duration)| {
+            display_spec != spec || duration > display_duration
+        }) {
+            self.reconfigure_display(spec, duration)?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the custom mixing matrix if it mixes audio with the given spec. It only mixes audio
+    /// with as many channels as the matrix has columns.
+    fn custom_matrix(&self, spec: SignalSpec) -> Option<&(Channels, mixer::Matrix)> {
+        self.mix_matrix.as_ref().filter(|(_, matrix)| matrix[0].len() == spec.channels.count())
+    }
+
+    /// Gets the sample rate and channels the audio output is preferably opened with to play audio
+    /// with the given spec.
+    fn preferred_output(&self, spec: SignalSpec) -> (u32, Channels) {
+        let channels = match self.custom_matrix(spec) {
+            Some(&(channels, _)) => channels,
+            None => self.output_channels.unwrap_or(spec.channels),
+        };
+
+        let rate = output::supported_rate(&self.output_opts, self.output_rate.unwrap_or(spec.rate));
+
+        (rate, output::supported_channels(&self.output_opts, channels))
+    }
+
+    /// Opens the audio output for buffers with the given spec and capacity. The audio is mixed and
+    /// resampled if the audio output does not support its channels or sample rate, or others were
+    /// requested.
+    fn open_output(&mut self, spec: SignalSpec, duration: u64) -> Result<()> {
+        let (rate, channels) = self.preferred_output(spec);
+
+        // If the audio output cannot be opened with the preferred spec, try the fallback rate, and
+        // then stereo.
+        let stereo = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+
+        let mut specs = vec![(rate, channels), (FALLBACK_RATE, channels), (FALLBACK_RATE, stereo)];
+        specs.dedup();
+
+        let mut last_err = None;
+
+        for (rate, channels) in specs {
+            // The capacity of the resampled buffers scales with the rate.
+            let out_duration = (duration * u64::from(rate)).div_ceil(u64::from(spec.rate));
+
+            let out_spec = SignalSpec::new(rate, channels);
+
+            match output::try_open(out_spec, out_duration, &self.output_opts) {
+                Ok(audio_output) => {
+                    self.audio_output = Some(audio_output);
+                    self.stream_spec = Some(out_spec);
+                    self.configure_output(spec, rate, channels);
+
+                    return Ok(());
+                }
+                Err(err) => {
+                    warn!("audio output: {} channels at {} Hz: {}", channels.count(), rate, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        // At least one spec was tried, so there is an error.
+        Err(last_err.unwrap().into())
+    }
+
+    /// Sets up the mixer and resampler that convert audio with the given spec to the sample rate
+    /// and channels of the audio output.
+    fn configure_output(&mut self, spec: SignalSpec, rate: u32, channels: Channels) {
+        let matrix = match self.custom_matrix(spec) {
+            Some((custom_channels, matrix)) if *custom_channels == channels => Some(matrix.clone()),
+            _ if channels != spec.channels => Some(mixer::standard_matrix(spec.channels, channels)),
+            _ => None,
+        };
+
+        if matrix.is_some() {
+            info!("mixing {} channels to {}", spec.channels.count(), channels.count());
+        }
+
+        if rate != spec.rate {
+            info!("resampling from {} Hz to {} Hz", spec.rate, rate);
+        }
+
+        // The audio is mixed before it is resampled.
+        let mixed_spec = SignalSpec::new(spec.rate, channels);
+
+        self.output_spec = Some(spec);
+        self.mixer = matrix.map(|matrix| mixer::ChannelMixer::new(spec, channels, matrix));
+        self.resampler = (rate != spec.rate).then(|| resample::Resampler::new(mixed_spec, rate));
+    }
+
+    /// Mixes and resamples a buffer for the audio output, if required, and writes it.
+    ///
+    /// If the stream was closed, the write is retried a few times. If it still fails, such as when
+    /// the sound server restarts, the audio output is reopened for the spec of the buffer, as it
+    /// was opened in the first place.
+    fn write_output(&mut self, buf: AudioBufferRef<'_>) -> Result<()> {
+        if self.audio_output.is_none() {
+            return Ok(());
+        }
+
+        match self.write_converted(&buf) {
+            Err(err @ output::AudioOutputError::StreamClosedError(_)) => {
+                warn!("audio output: {}, reopening", err);
+
+                // Drop the closed stream before opening a new one. The audio held back by the
+                // resampler is lost with it.
+                self.audio_output = None;
+                self.close_output();
+
+                self.open_output(*buf.spec(), buf.capacity() as u64)?;
+                self.write_converted(&buf).map_err(PlayerError::from)
+            }
+            result => result.map_err(PlayerError::from),
+        }
+    }
+
+    /// Mixes and resamples a buffer, and writes it to the audio output, retrying a few times if the
+    /// stream was closed.
+    fn write_converted(&mut self, buf: &AudioBufferRef<'_>) -> output::Result<()> {
+        let buf = match self.mixer.as_mut() {
+            Some(mixer) => AudioBufferRef::F32(Cow::Borrowed(mixer.process(buf))),
+            None => buf.clone(),
+        };
+
+        let buf = match self.resampler.as_mut() {
+            Some(resampler) => AudioBufferRef::F32(Cow::Borrowed(resampler.process(&buf))),
+            None => buf,
+        };
+
+        let audio_output = match self.audio_output.as_mut() {
+            Some(audio_output) => audio_output,
+            None => return Ok(()),
+        };
+
+        let mut result = audio_output.write(buf.clone());
+
+        for _ in 0..WRITE_RETRIES {
+            match &result {
+                Err(err @ output::AudioOutputError::StreamClosedError(_)) => {
+                    warn!("audio output: {}, retrying", err)
+                }
+                _ => break,
+            }
+
+            std::thread::sleep(WRITE_RETRY_INTERVAL);
+            result = audio_output.write(buf.clone());
+        }
+
+        result
+    }
+
+    /// Plays the audio held back by the resampler, if any.
+    fn drain_resampler(&mut self) {
+        if let (Some(audio_output), Some(resampler)) = (&mut self.audio_output, &mut self.resampler)
+        {
+            let _ = audio_output.write(AudioBufferRef::F32(Cow::Borrowed(resampler.flush())));
+        }
+    }
+
+    fn close_output(&mut self) {
+        self.drain_resampler();
+
+        // Flush the audio output to finish playing back any leftover samples.
+        if let Some(mut audio_output) = self.audio_output.take() {
+            audio_output.flush()
+        }
+
+        self.output_spec = None;
+        self.stream_spec = None;
+        self.mixer = None;
+        self.resampler = None;
+    }
+
+    /// Opens the display for buffers with the given spec and capacity.
+    fn open_display(
+        &mut self,
+        spec: SignalSpec,
+        duration: u64,
+        tb: Option<TimeBase>,
+        display_opts: &display::DisplayOptions,
+    ) -> Result<()> {
+        match display::try_open(spec, duration, tb, display_opts, &self.loudness) {
+            Ok(display) => {
+                self.display = Some(display);
+                self.display_spec = Some((spec, duration));
+                Ok(())
+            }
+            Err(err) => self.display_failed(err),
         }
-    };
+    }
 
-    std::process::exit(code)
-}
+    /// Reconfigures the display for buffers with the given spec and capacity, or closes it so that
+    /// it is reopened with the next buffer. Offline displays are never reopened, since that would
+    /// overwrite the file they write.
+    fn reconfigure_display(&mut self, spec: SignalSpec, duration: u64) -> Result<()> {
+        let result = match self.display.as_mut() {
+            Some(display) => display.reconfigure(spec, duration),
+            None => Ok(false),
+        };
 
-fn run(args: &ArgMatches) -> Result<i32> {
-    let path_str = args.value_of("INPUT").unwrap();
+        match result {
+            Ok(true) => {
+                self.display_spec = Some((spec, duration));
+                Ok(())
+            }
+            Ok(false) => self.close_display(),
+            Err(err) => self.display_failed(err),
+        }
+    }
 
-    // Create a hint to help the format registry guess what format reader is appropriate.
-    let mut hint = Hint::new();
+    /// Tells the display that the next input or track starts, with timestamps in the time base
+    /// `tb`.
+    fn start_display_input(&mut self, tb: Option<TimeBase>) {
+        if let Some(display) = self.display.as_mut() {
+            display.start_input(tb);
+        }
+    }
 
-    // If the path string is '-' then read from standard input.
-    let source = if path_str == "-" {
-        Box::new(ReadOnlySource::new(std::io::stdin())) as Box<dyn MediaSource>
+    /// Writes a decoded buffer, whose first frame has the timestamp `ts`, to the display.
+    fn write_display(&mut self, decoded: AudioBufferRef<'_>, ts: u64) -> Result<()> {
+        let result = match self.display.as_mut() {
+            Some(display) => display.write(decoded, ts),
+            None => Ok(()),
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(err) => self.display_failed(err),
+        }
     }
-    else {
-        // Othwerise, get a Path from the path string.
-        let path = Path::new(path_str);
 
-        // Provide the file extension as a hint.
-        if let Some(extension) = path.extension() {
-            if let Some(extension_str) = extension.to_str() {
-                hint.with_extension(extension_str);
-            }
+    /// Handles a display error. The errors of an offline display are fatal. A visualiser that
+    /// fails is hidden instead, so that playback continues.
+    fn display_failed(&mut self, err: display::DisplayError) -> Result<()> {
+        if self.display_offline {
+            return Err(err.into());
         }
 
-        Box::new(File::open(path)?)
-    };
+        warn!("{}, hiding the visualiser", err);
 
-    // Create the media source stream using the boxed media source from above.
-    let mss = MediaSourceStream::new(source, Default::default());
+        self.display_hidden = true;
+        self.close_display()?;
+        display::clear();
 
-    // Use the default options for format readers other than for gapless playback.
-    let format_opts =
-        FormatOptions { enable_gapless: !args.is_present("no-gapless"), ..Default::default() };
+        if let Some(progress) = self.progress.as_mut() {
+            progress.redraw();
+        }
 
-    // Use the default options for metadata readers.
-    let metadata_opts: MetadataOptions = Default::default();
+        Ok(())
+    }
 
-    // Get the value of the track option, if provided.
-    let track = match args.value_of("track") {
-        Some(track_str) => track_str.parse::<usize>().ok(),
-        _ => None,
-    };
+    fn close_display(&mut self) -> Result<()> {
+        self.display_spec = None;
 
-    let no_progress = args.is_present("no-progress");
+        // Flush the display to draw its final state and restore the terminal, or to write the
+        // output file of an offline display.
+        let result = match self.display.take() {
+            Some(mut display) => display.flush(),
+            None => Ok(()),
+        };
 
-    // Probe the media source stream for metadata and get the format reader.
-    match symphonia::default::get_probe().format(&hint, mss, &format_opts, &metadata_opts) {
-        Ok(probed) => {
-            // If present, parse the seek argument.
-            let seek_time = args.value_of("seek").map(|p| p.parse::<f64>().unwrap_or(0.0));
+        match result {
+            Err(err) if self.display_offline => Err(err.into()),
+            Err(err) => {
+                warn!("{}", err);
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
 
-            // Set the decoder options.
-            let decode_opts =
-                DecoderOptions { verify: args.is_present("verify"), ..Default::default() };
+    /// Finishes playback.
+    fn finish(&mut self) -> Result<()> {
+        self.close_output();
+        let result = self.close_display();
 
-            // Play it!
-            play(probed.format, track, seek_time, &decode_opts, no_progress)
-        }
-        Err(err) => {
-            // The input was not supported by any format reader.
-            info!("the input is not supported");
-            Err(err)
+        if let Some(progress) = self.progress.as_mut() {
+            progress.finish()
         }
+
+        // Restore the terminal before any errors or the loudness summary are printed.
+        self.controls = None;
+
+        self.loudness.print_summary();
+
+        result
     }
 }
 
-#[derive(Copy, Clone)]
-struct PlayTrackOptions {
-    track_id: u32,
-    seek_ts: u64,
+/// How playback of a track or input ended.
+enum TrackEnd {
+    /// The track was played to the end. Holds the verification result.
+    Finished(i32),
+    /// The user skipped forwards by a number of tracks, or backwards if negative. Skipping past
+    /// either end of an input moves on to the next or previous input.
+    Skip(i32),
+    /// The user quit.
+    Quit,
 }
 
 fn play(
@@ -152,35 +1231,46 @@ fn play(
     track_num: Option<usize>,
     seek_time: Option<f64>,
     decode_opts: &DecoderOptions,
-    no_progress: bool,
-) -> Result<i32> {
+    display_opts: &display::DisplayOptions,
+    loop_opts: &looper::LoopOptions,
+    state: &mut PlayerState,
+) -> Result<TrackEnd> {
     // If the user provided a track number, select that track if it exists, otherwise, select the
     // first track with a known codec.
     let track = track_num
         .and_then(|t| reader.tracks().get(t))
         .or_else(|| first_supported_track(reader.tracks()));
 
-    let mut track_id = match track {
-        Some(track) => track.id,
-        _ => return Ok(0),
+    let (mut track_id, region_ts) = match track {
+        Some(track) => {
+            let region_ts = loop_opts.region.map(|(a, _)| time_to_ts(a, &track.codec_params));
+            (track.id, region_ts)
+        }
+        _ => return Ok(TrackEnd::Finished(0)),
     };
 
-    // If there is a seek time, seek the reader to the time specified and get the timestamp of the
-    // seeked position. All packets with a timestamp < the seeked position will not be played.
-    //
-    // Note: This is a half-baked approach to seeking! After seeking the reader, packets should be
-    // decoded and *samples* discarded up-to the exact *sample* indicated by required_ts. The
-    // current approach will discard excess samples if seeking to a sample within a packet.
-    let seek_ts = if let Some(time) = seek_time {
-        let seek_to = SeekTo::Time { time: Time::from(time), track_id: Some(track_id) };
+    // Unless seeking elsewhere, playback of a looped region starts at its start.
+    let seek_to = match (seek_time, region_ts) {
+        (Some(time), _) => Some(SeekTo::Time { time: Time::from(time), track_id: Some(track_id) }),
+        (None, Some(ts)) => Some(SeekTo::TimeStamp { ts, track_id }),
+        (None, None) => None,
+    };
 
+    // If there is a seek time, seek the reader to the time specified and get the timestamp of the
+    // seeked position. After seeking, packets are decoded and all samples with a timestamp < the
+    // seeked position are discarded.
+    let seek_ts = if let Some(seek_to) = seek_to {
         // Attempt the seek. If the seek fails, ignore the error and return a seek timestamp of 0 so
         // that no samples are trimmed.
         match reader.seek(SeekMode::Accurate, seek_to) {
             Ok(seeked_to) => seeked_to.required_ts,
             Err(Error::ResetRequired) => {
-                print_tracks(reader.tracks());
-                track_id = first_supported_track(reader.tracks()).unwrap().id;
+                if !state.output_opts.target.is_stdout() {
+                    print_tracks(reader.tracks());
+                }
+                track_id = first_supported_track(reader.tracks())
+                    .ok_or(Error::Unsupported("no supported track"))?
+                    .id;
                 0
             }
             Err(err) => {
@@ -195,50 +1285,81 @@ fn play(
         0
     };
 
-    // The audio output device.
-    let mut audio_output = None;
-    let mut display = None;
-
     let mut track_info = PlayTrackOptions { track_id, seek_ts };
 
-    let result = loop {
-        match play_track(&mut reader, &mut audio_output, &mut display, track_info, decode_opts, no_progress) {
-            Err(Error::ResetRequired) => {
+    loop {
+        match play_track(&mut reader, track_info, decode_opts, display_opts, loop_opts, state) {
+            Err(PlayerError::Symphonia(Error::ResetRequired)) => {
                 // The demuxer indicated that a reset is required. This is sometimes seen with
                 // streaming OGG (e.g., Icecast) wherein the entire contents of the container change
                 // (new tracks, codecs, metadata, etc.). Therefore, we must select a new track and
                 // recreate the decoder.
-                print_tracks(reader.tracks());
+                if !state.output_opts.target.is_stdout() {
+                    print_tracks(reader.tracks());
+                }
 
                 // Select the first supported track since the user's selected track number might no
                 // longer be valid or make sense.
-                let track_id = first_supported_track(reader.tracks()).unwrap().id;
+                let track_id = first_supported_track(reader.tracks())
+                    .ok_or(Error::Unsupported("no supported track"))?
+                    .id;
                 track_info = PlayTrackOptions { track_id, seek_ts: 0 };
             }
-            res => break res,
+            Ok(TrackEnd::Skip(offset)) => {
+                match skip_track(&mut reader, track_info.track_id, offset) {
+                    Some(skipped) => track_info = skipped,
+                    None => return Ok(TrackEnd::Skip(offset)),
+                }
+            }
+            res => return res,
         }
-    };
-
-    // Flush the audio output to finish playing back any leftover samples.
-    if let Some(audio_output) = audio_output.as_mut() {
-        audio_output.flush()
     }
+}
 
-    result
+/// Selects the supported track `offset` tracks away from the track `track_id`, and seeks to its
+/// start. Returns `None` if there is no such track.
+fn skip_track(
+    reader: &mut Box<dyn FormatReader>,
+    track_id: u32,
+    offset: i32,
+) -> Option<PlayTrackOptions> {
+    let tracks: Vec<u32> = reader
+        .tracks()
+        .iter()
+        .filter(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .map(|t| t.id)
+        .collect();
+
+    let idx = tracks.iter().position(|&id| id == track_id).unwrap_or(0) as i64;
+    let idx = usize::try_from(idx + i64::from(offset)).ok()?;
+    let track_id = *tracks.get(idx)?;
+
+    let seek_to = SeekTo::TimeStamp { ts: 0, track_id };
+
+    // If the seek fails, the new track starts playing from the current position.
+    let seek_ts = match reader.seek(SeekMode::Accurate, seek_to) {
+        Ok(seeked_to) => seeked_to.required_ts,
+        Err(err) => {
+            warn!("seek error: {}", err);
+            0
+        }
+    };
+
+    Some(PlayTrackOptions { track_id, seek_ts })
 }
 
 fn play_track(
     reader: &mut Box<dyn FormatReader>,
-    audio_output: &mut Option<Box<dyn output::AudioOutput>>,
-    display: &mut Option<Box<dyn display::Display>>,
     play_opts: PlayTrackOptions,
     decode_opts: &DecoderOptions,
-    no_progress: bool,
-) -> Result<i32> {
+    display_opts: &display::DisplayOptions,
+    loop_opts: &looper::LoopOptions,
+    state: &mut PlayerState,
+) -> Result<TrackEnd> {
     // Get the selected track using the track ID.
     let track = match reader.tracks().iter().find(|track| track.id == play_opts.track_id) {
         Some(track) => track,
-        _ => return Ok(0),
+        _ => return Ok(TrackEnd::Finished(0)),
     };
 
     // Create a decoder for the track.
@@ -248,12 +1369,147 @@ fn play_track(
     let tb = track.codec_params.time_base;
     let dur = track.codec_params.n_frames.map(|frames| track.codec_params.start_ts + frames);
 
+    // The region and the positions in it are counted from the start of the track.
+    let start_ts = track.codec_params.start_ts;
+    let loop_start_ts = loop_opts.region.map(|(a, _)| time_to_ts(a, &track.codec_params));
+
+    state.start_display_input(tb);
+
+    if let Some(progress) = state.progress.as_mut() {
+        progress.set_track(track);
+        progress.set_paused(state.paused);
+        progress.set_volume(state.volume);
+    }
+
+    // Samples with a timestamp before the seeked position are not played.
+    let mut seek_ts = play_opts.seek_ts;
+
+    // The timestamp of the last packet played.
+    let mut pos = seek_ts;
+
+    // The number of times the track is repeated.
+    let mut repeat = loop_opts.repeat;
+
+    // The looped region. It is set up with the first decoded buffer.
+    let mut ab_loop: Option<looper::AbLoop> = None;
+
+    // The signal spec the looped region was set up for.
+    let mut loop_spec: Option<SignalSpec> = None;
+
+    // A buffer for the decoded audio with the samples before the seeked position discarded.
+    let mut trim_buf: Option<AudioBuffer<f32>> = None;
+
+    // A buffer for the decoded audio spliced at the loop point.
+    let mut loop_buf: Option<AudioBuffer<f32>> = None;
+
+    // A buffer for the decoded audio with the gain applied.
+    let mut gain_buf: Option<AudioBuffer<f32>> = None;
+
     // Decode and play the packets belonging to the selected track.
     let result = loop {
+        // Handle a key press, if any. While paused, wait for one instead of decoding packets.
+        let command = match &state.controls {
+            Some(controls) if state.paused => controls.wait(PAUSE_POLL_INTERVAL),
+            Some(controls) => controls.poll(),
+            None => None,
+        };
+
+        match command {
+            Some(Command::TogglePause) => {
+                state.paused = !state.paused;
+
+                if let Some(progress) = state.progress.as_mut() {
+                    progress.set_paused(state.paused);
+                }
+            }
+            Some(Command::Seek(delta)) => {
+                // Seeking is relative to the position of the last packet played.
+                if let Some(tb) = tb {
+                    let now = tb.calc_time(pos);
+                    let mut time = (now.seconds as f64 + now.frac + delta).max(0.0);
+
+                    if let Some(dur) = dur {
+                        let end = tb.calc_time(dur);
+                        time = time.min(end.seconds as f64 + end.frac);
+                    }
+
+                    let track_id = Some(play_opts.track_id);
+                    let seek_to = SeekTo::Time { time: Time::from(time), track_id };
+
+                    match reader.seek(SeekMode::Accurate, seek_to) {
+                        Ok(seeked_to) => {
+                            // The decoder must be reset after a seek.
+                            decoder.reset();
+                            seek_ts = seeked_to.required_ts;
+                            pos = seek_ts;
+
+                            if let Some(ab_loop) = ab_loop.as_mut() {
+                                ab_loop.cancel();
+                            }
+                        }
+                        Err(Error::ResetRequired) => break Err(Error::ResetRequired.into()),
+                        Err(err) => warn!("seek error: {}", err),
+                    }
+                }
+            }
+            Some(Command::Volume(step)) => {
+                state.volume = (state.volume + step).clamp(gain::MIN_VOLUME, gain::MAX_VOLUME);
+
+                if let Some(progress) = state.progress.as_mut() {
+                    progress.set_volume(state.volume);
+                }
+            }
+            Some(Command::Skip(offset)) => return Ok(TrackEnd::Skip(offset)),
+            Some(Command::ToggleDisplay) if state.output_opts.target.is_stdout() => (),
+            Some(Command::ToggleDisplay) => {
+                state.display_hidden = !state.display_hidden;
+
+                // Hiding the display flushes it to restore the terminal. It is opened again
+                // with the next decoded buffer when shown.
+                if state.display_hidden {
+                    state.close_display()?;
+                    display::clear();
+                }
+
+                if let Some(progress) = state.progress.as_mut() {
+                    progress.redraw();
+                }
+            }
+            Some(Command::Quit) => return Ok(TrackEnd::Quit),
+            None => (),
+        }
+
+        if state.paused {
+            continue;
+        }
+
         // Get the next packet from the format reader.
         let packet = match reader.next_packet() {
             Ok(packet) => packet,
-            Err(err) => break Err(err),
+            Err(err) if is_end_of_stream(&err) && repeat.take() => {
+                // Repeat the track by seeking back to its start.
+                let seek_to = SeekTo::TimeStamp { ts: start_ts, track_id: play_opts.track_id };
+
+                match reader.seek(SeekMode::Accurate, seek_to) {
+                    Ok(seeked_to) => {
+                        decoder.reset();
+                        seek_ts = seeked_to.required_ts;
+                        pos = seek_ts;
+
+                        // The frames held back for a crossfade belong to the previous pass.
+                        if let Some(ab_loop) = ab_loop.as_mut() {
+                            ab_loop.cancel();
+                        }
+
+                        continue;
+                    }
+                    Err(err) => {
+                        warn!("seek error: {}", err);
+                        break Ok(());
+                    }
+                }
+            }
+            Err(err) => break Err(err.into()),
         };
 
         // If the packet does not belong to the selected track, skip it.
@@ -264,9 +1520,28 @@ fn play_track(
         // Decode the packet into audio samples.
         match decoder.decode(&packet) {
             Ok(decoded) => {
+                // Consecutive buffers and tracks with the same signal spec share the audio
+                // output, so that there is no gap between them. Otherwise, the audio output is
+                // reconfigured or reopened, and so is the display.
+                if let Err(err) = state.check_sinks(*decoded.spec(), decoded.capacity() as u64) {
+                    break Err(err);
+                }
+
+                // The looped region is set up with the first decoded buffer, and again if the
+                // spec changes.
+                if loop_spec != Some(*decoded.spec()) {
+                    let spec = decoded.spec();
+
+                    ab_loop = loop_opts.region.and_then(|(a, b)| {
+                        looper::AbLoop::new(a, b, spec.rate, spec.channels.count())
+                    });
 
-                // If the audio output is not open, try to open it.
-                if audio_output.is_none() {
+                    loop_spec = Some(*spec);
+                }
+
+                // If the audio output is not open, try to open it. Nothing is played back when the
+                // display renders offline.
+                if state.audio_output.is_none() && !display_opts.is_offline() {
                     // Get the audio buffer specification. This is a description of the decoded
                     // audio buffer's sample format and sample rate.
                     let spec = *decoded.spec();
@@ -277,10 +1552,12 @@ fn play_track(
                     let duration = decoded.capacity() as u64;
 
                     // Try to open the audio output.
-                    audio_output.replace(output::try_open(spec, duration).unwrap());
+                    if let Err(err) = state.open_output(spec, duration) {
+                        break Err(err);
+                    }
                 }
 
-                if display.is_none() {
+                if state.display.is_none() && !state.display_hidden {
                     // Get the audio buffer specification. This is a description of the decoded
                     // audio buffer's sample format and sample rate.
                     let spec = *decoded.spec();
@@ -290,24 +1567,98 @@ fn play_track(
                     // decoder, but the length is not.
                     let duration = decoded.capacity() as u64;
 
-                    // Try to open the audio output.
-                    display.replace(display::try_open(spec, duration).unwrap());
+                    // Try to open the display.
+                    if let Err(err) = state.open_display(spec, duration, tb, display_opts) {
+                        break Err(err);
+                    }
                 }
 
+                // Get the number of frames before the seeked position (0 if not seeking). Packets
+                // that end before the seeked position are decoded, but not played.
+                let skip = frames_before(packet.ts(), seek_ts, tb, decoded.spec().rate);
+
+                if skip == 0 || skip < decoded.frames() {
+                    // Discard the frames before the seeked position from the packet that straddles
+                    // it, so that playback starts at the exact frame seeked to.
+                    let (decoded, ts) = if skip > 0 {
+                        let buf = copy_decoded(&decoded, &mut trim_buf);
+                        buf.shift(skip);
+
+                        (AudioBufferRef::F32(Cow::Borrowed(&*buf)), seek_ts)
+                    }
+                    else {
+                        (decoded, packet.ts())
+                    };
+
+                    // Splice the end of the looped region onto its start.
+                    let mut loop_end = false;
+
+                    let decoded = match ab_loop.as_mut() {
+                        Some(ab_loop) => {
+                            // Get the index of the buffer's first frame.
+                            let frame = frames_before(start_ts, ts, tb, decoded.spec().rate);
+                            let frame = frame as u64;
+
+                            let buf = copy_decoded(&decoded, &mut loop_buf);
+                            loop_end = ab_loop.process(buf, frame);
+
+                            AudioBufferRef::F32(Cow::Borrowed(&*buf))
+                        }
+                        None => decoded,
+                    };
 
-                // Write the decoded audio samples to the audio output if the presentation timestamp
-                // for the packet is >= the seeked position (0 if not seeking).
-                if packet.ts() >= play_opts.seek_ts {
-                    if !no_progress {
-                        // print_progress(packet.ts(), dur, tb);
+                    pos = ts;
+
+                    if let Some(progress) = state.progress.as_mut() {
+                        progress.update(&packet);
+                    }
+
+                    if state.audio_output.is_some() {
+                        // The user volume and ReplayGain are applied to the audio output only, so
+                        // the display shows the decoded signal.
+                        let gain = gain::db_to_linear(state.volume + state.replaygain);
+
+                        let output_buf = if gain != 1.0 {
+                            // The decoded buffer is borrowed from the decoder, so copy it to apply
+                            // the gain.
+                            let buf = copy_decoded(&decoded, &mut gain_buf);
+                            buf.transform(|sample| sample * gain);
+
+                            AudioBufferRef::F32(Cow::Borrowed(&*buf))
+                        }
+                        else {
+                            decoded.clone()
+                        };
+
+                        if let Err(err) = state.write_output(output_buf) {
+                            break Err(err);
+                        }
                     }
 
-                    if let Some(audio_output) = audio_output {
-                        audio_output.write(decoded.clone()).unwrap()
+                    if let Err(err) = state.write_display(decoded, ts) {
+                        break Err(err);
                     }
 
-                    if let Some(display) = display {
-                        display.write(decoded).unwrap()
+                    // Seek back to the start of the looped region once its end has been played.
+                    let loop_to = ab_loop.as_mut().zip(loop_start_ts).filter(|_| loop_end);
+
+                    if let Some((ab_loop, ts)) = loop_to {
+                        let seek_to = SeekTo::TimeStamp { ts, track_id: play_opts.track_id };
+
+                        match reader.seek(SeekMode::Accurate, seek_to) {
+                            Ok(seeked_to) => {
+                                decoder.reset();
+                                seek_ts = seeked_to.required_ts;
+                                pos = seek_ts;
+                                ab_loop.restart();
+                            }
+                            Err(Error::ResetRequired) => break Err(Error::ResetRequired.into()),
+                            Err(err) => {
+                                // Play on past the end of the region.
+                                warn!("seek error: {}", err);
+                                ab_loop.cancel();
+                            }
+                        }
                     }
                 }
             }
@@ -316,19 +1667,64 @@ fn play_track(
                 // packet as usual.
                 warn!("decode error: {}", err);
             }
-            Err(err) => break Err(err),
+            Err(err) => break Err(err.into()),
         }
     };
 
-    if !no_progress {
-        println!();
-    }
-
     // Return if a fatal error occured.
     ignore_end_of_stream_error(result)?;
 
     // Finalize the decoder and return the verification result if it's been enabled.
-    do_verification(decoder.finalize())
+    let to_stderr = state.output_opts.target.is_stdout();
+    do_verification(decoder.finalize(), to_stderr).map(TrackEnd::Finished)
+}
+
+/// Gets the number of frames at the start of a decoded buffer, whose first frame has the timestamp
+/// `ts`, that precede the timestamp `seek_ts`.
+fn frames_before(ts: u64, seek_ts: u64, tb: Option<TimeBase>, rate: u32) -> usize {
+    let delta = seek_ts.saturating_sub(ts);
+
+    match tb {
+        // Timestamps are usually in units of frames, but some containers use a coarser time base.
+        Some(tb) => {
+            let frames = u128::from(delta) * u128::from(tb.numer) * u128::from(rate)
+                / u128::from(tb.denom);
+
+            frames as usize
+        }
+        None => delta as usize,
+    }
+}
+
+/// Converts a time, in seconds from the start of a track, to a timestamp of the track.
+fn time_to_ts(time: f64, params: &CodecParameters) -> u64 {
+    let time = time.max(0.0);
+
+    let ts = match (params.time_base, params.sample_rate) {
+        (Some(tb), _) => tb.calc_timestamp(Time::from(time)),
+        // Without a time base, timestamps are taken to be in frames.
+        (None, Some(rate)) => (time * f64::from(rate)).round() as u64,
+        (None, None) => 0,
+    };
+
+    params.start_ts + ts
+}
+
+/// Copies a decoded buffer into `buf`, which is reallocated if it cannot hold the decoded buffer.
+fn copy_decoded<'a>(
+    decoded: &AudioBufferRef<'_>,
+    buf: &'a mut Option<AudioBuffer<f32>>,
+) -> &'a mut AudioBuffer<f32> {
+    let spec = *decoded.spec();
+    let duration = decoded.capacity() as u64;
+
+    if buf.as_ref().is_some_and(|buf| *buf.spec() != spec || (buf.capacity() as u64) < duration) {
+        *buf = None;
+    }
+
+    let buf = buf.get_or_insert_with(|| AudioBuffer::new(duration, spec));
+    decoded.convert(buf);
+    buf
 }
 
 fn first_supported_track(tracks: &[Track]) -> Option<&Track> {
@@ -337,10 +1733,7 @@ fn first_supported_track(tracks: &[Track]) -> Option<&Track> {
 
 fn ignore_end_of_stream_error(result: Result<()>) -> Result<()> {
     match result {
-        Err(Error::IoError(err))
-            if err.kind() == std::io::ErrorKind::UnexpectedEof
-                && err.to_string() == "end of stream" =>
-        {
+        Err(PlayerError::Symphonia(err)) if is_end_of_stream(&err) => {
             // Do not treat "end of stream" as a fatal error. It's the currently only way a
             // format reader can indicate the media is complete.
             Ok(())
@@ -349,11 +1742,28 @@ fn ignore_end_of_stream_error(result: Result<()>) -> Result<()> {
     }
 }
 
-fn do_verification(finalization: FinalizeResult) -> Result<i32> {
+fn is_end_of_stream(err: &Error) -> bool {
+    match err {
+        Error::IoError(err) => {
+            err.kind() == std::io::ErrorKind::UnexpectedEof && err.to_string() == "end of stream"
+        }
+        _ => false,
+    }
+}
+
+/// Prints the verification result, to standard error if `to_stderr` is set.
+fn do_verification(finalization: FinalizeResult, to_stderr: bool) -> Result<i32> {
     match finalization.verify_ok {
         Some(is_ok) => {
             // Got a verification result.
-            println!("verification: {}", if is_ok { "passed" } else { "failed" });
+            let result = if is_ok { "passed" } else { "failed" };
+
+            if to_stderr {
+                eprintln!("verification: {}", result);
+            }
+            else {
+                println!("verification: {}", result);
+            }
 
             Ok(i32::from(!is_ok))
         }
@@ -446,58 +1856,366 @@ fn fmt_time(ts: u64, tb: TimeBase) -> String {
     format!("{}:{:0>2}:{:0>6.3}", hours, mins, secs)
 }
 
-fn print_progress(ts: u64, dur: Option<u64>, tb: Option<TimeBase>) {
-    // Get a string slice containing a progress bar.
-    fn progress_bar(ts: u64, dur: u64) -> &'static str {
-        const NUM_STEPS: usize = 60;
+#[cfg(test)]
+mod tests {
+    use symphonia::core::audio::SampleBuffer;
+
+    use super::*;
+    use crate::testutil::temp_path;
+
+    const RATE: u32 = 44100;
+
+    const STEREO: Channels = Channels::FRONT_LEFT.union(Channels::FRONT_RIGHT);
+
+    /// Writes `frames` frames of 16-bit audio with the given spec to a WAV or FLAC file, chosen by
+    /// the extension of `path`, and returns the interleaved samples written. The first channel is
+    /// noise, and the others are ramps.
+    fn write_fixture(path: &Path, spec: SignalSpec, frames: usize) -> Vec<f32> {
+        let opts = output::OutputOptions {
+            target: output::OutputTarget::File(path.to_path_buf()),
+            format: Some(output::SampleFormat::S16),
+            ..Default::default()
+        };
+
+        let mut audio_output = output::try_open(spec, 1000, &opts).unwrap();
+
+        // The samples are multiples of the 16-bit step, so they are stored exactly.
+        let mut state = 1u32;
+        let mut samples = Vec::with_capacity(spec.channels.count() * frames);
+
+        for chunk in (0..frames).collect::<Vec<_>>().chunks(1000) {
+            let mut buf = AudioBuffer::<f32>::new(1000, spec);
+            buf.render_reserved(Some(chunk.len()));
+
+            for (i, &frame) in chunk.iter().enumerate() {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+
+                for c in 0..spec.channels.count() {
+                    let sample = match c {
+                        0 => f32::from((state >> 16) as i16) / 32768.0,
+                        _ => (frame % 200 * c) as f32 / 32768.0,
+                    };
+
+                    buf.chan_mut(c)[i] = sample;
+                    samples.push(sample);
+                }
+            }
+
+            audio_output.write(AudioBufferRef::F32(Cow::Borrowed(&buf))).unwrap();
+        }
+
+        audio_output.flush();
+        samples
+    }
+
+    /// Decodes a file from `seek` seconds, trimming the packet that straddles the seeked position
+    /// as playback does. Returns the seeked timestamp and the interleaved samples.
+    fn decode_from(path: &Path, seek: Option<f64>) -> (u64, Vec<f32>) {
+        let mut reader = open_input(path, &Default::default()).unwrap().format;
+
+        let track = first_supported_track(reader.tracks()).unwrap();
+        let track_id = track.id;
+        let tb = track.codec_params.time_base;
+
+        let codecs = symphonia::default::get_codecs();
+        let mut decoder = codecs.make(&track.codec_params, &Default::default()).unwrap();
+
+        let seek_ts = match seek {
+            Some(time) => {
+                let seek_to = SeekTo::Time { time: Time::from(time), track_id: Some(track_id) };
+                reader.seek(SeekMode::Accurate, seek_to).unwrap().required_ts
+            }
+            None => 0,
+        };
+
+        let mut trim_buf = None;
+        let mut samples = Vec::new();
+
+        loop {
+            let packet = match reader.next_packet() {
+                Ok(packet) => packet,
+                Err(err) if is_end_of_stream(&err) => break,
+                Err(err) => panic!("{}", err),
+            };
+
+            let decoded = decoder.decode(&packet).unwrap();
+            let skip = frames_before(packet.ts(), seek_ts, tb, decoded.spec().rate);
+
+            if skip > 0 && skip >= decoded.frames() {
+                continue;
+            }
+
+            let decoded = if skip > 0 {
+                let buf = copy_decoded(&decoded, &mut trim_buf);
+                buf.shift(skip);
 
-        lazy_static! {
-            static ref PROGRESS_BAR: Vec<String> = {
-                (0..NUM_STEPS + 1).map(|i| format!("[{:<60}]", str::repeat("■", i))).collect()
+                AudioBufferRef::F32(Cow::Borrowed(&*buf))
+            }
+            else {
+                decoded
             };
+
+            let duration = decoded.capacity() as u64;
+
+            let mut sample_buf = SampleBuffer::<f32>::new(duration, *decoded.spec());
+            sample_buf.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(sample_buf.samples());
+        }
+
+        (seek_ts, samples)
+    }
+
+    /// Checks that decoding after a seek produces the same samples as decoding the whole file and
+    /// slicing it at the seeked frame.
+    fn check_seek(name: &str) {
+        let path = temp_path(name);
+        let spec = SignalSpec::new(RATE, STEREO);
+        let written = write_fixture(&path, spec, 3 * RATE as usize + 123);
+
+        let (_, whole) = decode_from(&path, None);
+        assert_eq!(whole, written);
+
+        // Seek into the middle of packets, to a packet boundary, and into the last packet.
+        for seek in [0.25, 1.0, 1.337, 4096.0 / f64::from(RATE), 3.002] {
+            let (seek_ts, samples) = decode_from(&path, Some(seek));
+
+            assert_eq!(seek_ts, (seek * f64::from(RATE)) as u64, "seek to {}", seek);
+            assert!(samples == whole[2 * seek_ts as usize..], "seek to {}", seek);
         }
 
-        let i = (NUM_STEPS as u64)
-            .saturating_mul(ts)
-            .checked_div(dur)
-            .unwrap_or(0)
-            .clamp(0, NUM_STEPS as u64);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn seek_trims_wav() {
+        check_seek("seek.wav");
+    }
+
+    #[test]
+    fn seek_trims_flac() {
+        check_seek("seek.flac");
+    }
+
+    #[test]
+    fn frames_before_converts_time_base() {
+        // Timestamps in frames.
+        let tb = Some(TimeBase::new(1, 48000));
+        assert_eq!(frames_before(1000, 1500, tb, 48000), 500);
+        assert_eq!(frames_before(1500, 1000, tb, 48000), 0);
 
-        &PROGRESS_BAR[i as usize]
+        // Timestamps in milliseconds.
+        let tb = Some(TimeBase::new(1, 1000));
+        assert_eq!(frames_before(2000, 2010, tb, 48000), 480);
+
+        // Without a time base, timestamps are taken to be in frames.
+        assert_eq!(frames_before(10, 25, None, 44100), 15);
     }
 
-    // Multiple print! calls would need to be made to print the progress, so instead, only lock
-    // stdout once and use write! rather then print!.
-    let stdout = std::io::stdout();
-    let mut output = stdout.lock();
+    #[test]
+    fn validators_reject_invalid_values() {
+        assert!(is_number::<f64>("-1.5").is_ok());
+        assert!(is_number::<f64>("foo").is_err());
+        assert!(is_number::<usize>("-1").is_err());
+
+        assert!(is_positive("48000").is_ok());
+        assert!(is_positive("0").is_err());
+        assert!(is_positive("1.5").is_err());
 
-    if let Some(tb) = tb {
-        let t = tb.calc_time(ts);
+        assert!(is_overlap("0").is_ok());
+        assert!(is_overlap("75").is_ok());
+        assert!(is_overlap("100").is_err());
 
-        let hours = t.seconds / (60 * 60);
-        let mins = (t.seconds % (60 * 60)) / 60;
-        let secs = f64::from((t.seconds % 60) as u32) + t.frac;
+        assert!(is_repeat("3").is_ok());
+        assert!(is_repeat("INF").is_ok());
+        assert!(is_repeat("foo").is_err());
 
-        write!(output, "\r\u{25b6}\u{fe0f}  {}:{:0>2}:{:0>4.1}", hours, mins, secs).unwrap();
+        assert!(is_matrix("1,0;0,1").is_ok());
+        assert!(is_matrix("1;1;1;1;1;1;1;1;1").is_err());
+        assert!(is_matrix("1,0;1").is_err());
+    }
+
+    #[test]
+    fn time_to_ts_counts_from_track_start() {
+        let mut params = CodecParameters::new();
+        params.with_sample_rate(44100).with_time_base(TimeBase::new(1, 1000)).with_start_ts(500);
 
-        if let Some(dur) = dur {
-            let d = tb.calc_time(dur.saturating_sub(ts));
+        assert_eq!(time_to_ts(0.0, &params), 500);
+        assert_eq!(time_to_ts(1.25, &params), 1750);
+        assert_eq!(time_to_ts(-1.0, &params), 500);
+
+        // Without a time base, timestamps are taken to be in frames.
+        params.time_base = None;
+        assert_eq!(time_to_ts(0.5, &params), 500 + 22050);
+    }
 
-            let hours = d.seconds / (60 * 60);
-            let mins = (d.seconds % (60 * 60)) / 60;
-            let secs = f64::from((d.seconds % 60) as u32) + d.frac;
+    /// Plays inputs with the given specs and lengths in turn into an offline display, as a chained
+    /// stream whose spec changes at every link would be. Returns the result of the first input
+    /// that fails, or of finishing playback.
+    fn play_offline(
+        name: &str,
+        inputs: &[(SignalSpec, usize)],
+        opts: &display::DisplayOptions,
+    ) -> Result<()> {
+        let mut state = PlayerState::new(opts, &Default::default(), true);
+        play_inputs(name, inputs, opts, &mut state)
+    }
 
-            write!(output, " {} -{}:{:0>2}:{:0>4.1}", progress_bar(ts, dur), hours, mins, secs)
-                .unwrap();
+    /// Plays generated inputs with the given specs and lengths in turn, as the inputs given on the
+    /// command line are, and finishes playback.
+    fn play_inputs(
+        name: &str,
+        inputs: &[(SignalSpec, usize)],
+        opts: &display::DisplayOptions,
+        state: &mut PlayerState,
+    ) -> Result<()> {
+        let mut result = Ok(());
+
+        for (i, &(spec, frames)) in inputs.iter().enumerate() {
+            let path = temp_path(&format!("{}-{}.wav", name, i));
+            write_fixture(&path, spec, frames);
+
+            let reader = open_input(&path, &Default::default()).unwrap().format;
+            let (decode_opts, loop_opts) = (Default::default(), Default::default());
+            let played = play(reader, None, None, &decode_opts, opts, &loop_opts, state);
+            std::fs::remove_file(&path).unwrap();
+
+            if let Err(err) = played {
+                result = Err(err);
+                break;
+            }
         }
+
+        let finished = state.finish();
+        result.and(finished)
     }
-    else {
-        write!(output, "\r\u{25b6}\u{fe0f}  {}", ts).unwrap();
+
+    #[test]
+    fn file_output_converts_inputs_with_changing_specs() {
+        let path = temp_path("multi-spec-output.wav");
+        let display_opts = Default::default();
+
+        let mut state = PlayerState::new(&display_opts, &Default::default(), true);
+        state.controls = None;
+        state.display_hidden = true;
+        state.output_opts = output::OutputOptions {
+            target: output::OutputTarget::File(path.clone()),
+            ..Default::default()
+        };
+
+        // The file is opened with the spec of the first input. The second input is mixed and
+        // resampled to it, and the third is mixed down at the same rate.
+        let stereo = SignalSpec::new(RATE, STEREO);
+        let mono = SignalSpec::new(48000, Channels::FRONT_LEFT);
+        let surround = SignalSpec::new(RATE, mixer::layout(6).unwrap());
+        let inputs = [(stereo, 30_000), (mono, 24_000), (surround, 10_000)];
+
+        play_inputs("output-multi-spec", &inputs, &display_opts, &mut state).unwrap();
+
+        let reader = open_input(&path, &Default::default()).unwrap().format;
+        let params = &reader.tracks()[0].codec_params;
+
+        assert_eq!(params.sample_rate, Some(RATE));
+        assert_eq!(params.channels, Some(STEREO));
+
+        let (_, samples) = decode_from(&path, None);
+        std::fs::remove_file(&path).unwrap();
+
+        // The inputs follow each other without a gap, and the resampled input is exactly as long
+        // as it is at the new rate.
+        let frames: Vec<&[f32]> = samples.chunks_exact(2).collect();
+        assert_eq!(frames.len(), 30_000 + 22_050 + 10_000);
+
+        // The first input is written as it is.
+        let fixture = temp_path("output-multi-spec-fixture.wav");
+        let expected = write_fixture(&fixture, stereo, 30_000);
+        std::fs::remove_file(&fixture).unwrap();
+
+        assert_eq!(&samples[..60_000], &expected[..]);
+
+        // The mono input is played on both channels.
+        assert!(frames[30_000..52_050].iter().all(|frame| frame[0] == frame[1]));
+        assert!(frames[30_000..52_050].iter().any(|frame| frame[0] != 0.0));
+    }
+
+    fn export_options(path: &Path, per_channel: bool) -> display::DisplayOptions {
+        let export = display::ExportOptions {
+            path: path.to_path_buf(),
+            format: display::ExportFormat::Csv,
+            scale: display::ExportScale::Magnitude,
+            per_channel,
+        };
+
+        display::DisplayOptions { export: Some(export), ..Default::default() }
+    }
+
+    #[test]
+    fn offline_export_continues_across_specs() {
+        let path = temp_path("multi-spec.csv");
+        let opts = export_options(&path, false);
+
+        let mono = SignalSpec::new(RATE, Channels::FRONT_LEFT);
+        let surround = SignalSpec::new(RATE, mixer::layout(6).unwrap());
+        let inputs = [(mono, 30_000), (SignalSpec::new(RATE, STEREO), 20_000), (surround, 10_000)];
+
+        play_offline("multi-spec", &inputs, &opts).unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The file is written once, with one header, and the downmix of every input is analysed
+        // as one stream.
+        let lines: Vec<&str> = csv.lines().collect();
+        let (window, hop) = (opts.stft.window_size, opts.stft.hop_size);
+
+        assert!(lines[0].starts_with("time,channel,"));
+        assert_eq!(lines.iter().filter(|line| line.starts_with("time")).count(), 1);
+        assert_eq!(lines.len() - 1, (60_000 - window) / hop + 1);
+
+        // The times of each input continue from the end of the previous input, so every row is
+        // one hop after the previous row.
+        for (i, line) in lines[1..].iter().enumerate() {
+            let time = line.split(',').next().unwrap().parse::<f64>().unwrap();
+            assert!((time - (i * hop) as f64 / f64::from(RATE)).abs() < 1e-6, "row {}", i);
+        }
+    }
+
+    #[test]
+    fn offline_image_accepts_channel_change() {
+        let path = temp_path("multi-spec.png");
+        let image =
+            display::ImageOptions { path: path.clone(), width: 200, height: 100, labels: true };
+        let opts = display::DisplayOptions { image: Some(image), ..Default::default() };
+
+        let mono = SignalSpec::new(RATE, Channels::FRONT_LEFT);
+        let inputs = [(SignalSpec::new(RATE, STEREO), 20_000), (mono, 20_000)];
+        play_offline("multi-spec-image", &inputs, &opts).unwrap();
+
+        let png = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let decoder = png::Decoder::new(png.as_slice());
+        let info = decoder.read_info().unwrap().info().clone();
+
+        assert_eq!((info.width, info.height), (200, 100));
     }
 
-    // This extra space is a workaround for Konsole to correctly erase the previous line.
-    write!(output, " ").unwrap();
+    #[test]
+    fn offline_export_rejects_rate_change() {
+        let path = temp_path("rate-change.csv");
+
+        let stereo = SignalSpec::new(RATE, STEREO);
+        let inputs = [(stereo, 10_000), (SignalSpec::new(48_000, STEREO), 10_000)];
+        let result = play_offline("rate-change", &inputs, &export_options(&path, false));
+
+        assert!(matches!(result, Err(PlayerError::Display(_))));
 
-    // Flush immediately since stdout is buffered.
-    output.flush().unwrap();
-}
\ No newline at end of file
+        // A per-channel export cannot change its number of channels.
+        let mono = SignalSpec::new(RATE, Channels::FRONT_LEFT);
+        let inputs = [(SignalSpec::new(RATE, STEREO), 10_000), (mono, 10_000)];
+        let result = play_offline("channel-change", &inputs, &export_options(&path, true));
+
+        assert!(matches!(result, Err(PlayerError::Display(_))));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
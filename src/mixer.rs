@@ -0,0 +1,425 @@
+// Symphonia
+// Copyright (c) 2019-2022 The Project Symphonia Developers.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Channel Mixing
+
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Channels, Signal, SignalSpec};
+
+/// A mixing matrix. Each row holds the gain of every input channel in one output channel. Rows
+/// and columns are in the order of the channel bits.
+pub type Matrix = Vec<Vec<f32>>;
+
+/// Channels with the same role, in order of preference. A channel the output does not have is
+/// merged into the first channel of its group that the output has.
+const GROUPS: &[&[Channels]] = &[
+    &[
+        Channels::FRONT_LEFT,
+        Channels::FRONT_LEFT_CENTRE,
+        Channels::FRONT_LEFT_WIDE,
+        Channels::FRONT_LEFT_HIGH,
+        Channels::TOP_FRONT_LEFT,
+    ],
+    &[
+        Channels::FRONT_RIGHT,
+        Channels::FRONT_RIGHT_CENTRE,
+        Channels::FRONT_RIGHT_WIDE,
+        Channels::FRONT_RIGHT_HIGH,
+        Channels::TOP_FRONT_RIGHT,
+    ],
+    &[
+        Channels::FRONT_CENTRE,
+        Channels::FRONT_CENTRE_HIGH,
+        Channels::TOP_FRONT_CENTRE,
+        Channels::TOP_CENTRE,
+    ],
+    &[
+        Channels::SIDE_LEFT,
+        Channels::REAR_LEFT,
+        Channels::REAR_LEFT_CENTRE,
+        Channels::TOP_REAR_LEFT,
+    ],
+    &[
+        Channels::SIDE_RIGHT,
+        Channels::REAR_RIGHT,
+        Channels::REAR_RIGHT_CENTRE,
+        Channels::TOP_REAR_RIGHT,
+    ],
+    &[Channels::REAR_CENTRE, Channels::TOP_REAR_CENTRE],
+    &[Channels::LFE1, Channels::LFE2],
+];
+
+const FRONT_LEFT: usize = 0;
+const FRONT_RIGHT: usize = 1;
+const FRONT_CENTRE: usize = 2;
+const SURROUND_LEFT: usize = 3;
+const SURROUND_RIGHT: usize = 4;
+const REAR_CENTRE: usize = 5;
+
+/// Gets the groups, and their gains, that a group the output has no channel of is folded into.
+/// The gains are those of ITU-R BS.775. The low frequency channels are dropped.
+fn fold(group: usize) -> &'static [(usize, f32)] {
+    match group {
+        FRONT_LEFT | FRONT_RIGHT => &[(FRONT_CENTRE, FRAC_1_SQRT_2)],
+        FRONT_CENTRE => &[(FRONT_LEFT, FRAC_1_SQRT_2), (FRONT_RIGHT, FRAC_1_SQRT_2)],
+        SURROUND_LEFT => &[(FRONT_LEFT, FRAC_1_SQRT_2)],
+        SURROUND_RIGHT => &[(FRONT_RIGHT, FRAC_1_SQRT_2)],
+        REAR_CENTRE => &[(SURROUND_LEFT, FRAC_1_SQRT_2), (SURROUND_RIGHT, FRAC_1_SQRT_2)],
+        _ => &[],
+    }
+}
+
+/// Gets the standard layout with the given number of channels.
+pub fn layout(count: usize) -> Option<Channels> {
+    let front = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+    let rear = Channels::REAR_LEFT | Channels::REAR_RIGHT;
+    let side = Channels::SIDE_LEFT | Channels::SIDE_RIGHT;
+
+    let channels = match count {
+        1 => Channels::FRONT_LEFT,
+        2 => front,
+        3 => front | Channels::FRONT_CENTRE,
+        4 => front | rear,
+        5 => front | Channels::FRONT_CENTRE | rear,
+        6 => front | Channels::FRONT_CENTRE | Channels::LFE1 | rear,
+        7 => front | Channels::FRONT_CENTRE | Channels::LFE1 | Channels::REAR_CENTRE | side,
+        8 => front | Channels::FRONT_CENTRE | Channels::LFE1 | rear | side,
+        _ => return None,
+    };
+
+    Some(channels)
+}
+
+/// Parses a matrix such as `1,0,0.707;0,1,0.707`, with rows separated by semicolons and the gains
+/// in each row separated by commas. All rows must have the same number of gains.
+pub fn parse_matrix(value: &str) -> Option<Matrix> {
+    let matrix = value
+        .split(';')
+        .map(|row| row.split(',').map(|gain| gain.trim().parse::<f32>().ok()).collect())
+        .collect::<Option<Matrix>>()?;
+
+    let columns = matrix.first()?.len();
+
+    if columns == 0 || matrix.iter().any(|row| row.len() != columns) {
+        return None;
+    }
+
+    Some(matrix)
+}
+
+/// Builds the matrix that mixes the channels `input` into the channels `output`.
+///
+/// Channels the output has are copied. Other channels are merged into a channel with the same
+/// role, such as a side channel into a rear channel. Channels merged into the same output channel
+/// are scaled to keep their power, and are attenuated by a further 3 dB if the input also has
+/// that channel, which is copied at unity gain as in ITU-R BS.775. If the output has no channel
+/// with the same role, the channel is folded into the front channels with the gains of BS.775.
+pub fn standard_matrix(input: Channels, output: Channels) -> Matrix {
+    let in_channels: Vec<Channels> = input.iter().collect();
+    let out_channels: Vec<Channels> = output.iter().collect();
+
+    let mut matrix = vec![vec![0.0; in_channels.len()]; out_channels.len()];
+
+    if input == output {
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+    }
+    else if out_channels.len() == 1 {
+        // Mix to mono by averaging the left and right channels of a stereo mix.
+        let stereo = standard_matrix(input, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+
+        for (c, gain) in matrix[0].iter_mut().enumerate() {
+            *gain = 0.5 * (stereo[0][c] + stereo[1][c]);
+        }
+    }
+    else if in_channels.len() == 1 {
+        // Play mono on the centre channel if there is one, otherwise on both front channels.
+        for (o, &channel) in out_channels.iter().enumerate() {
+            let is_target = if output.contains(Channels::FRONT_CENTRE) {
+                channel == Channels::FRONT_CENTRE
+            }
+            else {
+                channel == Channels::FRONT_LEFT || channel == Channels::FRONT_RIGHT
+            };
+
+            if is_target {
+                matrix[o][0] = 1.0;
+            }
+        }
+    }
+    else {
+        for (c, &channel) in in_channels.iter().enumerate() {
+            if let Some(o) = out_channels.iter().position(|&out| out == channel) {
+                matrix[o][c] = 1.0;
+            }
+        }
+
+        for (group, members) in GROUPS.iter().enumerate() {
+            // The input channels of the group that the output does not have.
+            let missing: Vec<usize> = in_channels
+                .iter()
+                .enumerate()
+                .filter(|&(_, &channel)| members.contains(&channel) && !output.contains(channel))
+                .map(|(c, _)| c)
+                .collect();
+
+            if missing.is_empty() {
+                continue;
+            }
+
+            match members.iter().position(|&member| output.contains(member)) {
+                Some(m) => {
+                    // Merge the missing channels into the target channel. If the input has the
+                    // target channel, it was copied above and stays at unity gain.
+                    let target = members[m];
+                    let o = out_channels.iter().position(|&out| out == target).unwrap();
+
+                    let mut gain = 1.0 / (missing.len() as f32).sqrt();
+
+                    if input.contains(target) {
+                        gain *= FRAC_1_SQRT_2;
+                    }
+
+                    for &c in &missing {
+                        matrix[o][c] = gain;
+                    }
+                }
+                None => {
+                    let gain = 1.0 / (missing.len() as f32).sqrt();
+
+                    for &c in &missing {
+                        route(group, gain, 0, output, &out_channels, &mut matrix, c);
+                    }
+                }
+            }
+        }
+    }
+
+    matrix
+}
+
+/// Routes the input channel `c`, with a gain, from a group the output has no channel of into the
+/// groups it folds into.
+fn route(
+    group: usize,
+    gain: f32,
+    depth: usize,
+    output: Channels,
+    out_channels: &[Channels],
+    matrix: &mut Matrix,
+    c: usize,
+) {
+    // Folding never needs more than a couple of steps, but guard against cycles.
+    if depth > GROUPS.len() {
+        return;
+    }
+
+    for &(into, fold_gain) in fold(group) {
+        match GROUPS[into].iter().find(|&&member| output.contains(member)) {
+            Some(&target) => {
+                let o = out_channels.iter().position(|&out| out == target).unwrap();
+                matrix[o][c] += gain * fold_gain;
+            }
+            None => route(into, gain * fold_gain, depth + 1, output, out_channels, matrix, c),
+        }
+    }
+}
+
+/// Mixes the channels of a stream of audio buffers into another set of channels.
+pub struct ChannelMixer {
+    matrix: Matrix,
+    /// The input buffer converted to `f32`.
+    in_buf: Option<AudioBuffer<f32>>,
+    /// The mixed output.
+    out_buf: AudioBuffer<f32>,
+}
+
+impl ChannelMixer {
+    /// Creates a mixer for audio with the given spec into the channels `output`, with a matrix
+    /// that has a row for every output channel and a column for every input channel.
+    pub fn new(spec: SignalSpec, output: Channels, matrix: Matrix) -> Self {
+        let out_spec = SignalSpec::new(spec.rate, output);
+
+        ChannelMixer { matrix, in_buf: None, out_buf: AudioBuffer::new(0, out_spec) }
+    }
+
+    /// Mixes a buffer.
+    pub fn process(&mut self, input: &AudioBufferRef<'_>) -> &AudioBuffer<f32> {
+        let spec = *input.spec();
+        let duration = input.capacity() as u64;
+
+        if self.in_buf.as_ref().map_or(true, |buf| (buf.capacity() as u64) < duration) {
+            self.in_buf = Some(AudioBuffer::new(duration, spec));
+        }
+
+        let in_buf = self.in_buf.as_mut().unwrap();
+        input.convert(in_buf);
+
+        if (self.out_buf.capacity() as u64) < duration {
+            self.out_buf = AudioBuffer::new(duration, *self.out_buf.spec());
+        }
+
+        self.out_buf.clear();
+        self.out_buf.render_reserved(Some(in_buf.frames()));
+
+        for (o, row) in self.matrix.iter().enumerate() {
+            let out = self.out_buf.chan_mut(o);
+            out.fill(0.0);
+
+            for (c, &gain) in row.iter().enumerate().filter(|&(_, &gain)| gain != 0.0) {
+                for (out, &sample) in out.iter_mut().zip(in_buf.chan(c)) {
+                    *out += gain * sample;
+                }
+            }
+        }
+
+        &self.out_buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::borrow::Cow;
+
+    const G: f32 = FRAC_1_SQRT_2;
+
+    fn assert_matrix(actual: &Matrix, expected: &[&[f32]]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+
+        for (row, expected) in actual.iter().zip(expected) {
+            assert_eq!(row.len(), expected.len(), "{:?}", actual);
+
+            for (gain, expected) in row.iter().zip(expected.iter()) {
+                assert!((gain - expected).abs() < 1e-6, "{:?} != {:?}", actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn standard_matrix_copies_same_layout() {
+        let channels = layout(6).unwrap();
+        let matrix = standard_matrix(channels, channels);
+
+        for (o, row) in matrix.iter().enumerate() {
+            for (c, &gain) in row.iter().enumerate() {
+                assert_eq!(gain, if c == o { 1.0 } else { 0.0 });
+            }
+        }
+    }
+
+    #[test]
+    fn standard_matrix_stereo_to_mono() {
+        let matrix = standard_matrix(layout(2).unwrap(), layout(1).unwrap());
+        assert_matrix(&matrix, &[&[0.5, 0.5]]);
+    }
+
+    #[test]
+    fn standard_matrix_mono_to_stereo_and_surround() {
+        let matrix = standard_matrix(layout(1).unwrap(), layout(2).unwrap());
+        assert_matrix(&matrix, &[&[1.0], &[1.0]]);
+
+        // Mono is played on the centre channel only.
+        let matrix = standard_matrix(layout(1).unwrap(), layout(6).unwrap());
+        assert_matrix(&matrix, &[&[0.0], &[0.0], &[1.0], &[0.0], &[0.0], &[0.0]]);
+    }
+
+    #[test]
+    fn standard_matrix_5_1_to_stereo() {
+        // The inputs are FL, FR, FC, LFE, RL and RR. The centre and rear channels are folded into
+        // the front channels at -3 dB, and the LFE channel is dropped.
+        let matrix = standard_matrix(layout(6).unwrap(), layout(2).unwrap());
+
+        assert_matrix(&matrix, &[&[1.0, 0.0, G, 0.0, G, 0.0], &[0.0, 1.0, G, 0.0, 0.0, G]]);
+
+        // Down to mono, the left and right channels are averaged.
+        let matrix = standard_matrix(layout(6).unwrap(), layout(1).unwrap());
+        let h = 0.5 * G;
+
+        assert_matrix(&matrix, &[&[0.5, 0.5, G, 0.0, h, h]]);
+    }
+
+    #[test]
+    fn standard_matrix_7_1_to_5_1() {
+        // The inputs are FL, FR, FC, LFE, RL, RR, SL and SR. The rear channels are kept at unity
+        // gain, and the side channels are merged into them at -3 dB.
+        let matrix = standard_matrix(layout(8).unwrap(), layout(6).unwrap());
+
+        assert_matrix(
+            &matrix,
+            &[
+                &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                &[0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+                &[0.0, 0.0, 0.0, 0.0, 1.0, 0.0, G, 0.0],
+                &[0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, G],
+            ],
+        );
+    }
+
+    #[test]
+    fn standard_matrix_renames_missing_channels() {
+        // Without the rear channels in the input, the side channels replace them at unity gain.
+        let side = Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::LFE1
+            | Channels::SIDE_LEFT
+            | Channels::SIDE_RIGHT;
+
+        let matrix = standard_matrix(side, layout(6).unwrap());
+
+        assert_matrix(
+            &matrix,
+            &[
+                &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                &[0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+                &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+                &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+                &[0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+                &[0.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_matrix_rows_and_gains() {
+        let expected = vec![vec![1.0, 0.0, 0.707], vec![0.0, 1.0, 0.707]];
+        assert_eq!(parse_matrix("1,0,0.707;0,1,0.707"), Some(expected));
+        assert_eq!(parse_matrix(" 0.5 , 0.5 "), Some(vec![vec![0.5, 0.5]]));
+        assert_eq!(parse_matrix("-1;2"), Some(vec![vec![-1.0], vec![2.0]]));
+
+        assert_eq!(parse_matrix(""), None);
+        assert_eq!(parse_matrix("1,0;1"), None);
+        assert_eq!(parse_matrix("1,,0"), None);
+        assert_eq!(parse_matrix("1,0;"), None);
+        assert_eq!(parse_matrix("1,x"), None);
+    }
+
+    #[test]
+    fn mixer_applies_matrix() {
+        let spec = SignalSpec::new(48000, layout(2).unwrap());
+        let matrix = parse_matrix("0.5,0.5;1,-1;0,2").unwrap();
+        let mut mixer = ChannelMixer::new(spec, layout(3).unwrap(), matrix);
+
+        let mut buf = AudioBuffer::<f32>::new(4, spec);
+        buf.render_reserved(Some(3));
+        buf.chan_mut(0).copy_from_slice(&[1.0, 0.5, 0.0]);
+        buf.chan_mut(1).copy_from_slice(&[0.0, 0.5, -0.25]);
+
+        let out = mixer.process(&AudioBufferRef::F32(Cow::Borrowed(&buf)));
+
+        assert_eq!(out.frames(), 3);
+        assert_eq!(out.chan(0), &[0.5, 0.5, -0.125]);
+        assert_eq!(out.chan(1), &[1.0, 0.0, 0.25]);
+        assert_eq!(out.chan(2), &[0.0, 1.0, -0.5]);
+    }
+}
@@ -7,11 +7,16 @@
 
 //! Platform-dependant Audio Outputs
 
+use std::fmt;
+use std::path::PathBuf;
 use std::result;
 
-use symphonia::core::audio::{AudioBufferRef, SignalSpec};
+use symphonia::core::audio::{AudioBufferRef, Channels, SignalSpec};
 use symphonia::core::units::Duration;
 
+#[cfg(all(target_os = "linux", feature = "alsa"))]
+use log::warn;
+
 pub trait AudioOutput {
     fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()>;
     fn flush(&mut self);
@@ -21,16 +26,194 @@ pub trait AudioOutput {
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum AudioOutputError {
-    OpenStreamError,
-    PlayStreamError,
-    StreamClosedError,
+    OpenStreamError(String),
+    PlayStreamError(String),
+    StreamClosedError(String),
+}
+
+impl fmt::Display for AudioOutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioOutputError::OpenStreamError(msg) => write!(f, "failed to open stream: {}", msg),
+            AudioOutputError::PlayStreamError(msg) => write!(f, "failed to play stream: {}", msg),
+            AudioOutputError::StreamClosedError(msg) => write!(f, "stream closed: {}", msg),
+        }
+    }
 }
 
 pub type Result<T> = result::Result<T, AudioOutputError>;
 
+/// Where the audio is played.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum OutputTarget {
+    /// The sound server or sound card of the platform.
+    #[default]
+    Default,
+    /// An ALSA device, such as `default`, `hw:0,0` or `plughw:1`. If not set, the device option or
+    /// the default device is used.
+    Alsa(Option<String>),
+    /// A JACK server, with a port for every channel.
+    Jack,
+    /// A PipeWire server, played on through its native API.
+    PipeWire,
+    /// A WAV or FLAC file, chosen by the extension of the path.
+    File(PathBuf),
+    /// Raw interleaved samples written to standard output.
+    Stdout,
+    /// Discards the audio, as fast as it is decoded or at the pace it would be played.
+    Null { realtime: bool },
+}
+
+impl OutputTarget {
+    /// Parses an output such as `default`, `alsa:hw:0,0`, `file:out.wav`, `-` or `null:realtime`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.split_once(':') {
+            Some(("alsa", device)) if !device.is_empty() => {
+                Some(OutputTarget::Alsa(Some(device.to_string())))
+            }
+            Some(("file", path)) if !path.is_empty() => Some(OutputTarget::File(path.into())),
+            Some(("null", "realtime")) => Some(OutputTarget::Null { realtime: true }),
+            None => match value {
+                "default" => Some(OutputTarget::Default),
+                "alsa" => Some(OutputTarget::Alsa(None)),
+                "jack" => Some(OutputTarget::Jack),
+                "pipewire" => Some(OutputTarget::PipeWire),
+                "-" | "stdout" => Some(OutputTarget::Stdout),
+                "null" => Some(OutputTarget::Null { realtime: false }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// If true, the output is opened once with the spec of the first audio played, and all later
+    /// audio is mixed and resampled to that spec. Reopening such an output would overwrite what
+    /// was written to it, change the format of a raw stream midway, or restart the decoding speed
+    /// measured by the null output.
+    pub fn has_fixed_spec(&self) -> bool {
+        matches!(self, OutputTarget::File(_) | OutputTarget::Stdout | OutputTarget::Null { .. })
+    }
+
+    /// If true, the audio is written to standard output, so nothing else may be.
+    pub fn is_stdout(&self) -> bool {
+        *self == OutputTarget::Stdout
+    }
+}
+
+/// The format of the samples written to a file or device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    F32,
+    S16,
+    S24,
+    S32,
+}
+
+impl SampleFormat {
+    /// Gets the number of bits per sample.
+    fn bits(self) -> u32 {
+        match self {
+            SampleFormat::F32 => 32,
+            SampleFormat::S16 => 16,
+            SampleFormat::S24 => 24,
+            SampleFormat::S32 => 32,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OutputOptions {
+    pub target: OutputTarget,
+    /// The sample format of file and raw outputs. If not set, f32 samples are written, or s24
+    /// samples to FLAC files. ALSA devices use it if they support it.
+    pub format: Option<SampleFormat>,
+    /// If true, TPDF dither is added when samples are converted to integers.
+    pub dither: bool,
+    /// If true, raw outputs are written in big-endian byte order.
+    pub big_endian: bool,
+    /// The period size of ALSA devices, or the latency asked of PipeWire, in frames.
+    #[cfg_attr(not(any(feature = "alsa", feature = "pipewire")), allow(dead_code))]
+    pub period_size: Option<u32>,
+    /// The buffer size of ALSA devices, or of the audio queued for JACK and PipeWire, in frames.
+    #[cfg_attr(
+        not(any(feature = "alsa", feature = "jack", feature = "pipewire")),
+        allow(dead_code)
+    )]
+    pub buffer_size: Option<u32>,
+    /// The device to play on: a PulseAudio sink, an ALSA device, a pattern of the JACK ports to
+    /// connect to, or a PipeWire node.
+    pub device: Option<String>,
+    /// The PulseAudio or PipeWire server to connect to.
+    pub server: Option<String>,
+    /// The name of the stream shown by the sound server, or the name of the JACK client.
+    pub stream_name: Option<String>,
+}
+
+/// The name of the application shown by sound servers.
+const APP_NAME: &str = "Symphonia Player";
+
+/// The name of the stream shown by sound servers if no name was set.
+const STREAM_NAME: &str = "Music";
+
+/// A device that an audio output can play on.
+pub struct Device {
+    /// The name to select the device with.
+    pub name: String,
+    pub description: String,
+}
+
+/// Generates triangular probability density function (TPDF) dither with a peak amplitude of one
+/// least significant bit.
+///
+/// The generator always starts with the same seed, so that the output of a run is reproducible.
+struct Dither {
+    state: u32,
+}
+
+impl Dither {
+    fn new() -> Self {
+        Dither { state: 0x9e37_79b9 }
+    }
+
+    /// Gets a uniformly distributed number between 0 and 1 with a xorshift generator.
+    fn uniform(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+
+        f64::from(self.state) / (f64::from(u32::MAX) + 1.0)
+    }
+
+    /// Gets the dither for the next sample, in least significant bits.
+    fn next(&mut self) -> f64 {
+        self.uniform() - self.uniform()
+    }
+}
+
+/// Converts `f32` samples to integers with `bits` bits, adding dither if given. Samples outside of
+/// full scale are clipped.
+fn quantize(samples: &[f32], bits: u32, mut dither: Option<&mut Dither>, out: &mut Vec<i32>) {
+    let scale = f64::from(1u32 << (bits - 1));
+
+    out.clear();
+    out.extend(samples.iter().map(|&sample| {
+        let mut value = f64::from(sample) * scale;
+
+        if let Some(dither) = dither.as_mut() {
+            value += dither.next();
+        }
+
+        value.round().clamp(-scale, scale - 1.0) as i32
+    }));
+}
+
 #[cfg(target_os = "linux")]
 mod pulseaudio {
-    use super::{AudioOutput, AudioOutputError, Result};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    use super::{AudioOutput, AudioOutputError, Device, OutputOptions, Result};
+    use super::{APP_NAME, STREAM_NAME};
 
     use symphonia::core::audio::*;
     use symphonia::core::units::Duration;
@@ -38,7 +221,7 @@ mod pulseaudio {
     use libpulse_binding as pulse;
     use libpulse_simple_binding as psimple;
 
-    use log::{error, warn};
+    use log::warn;
 
     pub struct PulseAudioOutput {
         pa: psimple::Simple,
@@ -46,7 +229,11 @@ mod pulseaudio {
     }
 
     impl PulseAudioOutput {
-        pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn AudioOutput>> {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            opts: &OutputOptions,
+        ) -> Result<Box<dyn AudioOutput>> {
             // An interleaved buffer is required to send data to PulseAudio. Use a SampleBuffer to
             // move data between Symphonia AudioBuffers and the byte buffers required by PulseAudio.
             let sample_buf = RawSampleBuffer::<f32>::new(duration, spec);
@@ -58,7 +245,14 @@ mod pulseaudio {
                 rate: spec.rate,
             };
 
-            assert!(pa_spec.is_valid());
+            // The player negotiates a supported rate, but refuse to open a stream with any other
+            // invalid specification rather than panic.
+            if !pa_spec.is_valid() {
+                let channels = spec.channels.count();
+                let msg = format!("{} channels at {} Hz are not supported", channels, spec.rate);
+
+                return Err(AudioOutputError::OpenStreamError(msg));
+            }
 
             let pa_ch_map = map_channels_to_pa_channelmap(spec.channels);
 
@@ -73,13 +267,15 @@ mod pulseaudio {
             //     fragsize: std::u32::MAX,
             // };
 
+            let stream_name = opts.stream_name.as_deref().unwrap_or(STREAM_NAME);
+
             // Create a PulseAudio connection.
             let pa_result = psimple::Simple::new(
-                None,                               // Use default server
-                "Symphonia Player",                 // Application name
+                opts.server.as_deref(),             // Server, or the default server
+                APP_NAME,                           // Application name
                 pulse::stream::Direction::Playback, // Playback stream
-                None,                               // Default playback device
-                "Music",                            // Description of the stream
+                opts.device.as_deref(),             // Sink, or the default sink
+                stream_name,                        // Description of the stream
                 &pa_spec,                           // Signal specification
                 pa_ch_map.as_ref(),                 // Channel map
                 None,                               // Custom buffering attributes
@@ -87,11 +283,7 @@ mod pulseaudio {
 
             match pa_result {
                 Ok(pa) => Ok(Box::new(PulseAudioOutput { pa, sample_buf })),
-                Err(err) => {
-                    error!("audio output stream open error: {}", err);
-
-                    Err(AudioOutputError::OpenStreamError)
-                }
+                Err(err) => Err(AudioOutputError::OpenStreamError(format!("{}", err))),
             }
         }
     }
@@ -103,16 +295,19 @@ mod pulseaudio {
                 return Ok(());
             }
 
+            // The next input may be decoded into larger buffers than the stream was opened with, so
+            // grow the sample buffer if required.
+            if decoded.frames() * decoded.spec().channels.count() > self.sample_buf.capacity() {
+                let duration = decoded.capacity() as Duration;
+                self.sample_buf = RawSampleBuffer::<f32>::new(duration, *decoded.spec());
+            }
+
             // Interleave samples from the audio buffer into the sample buffer.
             self.sample_buf.copy_interleaved_ref(decoded);
 
             // Write interleaved samples to PulseAudio.
             match self.pa.write(self.sample_buf.as_bytes()) {
-                Err(err) => {
-                    error!("audio output stream write error: {}", err);
-
-                    Err(AudioOutputError::StreamClosedError)
-                }
+                Err(err) => Err(AudioOutputError::StreamClosedError(format!("{}", err))),
                 _ => Ok(()),
             }
         }
@@ -123,6 +318,87 @@ mod pulseaudio {
         }
     }
 
+    /// Lists the sinks of the PulseAudio server.
+    pub fn list_devices(opts: &OutputOptions) -> std::result::Result<Vec<Device>, String> {
+        use pulse::callbacks::ListResult;
+        use pulse::context::{Context, FlagSet, State};
+        use pulse::mainloop::standard::{IterateResult, Mainloop};
+
+        let iterate = |mainloop: &mut Mainloop| match mainloop.iterate(true) {
+            IterateResult::Success(_) => Ok(()),
+            IterateResult::Quit(_) => Err("the main loop quit".to_string()),
+            IterateResult::Err(err) => Err(format!("{}", err)),
+        };
+
+        let mut mainloop = Mainloop::new().ok_or("failed to create a main loop")?;
+        let mut context = Context::new(&mainloop, APP_NAME).ok_or("failed to create a context")?;
+
+        context
+            .connect(opts.server.as_deref(), FlagSet::NOFLAGS, None)
+            .map_err(|err| format!("{}", err))?;
+
+        loop {
+            iterate(&mut mainloop)?;
+
+            match context.get_state() {
+                State::Ready => break,
+                State::Failed | State::Terminated => return Err(format!("{}", context.errno())),
+                _ => (),
+            }
+        }
+
+        let devices = Rc::new(RefCell::new(Vec::new()));
+        let done = Rc::new(Cell::new(false));
+
+        let _op = context.introspect().get_sink_info_list({
+            let devices = Rc::clone(&devices);
+            let done = Rc::clone(&done);
+
+            move |result| match result {
+                ListResult::Item(info) => devices.borrow_mut().push(Device {
+                    name: info.name.as_deref().unwrap_or_default().to_string(),
+                    description: info.description.as_deref().unwrap_or_default().to_string(),
+                }),
+                ListResult::End | ListResult::Error => done.set(true),
+            }
+        });
+
+        while !done.get() {
+            iterate(&mut mainloop)?;
+        }
+
+        context.disconnect();
+
+        Ok(devices.take())
+    }
+
+    /// Gets the closest sample rate to `rate` that PulseAudio supports. Rates that are too high are
+    /// halved, so that the conversion ratio stays simple.
+    pub fn supported_rate(rate: u32) -> u32 {
+        let mut rate = rate.max(1);
+
+        while rate > pulse::sample::Spec::RATE_MAX {
+            rate /= 2;
+        }
+
+        rate
+    }
+
+    /// Gets the channels closest to `channels` that PulseAudio supports. Channels without a
+    /// PulseAudio position are mixed down to stereo.
+    pub fn supported_channels(channels: Channels) -> Channels {
+        let is_mono = channels.count() == 1;
+
+        if channels.count() <= usize::from(pulse::sample::Spec::CHANNELS_MAX)
+            && channels.iter().all(|channel| map_channel(channel, is_mono).is_some())
+        {
+            channels
+        }
+        else {
+            Channels::FRONT_LEFT | Channels::FRONT_RIGHT
+        }
+    }
+
     /// Maps a set of Symphonia `Channels` to a PulseAudio channel map.
     fn map_channels_to_pa_channelmap(channels: Channels) -> Option<pulse::channelmap::Map> {
         let mut map: pulse::channelmap::Map = Default::default();
@@ -132,27 +408,9 @@ mod pulseaudio {
         let is_mono = channels.count() == 1;
 
         for (i, channel) in channels.iter().enumerate() {
-            map.get_mut()[i] = match channel {
-                Channels::FRONT_LEFT if is_mono => pulse::channelmap::Position::Mono,
-                Channels::FRONT_LEFT => pulse::channelmap::Position::FrontLeft,
-                Channels::FRONT_RIGHT => pulse::channelmap::Position::FrontRight,
-                Channels::FRONT_CENTRE => pulse::channelmap::Position::FrontCenter,
-                Channels::REAR_LEFT => pulse::channelmap::Position::RearLeft,
-                Channels::REAR_CENTRE => pulse::channelmap::Position::RearCenter,
-                Channels::REAR_RIGHT => pulse::channelmap::Position::RearRight,
-                Channels::LFE1 => pulse::channelmap::Position::Lfe,
-                Channels::FRONT_LEFT_CENTRE => pulse::channelmap::Position::FrontLeftOfCenter,
-                Channels::FRONT_RIGHT_CENTRE => pulse::channelmap::Position::FrontRightOfCenter,
-                Channels::SIDE_LEFT => pulse::channelmap::Position::SideLeft,
-                Channels::SIDE_RIGHT => pulse::channelmap::Position::SideRight,
-                Channels::TOP_CENTRE => pulse::channelmap::Position::TopCenter,
-                Channels::TOP_FRONT_LEFT => pulse::channelmap::Position::TopFrontLeft,
-                Channels::TOP_FRONT_CENTRE => pulse::channelmap::Position::TopFrontCenter,
-                Channels::TOP_FRONT_RIGHT => pulse::channelmap::Position::TopFrontRight,
-                Channels::TOP_REAR_LEFT => pulse::channelmap::Position::TopRearLeft,
-                Channels::TOP_REAR_CENTRE => pulse::channelmap::Position::TopRearCenter,
-                Channels::TOP_REAR_RIGHT => pulse::channelmap::Position::TopRearRight,
-                _ => {
+            map.get_mut()[i] = match map_channel(channel, is_mono) {
+                Some(position) => position,
+                None => {
                     // If a Symphonia channel cannot map to a PulseAudio position then return None
                     // because PulseAudio will not be able to open a stream with invalid channels.
                     warn!("failed to map channel {:?} to output", channel);
@@ -163,9 +421,2888 @@ mod pulseaudio {
 
         Some(map)
     }
+
+    /// Maps a Symphonia channel to a PulseAudio channel position.
+    fn map_channel(channel: Channels, is_mono: bool) -> Option<pulse::channelmap::Position> {
+        let position = match channel {
+            Channels::FRONT_LEFT if is_mono => pulse::channelmap::Position::Mono,
+            Channels::FRONT_LEFT => pulse::channelmap::Position::FrontLeft,
+            Channels::FRONT_RIGHT => pulse::channelmap::Position::FrontRight,
+            Channels::FRONT_CENTRE => pulse::channelmap::Position::FrontCenter,
+            Channels::REAR_LEFT => pulse::channelmap::Position::RearLeft,
+            Channels::REAR_CENTRE => pulse::channelmap::Position::RearCenter,
+            Channels::REAR_RIGHT => pulse::channelmap::Position::RearRight,
+            Channels::LFE1 => pulse::channelmap::Position::Lfe,
+            Channels::FRONT_LEFT_CENTRE => pulse::channelmap::Position::FrontLeftOfCenter,
+            Channels::FRONT_RIGHT_CENTRE => pulse::channelmap::Position::FrontRightOfCenter,
+            Channels::SIDE_LEFT => pulse::channelmap::Position::SideLeft,
+            Channels::SIDE_RIGHT => pulse::channelmap::Position::SideRight,
+            Channels::TOP_CENTRE => pulse::channelmap::Position::TopCenter,
+            Channels::TOP_FRONT_LEFT => pulse::channelmap::Position::TopFrontLeft,
+            Channels::TOP_FRONT_CENTRE => pulse::channelmap::Position::TopFrontCenter,
+            Channels::TOP_FRONT_RIGHT => pulse::channelmap::Position::TopFrontRight,
+            Channels::TOP_REAR_LEFT => pulse::channelmap::Position::TopRearLeft,
+            Channels::TOP_REAR_CENTRE => pulse::channelmap::Position::TopRearCenter,
+            Channels::TOP_REAR_RIGHT => pulse::channelmap::Position::TopRearRight,
+            _ => return None,
+        };
+
+        Some(position)
+    }
 }
 
-#[cfg(target_os = "linux")]
-pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn AudioOutput>> {
-    pulseaudio::PulseAudioOutput::try_open(spec, duration)
+mod file {
+    use std::fs::File;
+    use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+    use std::path::{Path, PathBuf};
+
+    use super::{quantize, AudioOutput, AudioOutputError, Dither, OutputOptions, Result};
+    use super::SampleFormat;
+
+    use symphonia::core::audio::*;
+    use symphonia::core::checksum::{Crc16Ansi, Crc8Ccitt, Md5};
+    use symphonia::core::io::Monitor;
+    use symphonia::core::units::Duration;
+
+    use log::error;
+
+    /// The highest sample rate a FLAC file can be played at by most decoders.
+    const FLAC_RATE_MAX: u32 = 655_350;
+
+    /// The highest number of channels a FLAC file can hold.
+    const FLAC_CHANNELS_MAX: usize = 8;
+
+    /// The number of frames in each FLAC frame.
+    const FLAC_BLOCK_SIZE: usize = 4096;
+
+    /// The highest FLAC Rice partition order searched.
+    const FLAC_PARTITION_ORDER_MAX: u32 = 8;
+
+    /// The highest Rice parameter of the 4-bit parameter coding method. 15 is an escape code.
+    const FLAC_RICE_PARAM_MAX: u32 = 14;
+
+    /// Writes the audio stream to a file, exactly as it would have been played.
+    pub struct FileOutput {
+        writer: Writer,
+        path: PathBuf,
+        format: SampleFormat,
+        dither: Option<Dither>,
+        sample_buf: SampleBuffer<f32>,
+        int_buf: Vec<i32>,
+    }
+
+    enum Writer {
+        Wav(WavWriter),
+        Flac(FlacWriter),
+    }
+
+    /// Returns true if the path has a `.flac` extension.
+    pub(super) fn is_flac(path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("flac"))
+    }
+
+    impl FileOutput {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            path: &Path,
+            opts: &OutputOptions,
+        ) -> Result<Box<dyn AudioOutput>> {
+            let open_error = |msg: String| {
+                AudioOutputError::OpenStreamError(format!("{}: {}", path.display(), msg))
+            };
+
+            let is_flac = is_flac(path);
+
+            let format = match opts.format {
+                Some(SampleFormat::F32) if is_flac => {
+                    return Err(open_error("FLAC files cannot hold f32 samples".to_string()))
+                }
+                Some(SampleFormat::S32) if is_flac => {
+                    return Err(open_error("FLAC files cannot hold s32 samples".to_string()))
+                }
+                Some(format) => format,
+                None if is_flac => SampleFormat::S24,
+                None => SampleFormat::F32,
+            };
+
+            let file = File::create(path).map_err(|err| open_error(err.to_string()))?;
+            let file = BufWriter::new(file);
+
+            let writer = if is_flac {
+                Writer::Flac(FlacWriter::new(file, spec, format.bits()))
+            }
+            else {
+                Writer::Wav(WavWriter::new(file, spec, format))
+            };
+
+            let mut output = FileOutput {
+                writer,
+                path: path.to_path_buf(),
+                format,
+                dither: opts.dither.then(Dither::new),
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                int_buf: Vec::new(),
+            };
+
+            output.writer.write_header().map_err(|err| open_error(err.to_string()))?;
+
+            Ok(Box::new(output))
+        }
+    }
+
+    impl AudioOutput for FileOutput {
+        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            // Do nothing if there are no audio frames.
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            // Grow the sample buffer if the buffer is larger than the output was opened with.
+            if decoded.frames() * decoded.spec().channels.count() > self.sample_buf.capacity() {
+                let duration = decoded.capacity() as Duration;
+                self.sample_buf = SampleBuffer::<f32>::new(duration, *decoded.spec());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            let samples = self.sample_buf.samples();
+
+            let result = match (&mut self.writer, self.format) {
+                (Writer::Wav(wav), SampleFormat::F32) => wav.write_f32(samples),
+                (writer, format) => {
+                    quantize(samples, format.bits(), self.dither.as_mut(), &mut self.int_buf);
+
+                    match writer {
+                        Writer::Wav(wav) => wav.write_int(&self.int_buf),
+                        Writer::Flac(flac) => flac.write(&self.int_buf),
+                    }
+                }
+            };
+
+            // A failed write cannot be recovered by reopening the file, since that truncates it.
+            result.map_err(|err| {
+                AudioOutputError::PlayStreamError(format!("{}: {}", self.path.display(), err))
+            })
+        }
+
+        fn flush(&mut self) {
+            if let Err(err) = self.writer.finish() {
+                error!("failed to finish {}: {}", self.path.display(), err);
+            }
+        }
+    }
+
+    impl Writer {
+        fn write_header(&mut self) -> io::Result<()> {
+            match self {
+                Writer::Wav(wav) => wav.write_header(),
+                Writer::Flac(flac) => flac.write_header(),
+            }
+        }
+
+        /// Writes any buffered audio, and updates the header with the length of the stream.
+        fn finish(&mut self) -> io::Result<()> {
+            match self {
+                Writer::Wav(wav) => wav.finish(),
+                Writer::Flac(flac) => flac.finish(),
+            }
+        }
+    }
+
+    /// Gets the sample rate closest to `rate` that the file at `path` can hold.
+    pub fn supported_rate(path: &Path, rate: u32) -> u32 {
+        let mut rate = rate.max(1);
+
+        if is_flac(path) {
+            while rate > FLAC_RATE_MAX {
+                rate /= 2;
+            }
+        }
+
+        rate
+    }
+
+    /// Gets the channels closest to `channels` that the file at `path` can hold.
+    pub fn supported_channels(path: &Path, channels: Channels) -> Channels {
+        if is_flac(path) && channels.count() > FLAC_CHANNELS_MAX {
+            Channels::FRONT_LEFT | Channels::FRONT_RIGHT
+        }
+        else {
+            channels
+        }
+    }
+
+    /// Writes a RIFF WAVE file.
+    struct WavWriter {
+        file: BufWriter<File>,
+        spec: SignalSpec,
+        format: SampleFormat,
+        /// The number of bytes of audio written.
+        data_len: u64,
+    }
+
+    impl WavWriter {
+        fn new(file: BufWriter<File>, spec: SignalSpec, format: SampleFormat) -> Self {
+            WavWriter { file, spec, format, data_len: 0 }
+        }
+
+        /// Writes the header for the audio written so far at the start of the file.
+        fn write_header(&mut self) -> io::Result<()> {
+            let n_channels = self.spec.channels.count() as u16;
+            let bits = self.format.bits() as u16;
+            let block_align = n_channels * bits / 8;
+            let is_float = self.format == SampleFormat::F32;
+
+            // WAVE_FORMAT_EXTENSIBLE is required for more than 2 channels or integer samples wider
+            // than 16 bits, and it holds the channel layout.
+            let is_extensible = n_channels > 2 || (!is_float && bits > 16);
+
+            let mut fmt = Vec::with_capacity(40);
+            let tag: u16 = match (is_extensible, is_float) {
+                (true, _) => 0xfffe,
+                (false, true) => 3,
+                (false, false) => 1,
+            };
+
+            fmt.extend_from_slice(&tag.to_le_bytes());
+            fmt.extend_from_slice(&n_channels.to_le_bytes());
+            fmt.extend_from_slice(&self.spec.rate.to_le_bytes());
+            fmt.extend_from_slice(&(self.spec.rate * u32::from(block_align)).to_le_bytes());
+            fmt.extend_from_slice(&block_align.to_le_bytes());
+            fmt.extend_from_slice(&bits.to_le_bytes());
+
+            if is_extensible {
+                // The channel bits of Symphonia match the WAVE channel mask for the channels WAVE
+                // defines. Other channels are left unassigned.
+                let channel_bits = self.spec.channels.bits();
+                let mask = if channel_bits < 1 << 18 { channel_bits } else { 0 };
+
+                // The subformat GUID, which is the format tag followed by a fixed suffix.
+                let sub_format: u16 = if is_float { 3 } else { 1 };
+                let suffix = [
+                    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b,
+                    0x71,
+                ];
+
+                fmt.extend_from_slice(&22u16.to_le_bytes());
+                fmt.extend_from_slice(&bits.to_le_bytes());
+                fmt.extend_from_slice(&mask.to_le_bytes());
+                fmt.extend_from_slice(&sub_format.to_le_bytes());
+                fmt.extend_from_slice(&suffix);
+            }
+
+            // A chunk holding an odd number of bytes is followed by a padding byte.
+            let padded_len = self.data_len + (self.data_len & 1);
+            let riff_len = 4 + (8 + fmt.len() as u64) + 8 + padded_len;
+
+            // Files over 4 GiB cannot hold their true length, so the largest length is written.
+            let to_u32 = |len: u64| u32::try_from(len).unwrap_or(u32::MAX);
+
+            let mut header = Vec::with_capacity(68);
+            header.extend_from_slice(b"RIFF");
+            header.extend_from_slice(&to_u32(riff_len).to_le_bytes());
+            header.extend_from_slice(b"WAVE");
+            header.extend_from_slice(b"fmt ");
+            header.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+            header.extend_from_slice(&fmt);
+            header.extend_from_slice(b"data");
+            header.extend_from_slice(&to_u32(self.data_len).to_le_bytes());
+
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_all(&header)?;
+            self.file.seek(SeekFrom::End(0))?;
+
+            Ok(())
+        }
+
+        fn write_f32(&mut self, samples: &[f32]) -> io::Result<()> {
+            for sample in samples {
+                self.file.write_all(&sample.to_le_bytes())?;
+            }
+
+            self.data_len += 4 * samples.len() as u64;
+
+            Ok(())
+        }
+
+        fn write_int(&mut self, samples: &[i32]) -> io::Result<()> {
+            let width = self.format.bits() as usize / 8;
+
+            for sample in samples {
+                self.file.write_all(&sample.to_le_bytes()[..width])?;
+            }
+
+            self.data_len += (width * samples.len()) as u64;
+
+            Ok(())
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            if self.data_len & 1 == 1 {
+                self.file.write_all(&[0])?;
+            }
+
+            self.write_header()?;
+
+            // The padding byte is written again if more audio is written after this.
+            if self.data_len & 1 == 1 {
+                self.file.seek(SeekFrom::Current(-1))?;
+            }
+
+            self.file.flush()
+        }
+    }
+
+    /// Writes bits to a byte buffer, most significant bit first.
+    struct BitWriter {
+        buf: Vec<u8>,
+        acc: u64,
+        n_bits: u32,
+    }
+
+    impl BitWriter {
+        fn new() -> Self {
+            BitWriter { buf: Vec::new(), acc: 0, n_bits: 0 }
+        }
+
+        /// Writes the low `bits` bits of `value`. At most 32 bits can be written at once.
+        fn write(&mut self, value: u64, bits: u32) {
+            debug_assert!(bits <= 32);
+
+            self.acc = (self.acc << bits) | (value & ((1 << bits) - 1));
+            self.n_bits += bits;
+
+            while self.n_bits >= 8 {
+                self.n_bits -= 8;
+                self.buf.push((self.acc >> self.n_bits) as u8);
+            }
+        }
+
+        /// Writes a signed value in two's complement with `bits` bits.
+        fn write_signed(&mut self, value: i64, bits: u32) {
+            self.write(value as u64, bits)
+        }
+
+        /// Writes `value` in unary, as that many zeros followed by a one.
+        fn write_unary(&mut self, mut value: u64) {
+            while value >= 32 {
+                self.write(0, 32);
+                value -= 32;
+            }
+
+            self.write(1, value as u32 + 1);
+        }
+
+        /// Writes zeros up-to the next byte boundary.
+        fn align(&mut self) {
+            if self.n_bits > 0 {
+                self.write(0, 8 - self.n_bits);
+            }
+        }
+    }
+
+    /// Encodes a FLAC file with fixed predictors and Rice coded residuals.
+    struct FlacWriter {
+        file: BufWriter<File>,
+        spec: SignalSpec,
+        bits: u32,
+        /// The samples of each channel not yet encoded.
+        pending: Vec<Vec<i32>>,
+        /// The number of frames encoded.
+        n_frames: u32,
+        /// The number of samples per channel encoded.
+        n_samples: u64,
+        /// The sizes in bytes of the smallest and largest frames encoded.
+        frame_size: Option<(usize, usize)>,
+        md5: Md5,
+    }
+
+    impl FlacWriter {
+        fn new(file: BufWriter<File>, spec: SignalSpec, bits: u32) -> Self {
+            FlacWriter {
+                file,
+                spec,
+                bits,
+                pending: vec![Vec::with_capacity(FLAC_BLOCK_SIZE); spec.channels.count()],
+                n_frames: 0,
+                n_samples: 0,
+                frame_size: None,
+                md5: Md5::default(),
+            }
+        }
+
+        /// Writes the stream marker and the STREAMINFO block for the audio encoded so far at the
+        /// start of the file.
+        fn write_header(&mut self) -> io::Result<()> {
+            let (min_frame_size, max_frame_size) = self.frame_size.unwrap_or((0, 0));
+
+            let mut bw = BitWriter::new();
+
+            // The block size, which is the same for all frames but the last.
+            bw.write(FLAC_BLOCK_SIZE as u64, 16);
+            bw.write(FLAC_BLOCK_SIZE as u64, 16);
+            bw.write(min_frame_size as u64, 24);
+            bw.write(max_frame_size as u64, 24);
+            bw.write(u64::from(self.spec.rate), 20);
+            bw.write(self.spec.channels.count() as u64 - 1, 3);
+            bw.write(u64::from(self.bits) - 1, 5);
+            bw.write(self.n_samples >> 32, 4);
+            bw.write(self.n_samples & 0xffff_ffff, 32);
+
+            let mut header = Vec::with_capacity(42);
+            header.extend_from_slice(b"fLaC");
+            // The STREAMINFO block is the last, and only, metadata block.
+            header.extend_from_slice(&[0x80, 0, 0, 34]);
+            header.extend_from_slice(&bw.buf);
+            header.extend_from_slice(&self.md5.md5());
+
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_all(&header)?;
+            self.file.seek(SeekFrom::End(0))?;
+
+            Ok(())
+        }
+
+        fn write(&mut self, samples: &[i32]) -> io::Result<()> {
+            let n_channels = self.pending.len();
+            let width = self.bits as usize / 8;
+
+            for frame in samples.chunks_exact(n_channels) {
+                for (pending, &sample) in self.pending.iter_mut().zip(frame) {
+                    pending.push(sample);
+
+                    // The MD5 is of the interleaved samples in little-endian.
+                    self.md5.process_buf_bytes(&sample.to_le_bytes()[..width]);
+                }
+
+                if self.pending[0].len() == FLAC_BLOCK_SIZE {
+                    self.write_frame()?;
+                }
+            }
+
+            Ok(())
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            if !self.pending[0].is_empty() {
+                self.write_frame()?;
+            }
+
+            self.write_header()?;
+            self.file.flush()
+        }
+
+        /// Encodes the pending samples as a frame.
+        fn write_frame(&mut self) -> io::Result<()> {
+            let block_size = self.pending[0].len();
+
+            let mut bw = BitWriter::new();
+
+            // Sync code, with a fixed block size.
+            bw.write(0xfff8, 16);
+            // The block size is stored as a 16 bit number after the frame number, and the sample
+            // rate is that of the STREAMINFO block.
+            bw.write(0b0111, 4);
+            bw.write(0b0000, 4);
+            // Channels are coded independently.
+            bw.write(self.pending.len() as u64 - 1, 4);
+            bw.write(if self.bits == 16 { 0b100 } else { 0b110 }, 3);
+            bw.write(0, 1);
+            write_utf8(&mut bw, self.n_frames);
+            bw.write(block_size as u64 - 1, 16);
+
+            let mut crc8 = Crc8Ccitt::new(0);
+            crc8.process_buf_bytes(&bw.buf);
+            bw.write(u64::from(crc8.crc()), 8);
+
+            for pending in &self.pending {
+                write_subframe(&mut bw, pending, self.bits);
+            }
+
+            bw.align();
+
+            let mut crc16 = Crc16Ansi::new(0);
+            crc16.process_buf_bytes(&bw.buf);
+            bw.write(u64::from(crc16.crc()), 16);
+
+            self.file.write_all(&bw.buf)?;
+
+            let size = bw.buf.len();
+            self.frame_size = Some(match self.frame_size {
+                Some((min, max)) => (min.min(size), max.max(size)),
+                None => (size, size),
+            });
+
+            self.n_frames += 1;
+            self.n_samples += block_size as u64;
+
+            for pending in self.pending.iter_mut() {
+                pending.clear();
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Writes a frame number with the UTF-8 like coding of FLAC.
+    fn write_utf8(bw: &mut BitWriter, value: u32) {
+        let value = u64::from(value);
+
+        if value < 0x80 {
+            bw.write(value, 8);
+            return;
+        }
+
+        // A number coded with n bytes holds 5n + 1 bits.
+        let mut n_bytes = 2;
+
+        while value >= 1 << (5 * n_bytes + 1) {
+            n_bytes += 1;
+        }
+
+        let prefix = (0xff00 >> n_bytes) & 0xff;
+        bw.write(prefix | (value >> (6 * (n_bytes - 1))), 8);
+
+        for i in (0..n_bytes - 1).rev() {
+            bw.write(0x80 | ((value >> (6 * i)) & 0x3f), 8);
+        }
+    }
+
+    /// Encodes the samples of a channel as a subframe, with the fixed predictor that leaves the
+    /// smallest residual. Silence is encoded as a constant, and noise that cannot be compressed
+    /// verbatim.
+    fn write_subframe(bw: &mut BitWriter, samples: &[i32], bits: u32) {
+        if samples.iter().all(|&sample| sample == samples[0]) {
+            bw.write(0b0000_0000, 8);
+            bw.write_signed(i64::from(samples[0]), bits);
+            return;
+        }
+
+        // Pick the predictor order with the smallest sum of absolute residuals.
+        let max_order = 4.min(samples.len() - 1);
+
+        let (order, residual) = (0..=max_order)
+            .map(|order| (order, fixed_residual(samples, order)))
+            .min_by_key(|(_, residual)| residual.iter().map(|r| r.unsigned_abs()).sum::<u64>())
+            .unwrap();
+
+        let (partition_order, params, rice_bits) = rice_partitions(&residual, samples.len(), order);
+
+        let fixed_bits = 8 + order as u64 * u64::from(bits) + 6 + rice_bits;
+        let verbatim_bits = 8 + samples.len() as u64 * u64::from(bits);
+
+        if fixed_bits >= verbatim_bits {
+            bw.write(0b0000_0010, 8);
+
+            for &sample in samples {
+                bw.write_signed(i64::from(sample), bits);
+            }
+
+            return;
+        }
+
+        bw.write(((0b1000 | order) << 1) as u64, 8);
+
+        for &sample in &samples[..order] {
+            bw.write_signed(i64::from(sample), bits);
+        }
+
+        // Rice coding with 4-bit parameters.
+        bw.write(0b00, 2);
+        bw.write(u64::from(partition_order), 4);
+
+        let mut residual = residual.iter();
+
+        for (p, &param) in params.iter().enumerate() {
+            let len = (samples.len() >> partition_order) - if p == 0 { order } else { 0 };
+
+            bw.write(u64::from(param), 4);
+
+            for &r in residual.by_ref().take(len) {
+                let value = zigzag(r);
+                bw.write_unary(value >> param);
+                bw.write(value, param);
+            }
+        }
+    }
+
+    /// Gets the residual of the fixed predictor of the given order, for all samples but the
+    /// warm-up samples.
+    fn fixed_residual(samples: &[i32], order: usize) -> Vec<i64> {
+        let x = |i: usize| i64::from(samples[i]);
+
+        (order..samples.len())
+            .map(|i| match order {
+                0 => x(i),
+                1 => x(i) - x(i - 1),
+                2 => x(i) - 2 * x(i - 1) + x(i - 2),
+                3 => x(i) - 3 * x(i - 1) + 3 * x(i - 2) - x(i - 3),
+                _ => x(i) - 4 * x(i - 1) + 6 * x(i - 2) - 4 * x(i - 3) + x(i - 4),
+            })
+            .collect()
+    }
+
+    /// Maps a signed residual to an unsigned number, interleaving positive and negative values.
+    fn zigzag(value: i64) -> u64 {
+        ((value << 1) ^ (value >> 63)) as u64
+    }
+
+    /// Finds the Rice partition order, and the parameter of each partition, that codes the
+    /// residual in the fewest bits. Returns the order, the parameters, and the number of bits.
+    fn rice_partitions(residual: &[i64], block_size: usize, order: usize) -> (u32, Vec<u32>, u64) {
+        // Each partition must hold at least one sample more than the warm-up samples, and the
+        // block must divide evenly into the partitions.
+        let mut max_order = 0;
+
+        while max_order < FLAC_PARTITION_ORDER_MAX
+            && block_size % (1 << (max_order + 1)) == 0
+            && (block_size >> (max_order + 1)) > order
+        {
+            max_order += 1;
+        }
+
+        let values: Vec<u64> = residual.iter().map(|&r| zigzag(r)).collect();
+
+        // The number of bits to code each partition of the highest order with each parameter.
+        let n_params = FLAC_RICE_PARAM_MAX as usize + 1;
+        let n_partitions = 1 << max_order;
+
+        let mut costs = vec![vec![0u64; n_params]; n_partitions];
+        let mut start = 0;
+
+        for (p, cost) in costs.iter_mut().enumerate() {
+            let len = (block_size >> max_order) - if p == 0 { order } else { 0 };
+            let partition = &values[start..start + len];
+
+            for (param, cost) in cost.iter_mut().enumerate() {
+                let quotients: u64 = partition.iter().map(|&v| v >> param).sum();
+                *cost = quotients + (len * (param + 1)) as u64;
+            }
+
+            start += len;
+        }
+
+        let mut best: Option<(u32, Vec<u32>, u64)> = None;
+
+        // Lower orders merge pairs of adjacent partitions.
+        for partition_order in (0..=max_order).rev() {
+            let mut params = Vec::with_capacity(costs.len());
+            let mut total = 0;
+
+            for cost in &costs {
+                let (param, bits) =
+                    cost.iter().enumerate().min_by_key(|&(_, &bits)| bits).unwrap();
+
+                params.push(param as u32);
+                total += 4 + bits;
+            }
+
+            if best.as_ref().map_or(true, |(_, _, best_total)| total < *best_total) {
+                best = Some((partition_order, params, total));
+            }
+
+            costs = costs
+                .chunks(2)
+                .map(|pair| pair.iter().fold(vec![0; n_params], |acc, cost| {
+                    acc.iter().zip(cost).map(|(a, b)| a + b).collect()
+                }))
+                .collect();
+        }
+
+        best.unwrap()
+    }
+}
+
+mod raw {
+    use std::io::{self, BufWriter, Write};
+
+    use super::{quantize, AudioOutput, AudioOutputError, Dither, OutputOptions, Result};
+    use super::SampleFormat;
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use log::error;
+
+    /// Writes raw interleaved samples to standard output.
+    pub struct RawOutput {
+        stdout: BufWriter<io::Stdout>,
+        format: SampleFormat,
+        big_endian: bool,
+        dither: Option<Dither>,
+        sample_buf: SampleBuffer<f32>,
+        int_buf: Vec<i32>,
+        bytes: Vec<u8>,
+    }
+
+    impl RawOutput {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            opts: &OutputOptions,
+        ) -> Result<Box<dyn AudioOutput>> {
+            Ok(Box::new(RawOutput {
+                stdout: BufWriter::new(io::stdout()),
+                format: opts.format.unwrap_or(SampleFormat::F32),
+                big_endian: opts.big_endian,
+                dither: opts.dither.then(Dither::new),
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                int_buf: Vec::new(),
+                bytes: Vec::new(),
+            }))
+        }
+    }
+
+    impl AudioOutput for RawOutput {
+        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            // Do nothing if there are no audio frames.
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            // Grow the sample buffer if the buffer is larger than the output was opened with.
+            if decoded.frames() * decoded.spec().channels.count() > self.sample_buf.capacity() {
+                let duration = decoded.capacity() as Duration;
+                self.sample_buf = SampleBuffer::<f32>::new(duration, *decoded.spec());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            let samples = self.sample_buf.samples();
+            let big_endian = self.big_endian;
+
+            self.bytes.clear();
+
+            match self.format {
+                SampleFormat::F32 => {
+                    for sample in samples {
+                        let bytes =
+                            if big_endian { sample.to_be_bytes() } else { sample.to_le_bytes() };
+                        self.bytes.extend_from_slice(&bytes);
+                    }
+                }
+                format => {
+                    let width = format.bits() as usize / 8;
+
+                    quantize(samples, format.bits(), self.dither.as_mut(), &mut self.int_buf);
+
+                    for sample in &self.int_buf {
+                        if big_endian {
+                            self.bytes.extend_from_slice(&sample.to_be_bytes()[4 - width..]);
+                        }
+                        else {
+                            self.bytes.extend_from_slice(&sample.to_le_bytes()[..width]);
+                        }
+                    }
+                }
+            }
+
+            // There is no recovering from a closed pipe.
+            self.stdout.write_all(&self.bytes).map_err(|err| {
+                AudioOutputError::PlayStreamError(format!("standard output: {}", err))
+            })
+        }
+
+        fn flush(&mut self) {
+            if let Err(err) = self.stdout.flush() {
+                error!("failed to flush standard output: {}", err);
+            }
+        }
+    }
+}
+
+mod null {
+    use std::thread;
+    use std::time::{self, Instant};
+
+    use super::{AudioOutput, Result};
+
+    use symphonia::core::audio::*;
+
+    /// Discards the audio, and prints how much faster than real time it was decoded to standard
+    /// error when it is dropped, whatever the log level.
+    pub struct NullOutput {
+        rate: u32,
+        /// If true, writes are paced at the rate the audio would be played.
+        realtime: bool,
+        opened: Instant,
+        /// The time the audio written so far would finish playing.
+        deadline: Instant,
+        n_frames: u64,
+    }
+
+    impl NullOutput {
+        pub fn try_open(spec: SignalSpec, realtime: bool) -> Result<Box<dyn AudioOutput>> {
+            let now = Instant::now();
+
+            Ok(Box::new(NullOutput {
+                rate: spec.rate,
+                realtime,
+                opened: now,
+                deadline: now,
+                n_frames: 0,
+            }))
+        }
+    }
+
+    impl AudioOutput for NullOutput {
+        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            self.n_frames += decoded.frames() as u64;
+
+            if self.realtime {
+                let now = Instant::now();
+
+                // Do not catch up on time lost while paused or stalled.
+                self.deadline = self.deadline.max(now);
+                self.deadline +=
+                    time::Duration::from_secs_f64(decoded.frames() as f64 / f64::from(self.rate));
+
+                thread::sleep(self.deadline - now);
+            }
+
+            Ok(())
+        }
+
+        fn flush(&mut self) {}
+    }
+
+    impl Drop for NullOutput {
+        fn drop(&mut self) {
+            if self.n_frames == 0 {
+                return;
+            }
+
+            let played = self.n_frames as f64 / f64::from(self.rate);
+            let elapsed = self.opened.elapsed().as_secs_f64();
+
+            eprintln!(
+                "null output: {:.2} s of audio in {:.2} s ({:.1}x realtime)",
+                played,
+                elapsed,
+                played / elapsed.max(f64::EPSILON)
+            );
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "alsa"))]
+mod alsa_output {
+    use std::fmt;
+
+    use super::{quantize, AudioOutput, AudioOutputError, Device, Dither, OutputOptions, Result};
+    use super::SampleFormat;
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use alsa::device_name::HintIter;
+    use alsa::pcm::{Access, Format, Frames, HwParams, PCM};
+    use alsa::{Direction, ValueOr};
+
+    use log::{info, warn};
+
+    /// The channels ALSA devices play, in the order of the standard ALSA channel maps.
+    const CHANNEL_ORDER: [Channels; 8] = [
+        Channels::FRONT_LEFT,
+        Channels::FRONT_RIGHT,
+        Channels::REAR_LEFT,
+        Channels::REAR_RIGHT,
+        Channels::FRONT_CENTRE,
+        Channels::LFE1,
+        Channels::SIDE_LEFT,
+        Channels::SIDE_RIGHT,
+    ];
+
+    /// The sample formats tried, in order of preference, with the ALSA format of each. 24 bit
+    /// samples are in the low bits of 32 bit words.
+    const FORMATS: [(SampleFormat, Format); 4] = [
+        (SampleFormat::F32, Format::float()),
+        (SampleFormat::S32, Format::s32()),
+        (SampleFormat::S24, Format::s24()),
+        (SampleFormat::S16, Format::s16()),
+    ];
+
+    /// The length of the buffer in microseconds if no buffer size was requested.
+    const BUFFER_TIME: u32 = 200_000;
+
+    /// The number of periods per buffer if no period size was requested.
+    const PERIODS: Frames = 4;
+
+    pub struct AlsaOutput {
+        pcm: PCM,
+        device: String,
+        format: SampleFormat,
+        /// For each channel of the device, the index of the channel of the audio it plays.
+        order: Vec<usize>,
+        dither: Option<Dither>,
+        sample_buf: SampleBuffer<f32>,
+        int_buf: Vec<i32>,
+        bytes: Vec<u8>,
+    }
+
+    fn open_error(device: &str, err: impl fmt::Display) -> AudioOutputError {
+        AudioOutputError::OpenStreamError(format!("{}: {}", device, err))
+    }
+
+    impl AlsaOutput {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            device: &str,
+            opts: &OutputOptions,
+        ) -> Result<Box<dyn AudioOutput>> {
+            let channels: Vec<Channels> = spec.channels.iter().collect();
+
+            let order: Vec<usize> = CHANNEL_ORDER
+                .iter()
+                .filter_map(|channel| channels.iter().position(|c| c == channel))
+                .collect();
+
+            if order.len() != channels.len() {
+                return Err(open_error(device, "the channel layout is not supported"));
+            }
+
+            let pcm = PCM::new(device, Direction::Playback, false)
+                .map_err(|err| open_error(device, err))?;
+
+            let format = configure(&pcm, spec, opts).map_err(|err| open_error(device, err))?;
+
+            Ok(Box::new(AlsaOutput {
+                pcm,
+                device: device.to_string(),
+                format,
+                order,
+                dither: opts.dither.then(Dither::new),
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                int_buf: Vec::new(),
+                bytes: Vec::new(),
+            }))
+        }
+    }
+
+    /// Configures the device for the spec, and negotiates the sample format.
+    fn configure(
+        pcm: &PCM,
+        spec: SignalSpec,
+        opts: &OutputOptions,
+    ) -> std::result::Result<SampleFormat, String> {
+        let n_channels = spec.channels.count();
+
+        let hwp = HwParams::any(pcm).map_err(|err| err.to_string())?;
+        hwp.set_access(Access::RWInterleaved).map_err(|err| err.to_string())?;
+
+        hwp.set_channels(n_channels as u32)
+            .map_err(|_| format!("{} channels are not supported", n_channels))?;
+
+        // The player resamples the audio if the exact rate is not supported.
+        hwp.set_rate(spec.rate, ValueOr::Nearest).map_err(|err| err.to_string())?;
+
+        if hwp.get_rate().map_err(|err| err.to_string())? != spec.rate {
+            return Err(format!("{} Hz is not supported", spec.rate));
+        }
+
+        // Prefer the requested sample format.
+        let requested = FORMATS.iter().filter(|(format, _)| Some(*format) == opts.format);
+
+        let &(format, alsa_format) = requested
+            .chain(FORMATS.iter())
+            .find(|(_, alsa_format)| hwp.test_format(*alsa_format).is_ok())
+            .ok_or_else(|| "no supported sample format".to_string())?;
+
+        hwp.set_format(alsa_format).map_err(|err| err.to_string())?;
+
+        match opts.buffer_size {
+            Some(frames) => hwp.set_buffer_size_near(Frames::from(frames)).map(|_| ()),
+            None => hwp.set_buffer_time_near(BUFFER_TIME, ValueOr::Nearest).map(|_| ()),
+        }
+        .map_err(|err| err.to_string())?;
+
+        let period_size = match opts.period_size {
+            Some(frames) => Frames::from(frames),
+            None => hwp.get_buffer_size().map_err(|err| err.to_string())? / PERIODS,
+        };
+
+        hwp.set_period_size_near(period_size, ValueOr::Nearest).map_err(|err| err.to_string())?;
+
+        pcm.hw_params(&hwp).map_err(|err| err.to_string())?;
+
+        let hwp = pcm.hw_params_current().map_err(|err| err.to_string())?;
+        let buffer_size = hwp.get_buffer_size().map_err(|err| err.to_string())?;
+        let period_size = hwp.get_period_size().map_err(|err| err.to_string())?;
+
+        // Start playing once the buffer is nearly full, so that it does not underrun right away,
+        // and wake up to write a period at a time.
+        let swp = pcm.sw_params_current().map_err(|err| err.to_string())?;
+        swp.set_start_threshold(buffer_size - period_size).map_err(|err| err.to_string())?;
+        swp.set_avail_min(period_size).map_err(|err| err.to_string())?;
+        pcm.sw_params(&swp).map_err(|err| err.to_string())?;
+
+        info!(
+            "alsa: {:?} samples, buffer of {} frames, period of {} frames",
+            format, buffer_size, period_size
+        );
+
+        Ok(format)
+    }
+
+    impl AudioOutput for AlsaOutput {
+        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            // Do nothing if there are no audio frames.
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            // Grow the sample buffer if the buffer is larger than the output was opened with.
+            if decoded.frames() * decoded.spec().channels.count() > self.sample_buf.capacity() {
+                let duration = decoded.capacity() as Duration;
+                self.sample_buf = SampleBuffer::<f32>::new(duration, *decoded.spec());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            let samples = self.sample_buf.samples();
+            let n_channels = self.order.len();
+
+            // Interleave the samples in the channel order of the device, in native byte order.
+            self.bytes.clear();
+
+            match self.format {
+                SampleFormat::F32 => {
+                    for frame in samples.chunks_exact(n_channels) {
+                        for &c in &self.order {
+                            self.bytes.extend_from_slice(&frame[c].to_ne_bytes());
+                        }
+                    }
+                }
+                format => {
+                    quantize(samples, format.bits(), self.dither.as_mut(), &mut self.int_buf);
+
+                    for frame in self.int_buf.chunks_exact(n_channels) {
+                        for &c in &self.order {
+                            match format {
+                                SampleFormat::S16 => {
+                                    self.bytes.extend_from_slice(&(frame[c] as i16).to_ne_bytes())
+                                }
+                                _ => self.bytes.extend_from_slice(&frame[c].to_ne_bytes()),
+                            }
+                        }
+                    }
+                }
+            }
+
+            let frame_len = self.bytes.len() / (samples.len() / n_channels);
+
+            let io = self.pcm.io_bytes();
+            let mut offset = 0;
+
+            while offset < self.bytes.len() {
+                match io.writei(&self.bytes[offset..]) {
+                    Ok(frames) => offset += frames * frame_len,
+                    Err(err) => {
+                        // Recover from an underrun or a suspended device, and write again.
+                        let msg = err.to_string();
+
+                        self.pcm.try_recover(err, true).map_err(|err| {
+                            AudioOutputError::StreamClosedError(format!("{}: {}", self.device, err))
+                        })?;
+
+                        warn!("alsa: {}: recovered from {}", self.device, msg);
+                    }
+                }
+            }
+
+            Ok(())
+        }
+
+        fn flush(&mut self) {
+            // Flush is best-effort, ignore the returned result.
+            let _ = self.pcm.drain();
+        }
+    }
+
+    /// Lists the ALSA devices that can play audio.
+    pub fn list_devices() -> std::result::Result<Vec<Device>, String> {
+        let hints = HintIter::new_str(None, "pcm").map_err(|err| err.to_string())?;
+
+        let devices = hints
+            .filter(|hint| hint.direction.map_or(true, |dir| dir == Direction::Playback))
+            .filter_map(|hint| {
+                // Descriptions are split over lines.
+                let description = hint.desc.unwrap_or_default().replace('\n', ", ");
+                Some(Device { name: hint.name?, description })
+            })
+            .collect();
+
+        Ok(devices)
+    }
+
+    /// Gets the channels closest to `channels` that ALSA devices can play. Other layouts are
+    /// mixed down to stereo.
+    pub fn supported_channels(channels: Channels) -> Channels {
+        if channels.iter().all(|channel| CHANNEL_ORDER.contains(&channel)) {
+            channels
+        }
+        else {
+            Channels::FRONT_LEFT | Channels::FRONT_RIGHT
+        }
+    }
+}
+
+/// The length of the audio queued for JACK or PipeWire, in microseconds, if no buffer size was
+/// requested.
+#[cfg(any(feature = "jack", all(target_os = "linux", feature = "pipewire")))]
+const QUEUE_TIME: u64 = 200_000;
+
+/// Passes audio to the realtime callback of JACK or PipeWire through a lock-free ring buffer, so
+/// that the callback never waits for the player.
+#[cfg(any(feature = "jack", all(target_os = "linux", feature = "pipewire")))]
+mod ring {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time;
+
+    use super::{AudioOutputError, OutputOptions, Result, QUEUE_TIME};
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+
+    /// The writing end of the ring buffer.
+    pub struct RingWriter {
+        name: &'static str,
+        producer: HeapProducer<f32>,
+        sample_buf: SampleBuffer<f32>,
+        /// Set by the sound server when the stream stops.
+        closed: Arc<AtomicBool>,
+        /// How long to wait for the callback to make space in the ring buffer.
+        wait: time::Duration,
+    }
+
+    impl RingWriter {
+        /// Creates a ring buffer of interleaved samples. Returns the writer, the reader for the
+        /// callback, and the flag the sound server sets when the stream stops.
+        pub fn new(
+            name: &'static str,
+            spec: SignalSpec,
+            duration: Duration,
+            opts: &OutputOptions,
+        ) -> (RingWriter, HeapConsumer<f32>, Arc<AtomicBool>) {
+            let frames = match opts.buffer_size {
+                Some(frames) => u64::from(frames),
+                None => QUEUE_TIME * u64::from(spec.rate) / 1_000_000,
+            };
+
+            let n_channels = spec.channels.count();
+            let capacity = (frames as usize).max(1) * n_channels;
+
+            let (producer, consumer) = HeapRb::<f32>::new(capacity).split();
+            let closed = Arc::new(AtomicBool::new(false));
+
+            let writer = RingWriter {
+                name,
+                producer,
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                closed: Arc::clone(&closed),
+                wait: time::Duration::from_micros(frames * 250_000 / u64::from(spec.rate)),
+            };
+
+            (writer, consumer, closed)
+        }
+
+        /// Queues a buffer of audio, waiting for space in the ring buffer.
+        pub fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            // Do nothing if there are no audio frames.
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            // Grow the sample buffer if the buffer is larger than the output was opened with.
+            if decoded.frames() * decoded.spec().channels.count() > self.sample_buf.capacity() {
+                let duration = decoded.capacity() as Duration;
+                self.sample_buf = SampleBuffer::<f32>::new(duration, *decoded.spec());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+
+            let mut samples = self.sample_buf.samples();
+
+            loop {
+                if self.closed.load(Ordering::Acquire) {
+                    let msg = format!("the {} stream was closed", self.name);
+                    return Err(AudioOutputError::StreamClosedError(msg));
+                }
+
+                let n = self.producer.push_slice(samples);
+                samples = &samples[n..];
+
+                if samples.is_empty() {
+                    break;
+                }
+
+                thread::sleep(self.wait);
+            }
+
+            Ok(())
+        }
+
+        /// Waits until the callback has taken all the queued audio, and for one more wait so that
+        /// the sound server plays the last of it.
+        pub fn drain(&mut self) {
+            while !self.producer.is_empty() && !self.closed.load(Ordering::Acquire) {
+                thread::sleep(self.wait);
+            }
+
+            thread::sleep(self.wait);
+        }
+    }
+}
+
+#[cfg(feature = "jack")]
+mod jack_output {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use super::ring::RingWriter;
+    use super::{AudioOutput, AudioOutputError, Device, OutputOptions, Result, APP_NAME};
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use ringbuf::HeapConsumer;
+
+    use log::{info, warn};
+
+    /// The number of frames the process callback moves from the ring buffer at a time.
+    const CHUNK_FRAMES: usize = 1024;
+
+    /// What the player needs to know about the JACK server to choose the spec of the output.
+    #[derive(Copy, Clone)]
+    struct Server {
+        rate: u32,
+        /// The number of playback ports the output connects to.
+        n_ports: usize,
+    }
+
+    /// The server as it was when the output was last opened, so that choosing the spec of the
+    /// output does not connect to the server each time. It is forgotten when the server shuts
+    /// down.
+    static SERVER: Mutex<Option<Server>> = Mutex::new(None);
+
+    pub struct JackOutput {
+        ring: RingWriter,
+        /// The active client. Dropping it deactivates the client and closes its ports.
+        _client: jack::AsyncClient<Notifications, Process>,
+        /// Set when the JACK server shuts down.
+        closed: Arc<AtomicBool>,
+    }
+
+    /// Marks the stream closed when the JACK server shuts down.
+    struct Notifications {
+        closed: Arc<AtomicBool>,
+    }
+
+    impl jack::NotificationHandler for Notifications {
+        fn shutdown(&mut self, _: jack::ClientStatus, _: &str) {
+            self.closed.store(true, Ordering::Release);
+        }
+    }
+
+    /// Copies the queued audio to the ports in the realtime thread of the JACK server.
+    struct Process {
+        ports: Vec<jack::Port<jack::AudioOut>>,
+        consumer: HeapConsumer<f32>,
+        /// A chunk of interleaved samples, allocated up front so the callback does not allocate.
+        chunk: Vec<f32>,
+    }
+
+    impl jack::ProcessHandler for Process {
+        fn process(&mut self, _: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+            let n_channels = self.ports.len();
+
+            // Take whole frames only. If the player fell behind, the rest of the cycle is silent.
+            let n_frames = (self.consumer.len() / n_channels).min(ps.n_frames() as usize);
+
+            let mut start = 0;
+
+            while start < n_frames {
+                let len = (n_frames - start).min(CHUNK_FRAMES);
+                let chunk = &mut self.chunk[..len * n_channels];
+
+                self.consumer.pop_slice(chunk);
+
+                for (c, port) in self.ports.iter_mut().enumerate() {
+                    let out = &mut port.as_mut_slice(ps)[start..start + len];
+
+                    for (out, frame) in out.iter_mut().zip(chunk.chunks_exact(n_channels)) {
+                        *out = frame[c];
+                    }
+                }
+
+                start += len;
+            }
+
+            for port in self.ports.iter_mut() {
+                port.as_mut_slice(ps)[n_frames..].fill(0.0);
+            }
+
+            jack::Control::Continue
+        }
+    }
+
+    fn open_error(err: impl std::fmt::Display) -> AudioOutputError {
+        AudioOutputError::OpenStreamError(format!("jack: {}", err))
+    }
+
+    /// Connects to the JACK server, without starting one.
+    fn connect(opts: &OutputOptions) -> std::result::Result<jack::Client, jack::Error> {
+        let name = opts.stream_name.as_deref().unwrap_or(APP_NAME);
+
+        jack::Client::new(name, jack::ClientOptions::NO_START_SERVER).map(|(client, _)| client)
+    }
+
+    /// Gets the ports to play on, in order. These are the ports that match the device pattern,
+    /// or the physical playback ports.
+    fn playback_ports(client: &jack::Client, opts: &OutputOptions) -> Vec<String> {
+        let flags = match opts.device {
+            Some(_) => jack::PortFlags::IS_INPUT,
+            None => jack::PortFlags::IS_INPUT | jack::PortFlags::IS_PHYSICAL,
+        };
+
+        client.ports(opts.device.as_deref(), Some(jack::jack_sys::FLOAT_MONO_AUDIO), flags)
+    }
+
+    /// Gets the server the output was last opened on, or connects to the server if there is
+    /// none.
+    fn server(opts: &OutputOptions) -> Option<Server> {
+        let mut server = SERVER.lock().unwrap();
+
+        if server.is_none() {
+            *server = connect(opts).ok().map(|client| Server {
+                rate: client.sample_rate() as u32,
+                n_ports: playback_ports(&client, opts).len(),
+            });
+        }
+
+        *server
+    }
+
+    impl JackOutput {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            opts: &OutputOptions,
+        ) -> Result<Box<dyn AudioOutput>> {
+            let client = connect(opts).map_err(open_error)?;
+
+            // The player resamples the audio to the rate of the server.
+            let rate = client.sample_rate() as u32;
+
+            let playback = playback_ports(&client, opts);
+
+            *SERVER.lock().unwrap() = Some(Server { rate, n_ports: playback.len() });
+
+            if rate != spec.rate {
+                return Err(open_error(format!("the server runs at {} Hz", rate)));
+            }
+
+            let n_channels = spec.channels.count();
+
+            let ports = (1..=n_channels)
+                .map(|i| client.register_port(&format!("out_{}", i), jack::AudioOut))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(open_error)?;
+
+            let names: Vec<String> = ports.iter().filter_map(|port| port.name().ok()).collect();
+
+            let (ring, consumer, closed) = RingWriter::new("jack", spec, duration, opts);
+
+            let process = Process { ports, consumer, chunk: vec![0.0; CHUNK_FRAMES * n_channels] };
+
+            let notifications = Notifications { closed: Arc::clone(&closed) };
+
+            let client = client.activate_async(notifications, process).map_err(open_error)?;
+
+            // Connect the channels to the playback ports in order. Channels beyond the number of
+            // playback ports are left for the user to route.
+            for (port, target) in names.iter().zip(&playback) {
+                if let Err(err) = client.as_client().connect_ports_by_name(port, target) {
+                    warn!("jack: cannot connect {} to {}: {}", port, target, err);
+                }
+            }
+
+            info!("jack: {} ports at {} Hz, {} playback ports", n_channels, rate, playback.len());
+
+            Ok(Box::new(JackOutput { ring, _client: client, closed }))
+        }
+    }
+
+    impl Drop for JackOutput {
+        fn drop(&mut self) {
+            // The server may come back with another rate or other ports.
+            if self.closed.load(Ordering::Acquire) {
+                *SERVER.lock().unwrap() = None;
+            }
+        }
+    }
+
+    impl AudioOutput for JackOutput {
+        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            self.ring.write(decoded)
+        }
+
+        fn flush(&mut self) {
+            self.ring.drain();
+        }
+    }
+
+    /// Lists the audio input ports of the JACK server.
+    pub fn list_devices(opts: &OutputOptions) -> std::result::Result<Vec<Device>, String> {
+        let client = connect(opts).map_err(|err| err.to_string())?;
+
+        let audio = Some(jack::jack_sys::FLOAT_MONO_AUDIO);
+        let physical = client.ports(None, audio, jack::PortFlags::IS_PHYSICAL);
+
+        let devices = client
+            .ports(None, audio, jack::PortFlags::IS_INPUT)
+            .into_iter()
+            .map(|name| {
+                let description = if physical.contains(&name) {
+                    "physical playback port"
+                }
+                else {
+                    "input port"
+                };
+
+                Device { name, description: description.to_string() }
+            })
+            .collect();
+
+        Ok(devices)
+    }
+
+    /// Gets the sample rate of the JACK server, or `rate` if there is no server.
+    pub fn supported_rate(opts: &OutputOptions, rate: u32) -> u32 {
+        server(opts).map_or(rate, |server| server.rate)
+    }
+
+    /// Gets the channels closest to `channels` that the JACK server can play. Audio with more
+    /// channels than the server has playback ports is mixed down to a standard layout with one
+    /// channel per port.
+    pub fn supported_channels(opts: &OutputOptions, channels: Channels) -> Channels {
+        let n_ports = match server(opts) {
+            Some(server) => server.n_ports,
+            None => return channels,
+        };
+
+        if n_ports > 0 && channels.count() > n_ports {
+            crate::mixer::layout(n_ports).unwrap_or(channels)
+        }
+        else {
+            channels
+        }
+    }
+}
+
+/// Plays on a PipeWire server through its native API. The PipeWire library is loaded when the
+/// output is opened, so that the player is built and runs without it.
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+#[allow(unsafe_code)]
+mod pipewire_output {
+    use std::cell::UnsafeCell;
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_int, c_void};
+    use std::ptr;
+    use std::slice;
+    use std::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, Ordering};
+    use std::sync::Arc;
+
+    use super::ring::RingWriter;
+    use super::{AudioOutput, AudioOutputError, Device, OutputOptions, Result};
+    use super::{APP_NAME, STREAM_NAME};
+
+    use symphonia::core::audio::*;
+    use symphonia::core::units::Duration;
+
+    use libloading::Library;
+    use ringbuf::HeapConsumer;
+
+    use log::warn;
+
+    /// The name of the PipeWire library.
+    const LIBRARY: &str = "libpipewire-0.3.so.0";
+
+    /// How long to wait for the server to create the stream, in seconds.
+    const CONNECT_TIMEOUT: c_int = 5;
+
+    // SPA pod types, from spa/utils/type.h.
+    const SPA_TYPE_ID: u32 = 3;
+    const SPA_TYPE_INT: u32 = 4;
+    const SPA_TYPE_ARRAY: u32 = 13;
+    const SPA_TYPE_OBJECT: u32 = 15;
+    const SPA_TYPE_OBJECT_FORMAT: u32 = 0x40003;
+
+    // The EnumFormat parameter, and the keys of its properties, from spa/param/param.h and
+    // spa/param/format.h.
+    const SPA_PARAM_ENUM_FORMAT: u32 = 3;
+    const SPA_FORMAT_MEDIA_TYPE: u32 = 1;
+    const SPA_FORMAT_MEDIA_SUBTYPE: u32 = 2;
+    const SPA_FORMAT_AUDIO_FORMAT: u32 = 0x10001;
+    const SPA_FORMAT_AUDIO_RATE: u32 = 0x10003;
+    const SPA_FORMAT_AUDIO_CHANNELS: u32 = 0x10004;
+    const SPA_FORMAT_AUDIO_POSITION: u32 = 0x10005;
+
+    const SPA_MEDIA_TYPE_AUDIO: u32 = 1;
+    const SPA_MEDIA_SUBTYPE_RAW: u32 = 1;
+
+    // Native-endian 32-bit float samples, from spa/param/audio/raw.h.
+    #[cfg(target_endian = "little")]
+    const SPA_AUDIO_FORMAT_F32: u32 = 0x11b;
+    #[cfg(target_endian = "big")]
+    const SPA_AUDIO_FORMAT_F32: u32 = 0x11c;
+
+    /// The first auxiliary channel position, for channels without a standard position.
+    const SPA_AUDIO_CHANNEL_AUX0: u32 = 0x1000;
+
+    const SPA_DIRECTION_OUTPUT: u32 = 1;
+    const PW_ID_ANY: u32 = 0xffff_ffff;
+    const PW_ID_CORE: u32 = 0;
+
+    /// The version of the registry interface, from pipewire/core.h.
+    const PW_VERSION_REGISTRY: u32 = 3;
+
+    // Stream flags, from pipewire/stream.h.
+    const PW_STREAM_FLAG_AUTOCONNECT: u32 = 1 << 0;
+    const PW_STREAM_FLAG_MAP_BUFFERS: u32 = 1 << 2;
+    const PW_STREAM_FLAG_RT_PROCESS: u32 = 1 << 4;
+
+    // Stream states, from pipewire/stream.h.
+    const PW_STREAM_STATE_ERROR: i32 = -1;
+    const PW_STREAM_STATE_UNCONNECTED: i32 = 0;
+    const PW_STREAM_STATE_CONNECTING: i32 = 1;
+
+    #[repr(C)]
+    struct SpaDictItem {
+        key: *const c_char,
+        value: *const c_char,
+    }
+
+    #[repr(C)]
+    struct SpaDict {
+        flags: u32,
+        n_items: u32,
+        items: *const SpaDictItem,
+    }
+
+    #[repr(C)]
+    struct SpaChunk {
+        offset: u32,
+        size: u32,
+        stride: i32,
+        flags: i32,
+    }
+
+    #[repr(C)]
+    struct SpaData {
+        type_: u32,
+        flags: u32,
+        fd: i64,
+        mapoffset: u32,
+        maxsize: u32,
+        data: *mut c_void,
+        chunk: *mut SpaChunk,
+    }
+
+    #[repr(C)]
+    struct SpaBuffer {
+        n_metas: u32,
+        n_datas: u32,
+        metas: *mut c_void,
+        datas: *mut SpaData,
+    }
+
+    #[repr(C)]
+    struct PwBuffer {
+        buffer: *mut SpaBuffer,
+        user_data: *mut c_void,
+        size: u64,
+        /// The number of frames the server wants in this cycle, or 0 if unknown. Since 0.3.49.
+        requested: u64,
+    }
+
+    /// The events of a stream, up to the version 0 events.
+    #[repr(C)]
+    struct StreamEvents {
+        version: u32,
+        destroy: Option<unsafe extern "C" fn(*mut c_void)>,
+        state_changed: Option<unsafe extern "C" fn(*mut c_void, i32, i32, *const c_char)>,
+        control_info: Option<unsafe extern "C" fn(*mut c_void, u32, *const c_void)>,
+        io_changed: Option<unsafe extern "C" fn(*mut c_void, u32, *mut c_void, u32)>,
+        param_changed: Option<unsafe extern "C" fn(*mut c_void, u32, *const c_void)>,
+        add_buffer: Option<unsafe extern "C" fn(*mut c_void, *mut PwBuffer)>,
+        remove_buffer: Option<unsafe extern "C" fn(*mut c_void, *mut PwBuffer)>,
+        process: Option<unsafe extern "C" fn(*mut c_void)>,
+        drained: Option<unsafe extern "C" fn(*mut c_void)>,
+    }
+
+    /// The callbacks of a PipeWire interface, and the object they are called with.
+    #[repr(C)]
+    struct SpaCallbacks {
+        funcs: *const c_void,
+        data: *mut c_void,
+    }
+
+    /// The start of every PipeWire proxy, such as the core and the registry.
+    #[repr(C)]
+    struct SpaInterface {
+        type_: *const c_char,
+        version: u32,
+        cb: SpaCallbacks,
+    }
+
+    /// A listener of a PipeWire object: a list link, callbacks, a removed callback and private
+    /// data, all filled in by PipeWire.
+    #[repr(C)]
+    struct SpaHook {
+        fields: [*mut c_void; 6],
+    }
+
+    impl SpaHook {
+        fn new() -> Self {
+            SpaHook { fields: [ptr::null_mut(); 6] }
+        }
+    }
+
+    /// The methods of the core, up to the ones that are used.
+    #[repr(C)]
+    struct CoreMethods {
+        version: u32,
+        add_listener: unsafe extern "C" fn(
+            *mut c_void,
+            *mut SpaHook,
+            *const CoreEvents,
+            *mut c_void,
+        ) -> c_int,
+        hello: *const c_void,
+        sync: unsafe extern "C" fn(*mut c_void, u32, c_int) -> c_int,
+        pong: *const c_void,
+        error: *const c_void,
+        get_registry: unsafe extern "C" fn(*mut c_void, u32, usize) -> *mut Registry,
+    }
+
+    /// The events of the core, up to the version 0 events.
+    #[repr(C)]
+    struct CoreEvents {
+        version: u32,
+        info: Option<unsafe extern "C" fn(*mut c_void, *const c_void)>,
+        done: Option<unsafe extern "C" fn(*mut c_void, u32, c_int)>,
+        ping: Option<unsafe extern "C" fn(*mut c_void, u32, c_int)>,
+        error: Option<unsafe extern "C" fn(*mut c_void, u32, c_int, c_int, *const c_char)>,
+        remove_id: Option<unsafe extern "C" fn(*mut c_void, u32)>,
+        bound_id: Option<unsafe extern "C" fn(*mut c_void, u32, u32)>,
+        add_mem: Option<unsafe extern "C" fn(*mut c_void, u32, u32, c_int, u32)>,
+        remove_mem: Option<unsafe extern "C" fn(*mut c_void, u32)>,
+    }
+
+    /// The methods of the registry, up to the ones that are used.
+    #[repr(C)]
+    struct RegistryMethods {
+        version: u32,
+        add_listener: unsafe extern "C" fn(
+            *mut c_void,
+            *mut SpaHook,
+            *const RegistryEvents,
+            *mut c_void,
+        ) -> c_int,
+    }
+
+    /// The events of the registry, up to the version 0 events.
+    #[repr(C)]
+    struct RegistryEvents {
+        version: u32,
+        global: Option<
+            unsafe extern "C" fn(*mut c_void, u32, u32, *const c_char, u32, *const SpaDict),
+        >,
+        global_remove: Option<unsafe extern "C" fn(*mut c_void, u32)>,
+    }
+
+    static CORE_EVENTS: CoreEvents = CoreEvents {
+        version: 0,
+        info: None,
+        done: Some(on_core_done),
+        ping: None,
+        error: Some(on_core_error),
+        remove_id: None,
+        bound_id: None,
+        add_mem: None,
+        remove_mem: None,
+    };
+
+    static REGISTRY_EVENTS: RegistryEvents =
+        RegistryEvents { version: 0, global: Some(on_global), global_remove: None };
+
+    static STREAM_EVENTS: StreamEvents = StreamEvents {
+        version: 0,
+        destroy: None,
+        state_changed: Some(on_state_changed),
+        control_info: None,
+        io_changed: None,
+        param_changed: None,
+        add_buffer: None,
+        remove_buffer: None,
+        process: Some(on_process),
+        drained: None,
+    };
+
+    // Opaque PipeWire objects.
+    enum ThreadLoop {}
+    enum Loop {}
+    enum Properties {}
+    enum Stream {}
+    enum MainLoop {}
+    enum Context {}
+    enum Core {}
+    enum Registry {}
+
+    /// The functions of the PipeWire library that are used.
+    struct Lib {
+        init: unsafe extern "C" fn(*mut c_int, *mut *mut *mut c_char),
+        check_library_version: unsafe extern "C" fn(c_int, c_int, c_int) -> bool,
+        thread_loop_new: unsafe extern "C" fn(*const c_char, *const SpaDict) -> *mut ThreadLoop,
+        thread_loop_destroy: unsafe extern "C" fn(*mut ThreadLoop),
+        thread_loop_start: unsafe extern "C" fn(*mut ThreadLoop) -> c_int,
+        thread_loop_stop: unsafe extern "C" fn(*mut ThreadLoop),
+        thread_loop_lock: unsafe extern "C" fn(*mut ThreadLoop),
+        thread_loop_unlock: unsafe extern "C" fn(*mut ThreadLoop),
+        thread_loop_signal: unsafe extern "C" fn(*mut ThreadLoop, bool),
+        thread_loop_timed_wait: unsafe extern "C" fn(*mut ThreadLoop, c_int) -> c_int,
+        thread_loop_get_loop: unsafe extern "C" fn(*mut ThreadLoop) -> *mut Loop,
+        properties_new_dict: unsafe extern "C" fn(*const SpaDict) -> *mut Properties,
+        stream_new_simple: unsafe extern "C" fn(
+            *mut Loop,
+            *const c_char,
+            *mut Properties,
+            *const StreamEvents,
+            *mut c_void,
+        ) -> *mut Stream,
+        stream_connect:
+            unsafe extern "C" fn(*mut Stream, u32, u32, u32, *mut *const u32, u32) -> c_int,
+        stream_destroy: unsafe extern "C" fn(*mut Stream),
+        stream_dequeue_buffer: unsafe extern "C" fn(*mut Stream) -> *mut PwBuffer,
+        stream_queue_buffer: unsafe extern "C" fn(*mut Stream, *mut PwBuffer) -> c_int,
+        main_loop_new: unsafe extern "C" fn(*const SpaDict) -> *mut MainLoop,
+        main_loop_destroy: unsafe extern "C" fn(*mut MainLoop),
+        main_loop_get_loop: unsafe extern "C" fn(*mut MainLoop) -> *mut Loop,
+        main_loop_run: unsafe extern "C" fn(*mut MainLoop) -> c_int,
+        main_loop_quit: unsafe extern "C" fn(*mut MainLoop) -> c_int,
+        context_new: unsafe extern "C" fn(*mut Loop, *mut Properties, usize) -> *mut Context,
+        context_destroy: unsafe extern "C" fn(*mut Context),
+        context_connect: unsafe extern "C" fn(*mut Context, *mut Properties, usize) -> *mut Core,
+        core_disconnect: unsafe extern "C" fn(*mut Core) -> c_int,
+        /// Only the registry is destroyed as a proxy.
+        proxy_destroy: unsafe extern "C" fn(*mut Registry),
+        /// The library is unloaded when dropped, so it is kept for as long as the functions.
+        _library: Library,
+    }
+
+    impl Lib {
+        /// Loads the PipeWire library, and initializes it.
+        fn load() -> std::result::Result<Lib, String> {
+            // Safety: the library is PipeWire, and the types of the functions match its headers.
+            unsafe {
+                let library = Library::new(LIBRARY).map_err(|err| err.to_string())?;
+
+                macro_rules! get {
+                    ($name:literal) => {
+                        *library
+                            .get(concat!($name, "\0").as_bytes())
+                            .map_err(|err| format!("{}: {}", $name, err))?
+                    };
+                }
+
+                let lib = Lib {
+                    init: get!("pw_init"),
+                    check_library_version: get!("pw_check_library_version"),
+                    thread_loop_new: get!("pw_thread_loop_new"),
+                    thread_loop_destroy: get!("pw_thread_loop_destroy"),
+                    thread_loop_start: get!("pw_thread_loop_start"),
+                    thread_loop_stop: get!("pw_thread_loop_stop"),
+                    thread_loop_lock: get!("pw_thread_loop_lock"),
+                    thread_loop_unlock: get!("pw_thread_loop_unlock"),
+                    thread_loop_signal: get!("pw_thread_loop_signal"),
+                    thread_loop_timed_wait: get!("pw_thread_loop_timed_wait"),
+                    thread_loop_get_loop: get!("pw_thread_loop_get_loop"),
+                    properties_new_dict: get!("pw_properties_new_dict"),
+                    stream_new_simple: get!("pw_stream_new_simple"),
+                    stream_connect: get!("pw_stream_connect"),
+                    stream_destroy: get!("pw_stream_destroy"),
+                    stream_dequeue_buffer: get!("pw_stream_dequeue_buffer"),
+                    stream_queue_buffer: get!("pw_stream_queue_buffer"),
+                    main_loop_new: get!("pw_main_loop_new"),
+                    main_loop_destroy: get!("pw_main_loop_destroy"),
+                    main_loop_get_loop: get!("pw_main_loop_get_loop"),
+                    main_loop_run: get!("pw_main_loop_run"),
+                    main_loop_quit: get!("pw_main_loop_quit"),
+                    context_new: get!("pw_context_new"),
+                    context_destroy: get!("pw_context_destroy"),
+                    context_connect: get!("pw_context_connect"),
+                    core_disconnect: get!("pw_core_disconnect"),
+                    proxy_destroy: get!("pw_proxy_destroy"),
+                    _library: library,
+                };
+
+                // The number of frames requested by the server was added to buffers in 0.3.49.
+                if !(lib.check_library_version)(0, 3, 49) {
+                    return Err("PipeWire 0.3.49 or later is required".to_string());
+                }
+
+                (lib.init)(ptr::null_mut(), ptr::null_mut());
+
+                Ok(lib)
+            }
+        }
+    }
+
+    /// Properties of PipeWire objects, kept as C strings for as long as they are used.
+    struct Props(Vec<(CString, CString)>);
+
+    impl Props {
+        fn new() -> Self {
+            Props(Vec::new())
+        }
+
+        fn insert(&mut self, key: &str, value: &str) {
+            // Interior nul bytes cannot be passed to PipeWire, so they end the value.
+            let value = value.split('\0').next().unwrap_or_default();
+            self.0.push((CString::new(key).unwrap(), CString::new(value).unwrap()));
+        }
+
+        /// Creates PipeWire properties with a copy of these properties.
+        fn to_properties(&self, lib: &Lib) -> *mut Properties {
+            let items: Vec<SpaDictItem> = self
+                .0
+                .iter()
+                .map(|(key, value)| SpaDictItem { key: key.as_ptr(), value: value.as_ptr() })
+                .collect();
+
+            let dict = SpaDict { flags: 0, n_items: items.len() as u32, items: items.as_ptr() };
+
+            // Safety: the dictionary and its strings outlive the call, which copies them.
+            unsafe { (lib.properties_new_dict)(&dict) }
+        }
+    }
+
+    /// The state shared with the callbacks of the stream, which run on the threads of the loop.
+    struct Shared {
+        lib: Arc<Lib>,
+        thread_loop: *mut ThreadLoop,
+        stream: AtomicPtr<Stream>,
+        /// The reading end of the ring buffer. It is only used by the process callback.
+        consumer: UnsafeCell<HeapConsumer<f32>>,
+        n_channels: usize,
+        /// The state of the stream.
+        state: AtomicI32,
+        /// Set when the stream fails or is disconnected.
+        closed: Arc<AtomicBool>,
+    }
+
+    pub struct PipeWireOutput {
+        ring: RingWriter,
+        lib: Arc<Lib>,
+        thread_loop: *mut ThreadLoop,
+        stream: *mut Stream,
+        /// Freed once the stream, and so its callbacks, are gone.
+        shared: *mut Shared,
+    }
+
+    fn open_error(err: impl std::fmt::Display) -> AudioOutputError {
+        AudioOutputError::OpenStreamError(format!("pipewire: {}", err))
+    }
+
+    impl PipeWireOutput {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            opts: &OutputOptions,
+        ) -> Result<Box<dyn AudioOutput>> {
+            let lib = Arc::new(Lib::load().map_err(open_error)?);
+
+            let (ring, consumer, closed) = RingWriter::new("pipewire", spec, duration, opts);
+
+            // Ask for a quarter of the ring buffer per cycle, unless a period size was requested.
+            let period = match opts.period_size {
+                Some(frames) => frames as usize,
+                None => consumer.capacity() / spec.channels.count() / 4,
+            };
+
+            let props = stream_props(opts, &format!("{}/{}", period.max(1), spec.rate));
+
+            let positions: Vec<u32> = spec
+                .channels
+                .iter()
+                .enumerate()
+                .map(|(i, channel)| map_channel(channel, spec.channels.count() == 1, i))
+                .collect();
+
+            let format = format_pod(spec.rate, &positions);
+
+            let name = CString::new(opts.stream_name.as_deref().unwrap_or(STREAM_NAME))
+                .unwrap_or_else(|_| CString::new(STREAM_NAME).unwrap());
+
+            // Safety: the loop and stream are used while the loop is locked, and destroyed when
+            // the output is dropped. The shared state outlives them.
+            unsafe {
+                let loop_name = b"pipewire\0".as_ptr() as *const c_char;
+                let thread_loop = (lib.thread_loop_new)(loop_name, ptr::null());
+
+                if thread_loop.is_null() {
+                    return Err(open_error("cannot create a loop"));
+                }
+
+                let shared = Box::into_raw(Box::new(Shared {
+                    lib: Arc::clone(&lib),
+                    thread_loop,
+                    stream: AtomicPtr::new(ptr::null_mut()),
+                    consumer: UnsafeCell::new(consumer),
+                    n_channels: spec.channels.count(),
+                    state: AtomicI32::new(PW_STREAM_STATE_UNCONNECTED),
+                    closed,
+                }));
+
+                let mut output = PipeWireOutput {
+                    ring,
+                    lib: Arc::clone(&lib),
+                    thread_loop,
+                    stream: ptr::null_mut(),
+                    shared,
+                };
+
+                if (lib.thread_loop_start)(thread_loop) < 0 {
+                    return Err(open_error("cannot start the loop"));
+                }
+
+                (lib.thread_loop_lock)(thread_loop);
+
+                let result = output.connect(&name, &props, &format);
+
+                (lib.thread_loop_unlock)(thread_loop);
+
+                result.map(|_| Box::new(output) as Box<dyn AudioOutput>)
+            }
+        }
+
+        /// Creates and connects the stream, and waits for the server to create it. The loop must
+        /// be locked.
+        unsafe fn connect(&mut self, name: &CString, props: &Props, format: &[u32]) -> Result<()> {
+            let lib = &self.lib;
+            let shared = &*self.shared;
+
+            let stream = (lib.stream_new_simple)(
+                (lib.thread_loop_get_loop)(self.thread_loop),
+                name.as_ptr(),
+                props.to_properties(lib),
+                &STREAM_EVENTS,
+                self.shared as *mut c_void,
+            );
+
+            if stream.is_null() {
+                return Err(open_error("cannot create the stream"));
+            }
+
+            self.stream = stream;
+            shared.stream.store(stream, Ordering::Release);
+
+            let mut params = [format.as_ptr()];
+            let flags =
+                PW_STREAM_FLAG_AUTOCONNECT | PW_STREAM_FLAG_MAP_BUFFERS | PW_STREAM_FLAG_RT_PROCESS;
+
+            let err = (lib.stream_connect)(
+                stream,
+                SPA_DIRECTION_OUTPUT,
+                PW_ID_ANY,
+                flags,
+                params.as_mut_ptr(),
+                1,
+            );
+
+            if err < 0 {
+                return Err(open_error(std::io::Error::from_raw_os_error(-err)));
+            }
+
+            // The state changes once the server has created the stream, or failed to.
+            loop {
+                match shared.state.load(Ordering::Acquire) {
+                    PW_STREAM_STATE_UNCONNECTED | PW_STREAM_STATE_CONNECTING => (),
+                    PW_STREAM_STATE_ERROR => {
+                        return Err(open_error("the server refused the stream"));
+                    }
+                    _ => return Ok(()),
+                }
+
+                if (lib.thread_loop_timed_wait)(self.thread_loop, CONNECT_TIMEOUT) != 0 {
+                    return Err(open_error("timed out connecting to the server"));
+                }
+            }
+        }
+    }
+
+    /// Gets the properties of the stream, including the latency it asks for.
+    fn stream_props(opts: &OutputOptions, latency: &str) -> Props {
+        let mut props = Props::new();
+
+        props.insert("application.name", APP_NAME);
+        props.insert("media.name", opts.stream_name.as_deref().unwrap_or(STREAM_NAME));
+        props.insert("media.type", "Audio");
+        props.insert("media.category", "Playback");
+        props.insert("media.role", "Music");
+        props.insert("node.latency", latency);
+
+        if let Some(device) = &opts.device {
+            props.insert("target.object", device);
+        }
+
+        if let Some(server) = &opts.server {
+            props.insert("remote.name", server);
+        }
+
+        props
+    }
+
+    /// The state of a listing of the devices, shared with the callbacks of the core and registry.
+    struct Listing<'a> {
+        lib: &'a Lib,
+        main_loop: *mut MainLoop,
+        /// The sequence number of the sync that the server answers once it listed every object.
+        seq: c_int,
+        devices: Vec<Device>,
+        error: Option<String>,
+    }
+
+    /// Lists the audio sinks of the PipeWire server.
+    pub fn list_devices(opts: &OutputOptions) -> std::result::Result<Vec<Device>, String> {
+        let lib = Lib::load()?;
+
+        let mut props = Props::new();
+
+        if let Some(server) = &opts.server {
+            props.insert("remote.name", server);
+        }
+
+        // Safety: the loop and context are destroyed here, after everything that uses them.
+        unsafe {
+            let main_loop = (lib.main_loop_new)(ptr::null());
+
+            if main_loop.is_null() {
+                return Err("cannot create a loop".to_string());
+            }
+
+            let loop_ = (lib.main_loop_get_loop)(main_loop);
+            let context = (lib.context_new)(loop_, ptr::null_mut(), 0);
+
+            let result = if context.is_null() {
+                Err("cannot create a context".to_string())
+            }
+            else {
+                let result = list_sinks(&lib, main_loop, context, &props);
+                (lib.context_destroy)(context);
+                result
+            };
+
+            (lib.main_loop_destroy)(main_loop);
+
+            result
+        }
+    }
+
+    /// Connects to the server, and runs the loop until it has announced every object.
+    unsafe fn list_sinks(
+        lib: &Lib,
+        main_loop: *mut MainLoop,
+        context: *mut Context,
+        props: &Props,
+    ) -> std::result::Result<Vec<Device>, String> {
+        let core = (lib.context_connect)(context, props.to_properties(lib), 0);
+
+        if core.is_null() {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+
+        let listing = Box::into_raw(Box::new(Listing {
+            lib,
+            main_loop,
+            seq: 0,
+            devices: Vec::new(),
+            error: None,
+        }));
+
+        // The hooks are linked into the listeners of the core and registry, so they must not
+        // move, and are only freed once both are gone.
+        let mut hooks = Box::new([SpaHook::new(), SpaHook::new()]);
+
+        let core = &*(core as *const SpaInterface);
+        let core_methods = &*(core.cb.funcs as *const CoreMethods);
+
+        (core_methods.add_listener)(core.cb.data, &mut hooks[0], &CORE_EVENTS, listing as _);
+
+        let registry = (core_methods.get_registry)(core.cb.data, PW_VERSION_REGISTRY, 0);
+
+        if !registry.is_null() {
+            let iface = &*(registry as *const SpaInterface);
+            let methods = &*(iface.cb.funcs as *const RegistryMethods);
+
+            (methods.add_listener)(iface.cb.data, &mut hooks[1], &REGISTRY_EVENTS, listing as _);
+
+            // The server answers the sync after it has announced the objects it already has.
+            (*listing).seq = (core_methods.sync)(core.cb.data, PW_ID_CORE, 0);
+
+            (lib.main_loop_run)(main_loop);
+            (lib.proxy_destroy)(registry);
+        }
+
+        (lib.core_disconnect)(core as *const SpaInterface as *mut Core);
+
+        drop(hooks);
+
+        let Listing { devices, error, .. } = *Box::from_raw(listing);
+
+        if registry.is_null() {
+            return Err("cannot get the registry".to_string());
+        }
+
+        match error {
+            Some(err) => Err(err),
+            None => Ok(devices),
+        }
+    }
+
+    /// Looks up the value of `key` in a dictionary of PipeWire properties.
+    unsafe fn lookup<'a>(dict: &'a SpaDict, key: &str) -> Option<&'a str> {
+        if dict.items.is_null() {
+            return None;
+        }
+
+        slice::from_raw_parts(dict.items, dict.n_items as usize)
+            .iter()
+            .filter(|item| !item.key.is_null())
+            .find(|item| CStr::from_ptr(item.key).to_bytes() == key.as_bytes())
+            .filter(|item| !item.value.is_null())
+            .and_then(|item| CStr::from_ptr(item.value).to_str().ok())
+    }
+
+    unsafe extern "C" fn on_global(
+        data: *mut c_void,
+        _id: u32,
+        _permissions: u32,
+        _type: *const c_char,
+        _version: u32,
+        props: *const SpaDict,
+    ) {
+        let listing = &mut *(data as *mut Listing<'_>);
+
+        if props.is_null() || lookup(&*props, "media.class") != Some("Audio/Sink") {
+            return;
+        }
+
+        if let Some(name) = lookup(&*props, "node.name") {
+            listing.devices.push(Device {
+                name: name.to_string(),
+                description: lookup(&*props, "node.description").unwrap_or_default().to_string(),
+            });
+        }
+    }
+
+    unsafe extern "C" fn on_core_done(data: *mut c_void, id: u32, seq: c_int) {
+        let listing = &*(data as *const Listing<'_>);
+
+        if id == PW_ID_CORE && seq == listing.seq {
+            (listing.lib.main_loop_quit)(listing.main_loop);
+        }
+    }
+
+    unsafe extern "C" fn on_core_error(
+        data: *mut c_void,
+        id: u32,
+        _seq: c_int,
+        res: c_int,
+        message: *const c_char,
+    ) {
+        let listing = &mut *(data as *mut Listing<'_>);
+
+        // Errors of the core end the connection. Errors of other objects are not fatal.
+        if id == PW_ID_CORE {
+            listing.error = Some(if message.is_null() {
+                std::io::Error::from_raw_os_error(-res).to_string()
+            }
+            else {
+                CStr::from_ptr(message).to_string_lossy().into_owned()
+            });
+
+            (listing.lib.main_loop_quit)(listing.main_loop);
+        }
+    }
+
+    /// Maps a Symphonia channel to a PipeWire channel position. Channels without a standard
+    /// position get the auxiliary position of their index.
+    pub(super) fn map_channel(channel: Channels, is_mono: bool, index: usize) -> u32 {
+        match channel {
+            Channels::FRONT_LEFT if is_mono => 2,
+            Channels::FRONT_LEFT => 3,
+            Channels::FRONT_RIGHT => 4,
+            Channels::FRONT_CENTRE => 5,
+            Channels::LFE1 => 6,
+            Channels::SIDE_LEFT => 7,
+            Channels::SIDE_RIGHT => 8,
+            Channels::FRONT_LEFT_CENTRE => 9,
+            Channels::FRONT_RIGHT_CENTRE => 10,
+            Channels::REAR_CENTRE => 11,
+            Channels::REAR_LEFT => 12,
+            Channels::REAR_RIGHT => 13,
+            Channels::TOP_CENTRE => 14,
+            Channels::TOP_FRONT_LEFT => 15,
+            Channels::TOP_FRONT_CENTRE => 16,
+            Channels::TOP_FRONT_RIGHT => 17,
+            Channels::TOP_REAR_LEFT => 18,
+            Channels::TOP_REAR_CENTRE => 19,
+            Channels::TOP_REAR_RIGHT => 20,
+            Channels::REAR_LEFT_CENTRE => 21,
+            Channels::REAR_RIGHT_CENTRE => 22,
+            Channels::FRONT_LEFT_WIDE => 23,
+            Channels::FRONT_RIGHT_WIDE => 24,
+            Channels::LFE2 => 25,
+            Channels::FRONT_LEFT_HIGH => 26,
+            Channels::FRONT_CENTRE_HIGH => 27,
+            Channels::FRONT_RIGHT_HIGH => 28,
+            _ => SPA_AUDIO_CHANNEL_AUX0 + index as u32,
+        }
+    }
+
+    /// Builds the SPA pod of the format the stream offers: interleaved native-endian 32-bit
+    /// float samples at `rate`, with a channel at each of `positions`. Pods are made of 32-bit
+    /// words, each value padded to 8 bytes.
+    pub(super) fn format_pod(rate: u32, positions: &[u32]) -> Vec<u32> {
+        let mut props = Vec::new();
+
+        // Each property is its key, its flags, and a pod of a size and a type.
+        for (key, type_, value) in [
+            (SPA_FORMAT_MEDIA_TYPE, SPA_TYPE_ID, SPA_MEDIA_TYPE_AUDIO),
+            (SPA_FORMAT_MEDIA_SUBTYPE, SPA_TYPE_ID, SPA_MEDIA_SUBTYPE_RAW),
+            (SPA_FORMAT_AUDIO_FORMAT, SPA_TYPE_ID, SPA_AUDIO_FORMAT_F32),
+            (SPA_FORMAT_AUDIO_RATE, SPA_TYPE_INT, rate),
+            (SPA_FORMAT_AUDIO_CHANNELS, SPA_TYPE_INT, positions.len() as u32),
+        ] {
+            props.extend_from_slice(&[key, 0, 4, type_, value, 0]);
+        }
+
+        // The array holds the size and type of its items, then the items.
+        let array_len = 8 + 4 * positions.len() as u32;
+
+        props.extend_from_slice(&[SPA_FORMAT_AUDIO_POSITION, 0, array_len, SPA_TYPE_ARRAY]);
+        props.extend_from_slice(&[4, SPA_TYPE_ID]);
+        props.extend_from_slice(positions);
+
+        if positions.len() % 2 == 1 {
+            props.push(0);
+        }
+
+        let mut pod = vec![8 + 4 * props.len() as u32, SPA_TYPE_OBJECT];
+        pod.extend_from_slice(&[SPA_TYPE_OBJECT_FORMAT, SPA_PARAM_ENUM_FORMAT]);
+        pod.extend_from_slice(&props);
+        pod
+    }
+
+    unsafe extern "C" fn on_state_changed(
+        data: *mut c_void,
+        _old: i32,
+        state: i32,
+        error: *const c_char,
+    ) {
+        let shared = &*(data as *const Shared);
+
+        shared.state.store(state, Ordering::Release);
+
+        if state == PW_STREAM_STATE_ERROR {
+            if !error.is_null() {
+                warn!("pipewire: {}", CStr::from_ptr(error).to_string_lossy());
+            }
+
+            shared.closed.store(true, Ordering::Release);
+        }
+
+        (shared.lib.thread_loop_signal)(shared.thread_loop, false);
+    }
+
+    /// Copies the queued audio to the buffer of the stream in the realtime thread of the loop.
+    unsafe extern "C" fn on_process(data: *mut c_void) {
+        let shared = &*(data as *const Shared);
+        let stream = shared.stream.load(Ordering::Acquire);
+
+        let buffer = (shared.lib.stream_dequeue_buffer)(stream);
+
+        if buffer.is_null() {
+            return;
+        }
+
+        let spa_buffer = &*(*buffer).buffer;
+
+        if spa_buffer.n_datas == 0 {
+            (shared.lib.stream_queue_buffer)(stream, buffer);
+            return;
+        }
+
+        let data = &mut *spa_buffer.datas;
+        let n_channels = shared.n_channels;
+        let stride = n_channels * std::mem::size_of::<f32>();
+
+        let mut n_frames = 0;
+
+        if !data.data.is_null() {
+            n_frames = data.maxsize as usize / stride;
+
+            let requested = (*buffer).requested as usize;
+
+            if requested > 0 {
+                n_frames = n_frames.min(requested);
+            }
+
+            let samples = slice::from_raw_parts_mut(data.data as *mut f32, n_frames * n_channels);
+
+            // Take whole frames only. If the player fell behind, the rest of the buffer is
+            // silent.
+            let consumer = &mut *shared.consumer.get();
+            let n_samples = (consumer.len() / n_channels).min(n_frames) * n_channels;
+
+            consumer.pop_slice(&mut samples[..n_samples]);
+            samples[n_samples..].fill(0.0);
+        }
+
+        let chunk = &mut *data.chunk;
+        chunk.offset = 0;
+        chunk.stride = stride as i32;
+        chunk.size = (n_frames * stride) as u32;
+
+        (shared.lib.stream_queue_buffer)(stream, buffer);
+    }
+
+    impl AudioOutput for PipeWireOutput {
+        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            self.ring.write(decoded)
+        }
+
+        fn flush(&mut self) {
+            self.ring.drain();
+        }
+    }
+
+    impl Drop for PipeWireOutput {
+        fn drop(&mut self) {
+            // Safety: once the stream is destroyed and the loop stopped, no callback uses the
+            // shared state.
+            unsafe {
+                if !self.stream.is_null() {
+                    (self.lib.thread_loop_lock)(self.thread_loop);
+                    (self.lib.stream_destroy)(self.stream);
+                    (self.lib.thread_loop_unlock)(self.thread_loop);
+                }
+
+                (self.lib.thread_loop_stop)(self.thread_loop);
+                (self.lib.thread_loop_destroy)(self.thread_loop);
+
+                drop(Box::from_raw(self.shared));
+            }
+        }
+    }
+}
+
+/// Gets the error for an output that was not built in.
+#[cfg(any(
+    not(feature = "jack"),
+    not(all(target_os = "linux", feature = "pipewire")),
+    all(target_os = "linux", not(feature = "alsa"))
+))]
+fn not_built(feature: &str) -> AudioOutputError {
+    let msg = format!("{} support was not built, rebuild with --features {}", feature, feature);
+    AudioOutputError::OpenStreamError(msg)
+}
+
+#[cfg(target_os = "linux")]
+fn try_open_default(
+    spec: SignalSpec,
+    duration: Duration,
+    opts: &OutputOptions,
+) -> Result<Box<dyn AudioOutput>> {
+    match pulseaudio::PulseAudioOutput::try_open(spec, duration, opts) {
+        Ok(audio_output) => Ok(audio_output),
+        #[cfg(feature = "alsa")]
+        Err(err) => {
+            // Without a PulseAudio server, play on the default ALSA device.
+            warn!("pulseaudio: {}, trying alsa", err);
+            alsa_output::AlsaOutput::try_open(spec, duration, "default", opts)
+        }
+        #[cfg(not(feature = "alsa"))]
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn try_open_default(
+    _spec: SignalSpec,
+    _duration: Duration,
+    _opts: &OutputOptions,
+) -> Result<Box<dyn AudioOutput>> {
+    Err(AudioOutputError::OpenStreamError("no audio output for this platform".to_string()))
+}
+
+#[cfg(all(target_os = "linux", feature = "alsa"))]
+fn try_open_alsa(
+    spec: SignalSpec,
+    duration: Duration,
+    device: &str,
+    opts: &OutputOptions,
+) -> Result<Box<dyn AudioOutput>> {
+    alsa_output::AlsaOutput::try_open(spec, duration, device, opts)
+}
+
+#[cfg(all(target_os = "linux", not(feature = "alsa")))]
+fn try_open_alsa(
+    _spec: SignalSpec,
+    _duration: Duration,
+    _device: &str,
+    _opts: &OutputOptions,
+) -> Result<Box<dyn AudioOutput>> {
+    Err(not_built("alsa"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn try_open_alsa(
+    _spec: SignalSpec,
+    _duration: Duration,
+    _device: &str,
+    _opts: &OutputOptions,
+) -> Result<Box<dyn AudioOutput>> {
+    Err(AudioOutputError::OpenStreamError("alsa is not available on this platform".to_string()))
+}
+
+/// Lists the devices of every audio output that was built, by the name of the output.
+#[cfg_attr(not(any(target_os = "linux", feature = "jack")), allow(unused_variables))]
+pub fn list_devices(
+    opts: &OutputOptions,
+) -> Vec<(&'static str, result::Result<Vec<Device>, String>)> {
+    vec![
+        #[cfg(target_os = "linux")]
+        ("pulseaudio", pulseaudio::list_devices(opts)),
+        #[cfg(all(target_os = "linux", feature = "alsa"))]
+        ("alsa", alsa_output::list_devices()),
+        #[cfg(feature = "jack")]
+        ("jack", jack_output::list_devices(opts)),
+        #[cfg(all(target_os = "linux", feature = "pipewire"))]
+        ("pipewire", pipewire_output::list_devices(opts)),
+    ]
+}
+
+/// Opens the audio output selected by the options for audio with the given spec.
+pub fn try_open(
+    spec: SignalSpec,
+    duration: Duration,
+    opts: &OutputOptions,
+) -> Result<Box<dyn AudioOutput>> {
+    match &opts.target {
+        OutputTarget::Default => try_open_default(spec, duration, opts),
+        OutputTarget::Alsa(device) => {
+            let device = device.as_deref().or(opts.device.as_deref()).unwrap_or("default");
+            try_open_alsa(spec, duration, device, opts)
+        }
+        #[cfg(feature = "jack")]
+        OutputTarget::Jack => jack_output::JackOutput::try_open(spec, duration, opts),
+        #[cfg(not(feature = "jack"))]
+        OutputTarget::Jack => Err(not_built("jack")),
+        #[cfg(all(target_os = "linux", feature = "pipewire"))]
+        OutputTarget::PipeWire => pipewire_output::PipeWireOutput::try_open(spec, duration, opts),
+        #[cfg(not(all(target_os = "linux", feature = "pipewire")))]
+        OutputTarget::PipeWire => Err(not_built("pipewire")),
+        OutputTarget::File(path) => file::FileOutput::try_open(spec, duration, path, opts),
+        OutputTarget::Stdout => raw::RawOutput::try_open(spec, duration, opts),
+        OutputTarget::Null { realtime } => null::NullOutput::try_open(spec, *realtime),
+    }
+}
+
+/// Gets the sample rate closest to `rate` that the audio output supports.
+pub fn supported_rate(opts: &OutputOptions, rate: u32) -> u32 {
+    match &opts.target {
+        #[cfg(target_os = "linux")]
+        OutputTarget::Default => pulseaudio::supported_rate(rate),
+        #[cfg(not(target_os = "linux"))]
+        OutputTarget::Default => rate,
+        #[cfg(feature = "jack")]
+        OutputTarget::Jack => jack_output::supported_rate(opts, rate),
+        OutputTarget::File(path) => file::supported_rate(path, rate),
+        _ => rate.max(1),
+    }
+}
+
+/// Gets the channels closest to `channels` that the audio output supports.
+pub fn supported_channels(opts: &OutputOptions, channels: Channels) -> Channels {
+    match &opts.target {
+        #[cfg(target_os = "linux")]
+        OutputTarget::Default => pulseaudio::supported_channels(channels),
+        #[cfg(not(target_os = "linux"))]
+        OutputTarget::Default => channels,
+        #[cfg(all(target_os = "linux", feature = "alsa"))]
+        OutputTarget::Alsa(_) => alsa_output::supported_channels(channels),
+        #[cfg(feature = "jack")]
+        OutputTarget::Jack => jack_output::supported_channels(opts, channels),
+        OutputTarget::File(path) => file::supported_channels(path, channels),
+        _ => channels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::borrow::Cow;
+    use std::path::Path;
+
+    use symphonia::core::audio::{AudioBuffer, SampleBuffer, Signal};
+    use symphonia::core::checksum::Md5;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::errors::Error;
+    use symphonia::core::io::{MediaSourceStream, Monitor};
+    use symphonia::core::probe::Hint;
+
+    use crate::testutil::temp_path;
+
+    /// The lengths of the buffers written, in turn, so that buffers straddle the FLAC blocks.
+    const CHUNKS: [usize; 4] = [1, 1151, 4096, 333];
+
+    /// Generates `frames` frames of integer samples with the given number of bits, interleaved.
+    /// Runs of a sinusoid, noise and a constant exercise the fixed predictors, verbatim and
+    /// constant subframes, and the first frames hold the extremes of the sample range.
+    fn generate(channels: usize, bits: u32, frames: usize) -> Vec<i32> {
+        let max = (1i64 << (bits - 1)) - 1;
+        let mut state = 12345u32;
+        let mut samples = Vec::with_capacity(channels * frames);
+
+        for n in 0..frames {
+            for c in 0..channels {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+
+                let sample = match (n, n / 3000 % 3) {
+                    (0, _) => -max - 1,
+                    (1, _) => max,
+                    (_, 0) => {
+                        let phase = 0.01 * (c + 1) as f64 * n as f64;
+                        (0.8 * max as f64 * phase.sin()).round() as i64
+                    }
+                    (_, 1) => i64::from(state as i32) >> (32 - bits),
+                    _ => c as i64 * 100 - 50,
+                };
+
+                samples.push(sample as i32);
+            }
+        }
+
+        samples
+    }
+
+    /// Writes integer samples to a file through the file output, in buffers of varying lengths.
+    fn write_file(path: &Path, spec: SignalSpec, format: SampleFormat, samples: &[i32]) {
+        let opts = OutputOptions {
+            target: OutputTarget::File(path.to_path_buf()),
+            format: Some(format),
+            dither: false,
+            ..Default::default()
+        };
+
+        let channels = spec.channels.count();
+        let scale = (1u64 << (format.bits() - 1)) as f32;
+
+        let mut output = try_open(spec, 4096, &opts).unwrap();
+        let mut frames = samples.chunks(channels);
+
+        for &len in CHUNKS.iter().cycle() {
+            let chunk: Vec<&[i32]> = frames.by_ref().take(len).collect();
+
+            if chunk.is_empty() {
+                break;
+            }
+
+            let mut buf = AudioBuffer::<f32>::new(4096, spec);
+            buf.render_reserved(Some(chunk.len()));
+
+            for (i, frame) in chunk.iter().enumerate() {
+                for (c, &sample) in frame.iter().enumerate() {
+                    buf.chan_mut(c)[i] = sample as f32 / scale;
+                }
+            }
+
+            output.write(AudioBufferRef::F32(Cow::Borrowed(&buf))).unwrap();
+        }
+
+        output.flush();
+    }
+
+    /// Decodes a file with Symphonia, verifying it if the format supports that. Returns the
+    /// number of frames given by the header, and the interleaved samples scaled to integers with
+    /// the given number of bits.
+    fn decode_file(path: &Path, bits: u32) -> (Option<u64>, Vec<i32>) {
+        let file = std::fs::File::open(path).unwrap();
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+        let probed = symphonia::default::get_probe()
+            .format(&Hint::new(), mss, &Default::default(), &Default::default())
+            .unwrap();
+
+        let mut reader = probed.format;
+        let params = reader.default_track().unwrap().codec_params.clone();
+
+        assert_eq!(params.bits_per_sample, Some(bits));
+
+        let decode_opts = DecoderOptions { verify: true };
+        let mut decoder = symphonia::default::get_codecs().make(&params, &decode_opts).unwrap();
+
+        let mut samples = Vec::new();
+
+        loop {
+            let packet = match reader.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    break
+                }
+                Err(err) => panic!("{}", err),
+            };
+
+            let decoded = decoder.decode(&packet).unwrap();
+
+            let mut buf = SampleBuffer::<i32>::new(decoded.capacity() as u64, *decoded.spec());
+            buf.copy_interleaved_ref(decoded);
+            samples.extend(buf.samples().iter().map(|&sample| sample >> (32 - bits)));
+        }
+
+        // FLAC files are verified against the MD5 of their STREAMINFO block. WAV files have no
+        // checksum.
+        let verified = decoder.finalize().verify_ok;
+        let expected = if file::is_flac(path) { Some(true) } else { None };
+        assert_eq!(verified, expected, "{}", path.display());
+
+        (params.n_frames, samples)
+    }
+
+    fn check_round_trip(name: &str, channels: usize, format: SampleFormat, frames: usize) {
+        let path = temp_path(name);
+        let bits = format.bits();
+
+        let spec = SignalSpec::new(44100, crate::mixer::layout(channels).unwrap());
+        let samples = generate(channels, bits, frames);
+
+        write_file(&path, spec, format, &samples);
+        let (n_frames, decoded) = decode_file(&path, bits);
+
+        std::fs::remove_file(&path).unwrap();
+
+        let label = format!("{}, {} frames", name, frames);
+
+        assert_eq!(n_frames, Some(frames as u64), "{}", label);
+        assert_eq!(decoded.len(), samples.len(), "{}", label);
+
+        let mismatch = decoded.iter().zip(&samples).position(|(a, b)| a != b);
+        assert_eq!(mismatch, None, "{}", label);
+    }
+
+    /// The numbers of frames tested. FLAC files end with a short block unless the length is a
+    /// multiple of the block size.
+    const LENGTHS: [usize; 5] = [1, 4095, 4096, 2 * 4096 + 1, 10_007];
+
+    #[test]
+    fn flac_round_trip() {
+        for &frames in &LENGTHS {
+            for &channels in &[1, 2] {
+                let name = format!("{}ch-16.flac", channels);
+                check_round_trip(&name, channels, SampleFormat::S16, frames);
+
+                let name = format!("{}ch-24.flac", channels);
+                check_round_trip(&name, channels, SampleFormat::S24, frames);
+            }
+        }
+    }
+
+    #[test]
+    fn flac_md5_matches_samples() {
+        let path = temp_path("md5.flac");
+        let spec = SignalSpec::new(48000, crate::mixer::layout(2).unwrap());
+        let samples = generate(2, 16, 5000);
+
+        write_file(&path, spec, SampleFormat::S16, &samples);
+
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The MD5 of the interleaved little-endian samples ends the STREAMINFO block.
+        let mut md5 = Md5::default();
+        for sample in &samples {
+            md5.process_buf_bytes(&(*sample as i16).to_le_bytes());
+        }
+
+        assert_eq!(&file[26..42], &md5.md5());
+    }
+
+    #[test]
+    fn wav_round_trip() {
+        for &frames in &LENGTHS {
+            for &channels in &[1, 2, 6] {
+                for &format in &[SampleFormat::S16, SampleFormat::S24] {
+                    let name = format!("{}ch-{}.wav", channels, format.bits());
+                    check_round_trip(&name, channels, format, frames);
+                }
+            }
+        }
+    }
+
+    #[cfg(all(target_os = "linux", feature = "alsa"))]
+    #[test]
+    fn alsa_plays_on_null_device() {
+        // The null PCM accepts any sample format, rate and channels, and discards the audio.
+        let formats = [None, Some(SampleFormat::S16), Some(SampleFormat::S24)];
+
+        for format in formats {
+            for channels in [1, 2, 6] {
+                let opts = OutputOptions {
+                    target: OutputTarget::Alsa(Some("null".to_string())),
+                    format,
+                    ..Default::default()
+                };
+
+                let spec = SignalSpec::new(48000, crate::mixer::layout(channels).unwrap());
+
+                let mut output = try_open(spec, 4096, &opts).unwrap();
+
+                let mut buf = AudioBuffer::<f32>::new(4096, spec);
+                buf.render_reserved(Some(4096));
+
+                for c in 0..channels {
+                    for (i, sample) in buf.chan_mut(c).iter_mut().enumerate() {
+                        *sample = (i as f32 * 0.01).sin() * 0.5;
+                    }
+                }
+
+                output.write(AudioBufferRef::F32(Cow::Borrowed(&buf))).unwrap();
+                output.flush();
+            }
+        }
+    }
+
+    /// Walks the properties of a SPA object pod, and returns the key, type and values of each.
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    fn pod_props(pod: &[u32]) -> Vec<(u32, u32, Vec<u32>)> {
+        // The size of the object excludes its own size and type.
+        assert_eq!(pod[0] as usize, 4 * (pod.len() - 2));
+
+        let mut props = Vec::new();
+        let mut i = 4;
+
+        while i < pod.len() {
+            let (key, size, type_) = (pod[i], pod[i + 2] as usize, pod[i + 3]);
+            assert_eq!(size % 4, 0);
+
+            props.push((key, type_, pod[i + 4..i + 4 + size / 4].to_vec()));
+
+            // Values are padded to 8 bytes.
+            i += 4 + (size / 4).div_ceil(2) * 2;
+        }
+
+        assert_eq!(i, pod.len());
+        props
+    }
+
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    #[test]
+    fn pipewire_format_pod() {
+        use pipewire_output::{format_pod, map_channel};
+
+        for channels in [1, 2, 6, 8] {
+            let layout = crate::mixer::layout(channels).unwrap();
+            let positions: Vec<u32> = layout
+                .iter()
+                .enumerate()
+                .map(|(i, channel)| map_channel(channel, channels == 1, i))
+                .collect();
+
+            let pod = format_pod(48000, &positions);
+
+            // An object of type Format, for the EnumFormat parameter.
+            assert_eq!(&pod[1..4], &[15, 0x40003, 3]);
+
+            let format = if cfg!(target_endian = "little") { 0x11b } else { 0x11c };
+
+            // The array of positions holds the size and type of its items first.
+            let mut array = vec![4, 3];
+            array.extend_from_slice(&positions);
+
+            let expected = vec![
+                (1, 3, vec![1]),
+                (2, 3, vec![1]),
+                (0x10001, 3, vec![format]),
+                (0x10003, 4, vec![48000]),
+                (0x10004, 4, vec![channels as u32]),
+                (0x10005, 13, array),
+            ];
+
+            assert_eq!(pod_props(&pod), expected, "{} channels", channels);
+        }
+    }
+
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    #[test]
+    fn pipewire_maps_channels() {
+        use pipewire_output::map_channel;
+
+        let map = |channels: Channels| -> Vec<u32> {
+            let is_mono = channels.count() == 1;
+            channels.iter().enumerate().map(|(i, c)| map_channel(c, is_mono, i)).collect()
+        };
+
+        // Mono is played as mono rather than on the left channel.
+        assert_eq!(map(Channels::FRONT_LEFT), [2]);
+        assert_eq!(map(crate::mixer::layout(2).unwrap()), [3, 4]);
+
+        // FL, FR, FC, LFE, RL, RR, SL, SR.
+        assert_eq!(map(crate::mixer::layout(8).unwrap()), [3, 4, 5, 6, 12, 13, 7, 8]);
+
+        // Every channel Symphonia knows has a position.
+        assert!(Channels::all().iter().all(|c| map_channel(c, false, 0) < 0x1000));
+        assert_eq!(map_channel(Channels::empty(), false, 1), 0x1001);
+    }
+
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    #[test]
+    fn pipewire_reports_missing_server() {
+        // Without the PipeWire library or the server, opening fails rather than panicking.
+        let opts = OutputOptions {
+            target: OutputTarget::PipeWire,
+            server: Some(format!("boombox-{}-missing", std::process::id())),
+            ..Default::default()
+        };
+
+        let spec = SignalSpec::new(48000, crate::mixer::layout(2).unwrap());
+
+        match try_open(spec, 4096, &opts) {
+            Err(AudioOutputError::OpenStreamError(msg)) => {
+                assert!(msg.starts_with("pipewire: "), "{}", msg)
+            }
+            Err(err) => panic!("{}", err),
+            Ok(_) => panic!("opened a stream on a missing server"),
+        }
+    }
+
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    #[test]
+    fn pipewire_lists_no_devices_of_missing_server() {
+        let opts = OutputOptions {
+            server: Some(format!("boombox-{}-missing", std::process::id())),
+            ..Default::default()
+        };
+
+        assert!(pipewire_output::list_devices(&opts).is_err());
+    }
 }
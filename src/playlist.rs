@@ -0,0 +1,345 @@
+// Symphonia
+// Copyright (c) 2019-2022 The Project Symphonia Developers.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Playlists and Directories
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+/// The extensions of files that are played when a directory is scanned.
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "aac", "aif", "aifc", "aiff", "caf", "flac", "m4a", "m4b", "mka", "mkv", "mp1", "mp2", "mp3",
+    "mp4", "oga", "ogg", "wav", "webm",
+];
+
+/// The maximum depth of nested directories and playlists. This guards against cycles.
+const MAX_DEPTH: usize = 16;
+
+/// Expands the inputs given on the command line into the list of files to play. Directories are
+/// scanned recursively, and M3U, M3U8 and PLS playlists are replaced by their entries. Any other
+/// input, including `-` for standard input, is kept as-is.
+pub fn expand(inputs: &[&str]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    for input in inputs {
+        expand_path(Path::new(input), 0, &mut paths);
+    }
+
+    paths
+}
+
+fn expand_path(path: &Path, depth: usize, paths: &mut Vec<PathBuf>) {
+    if depth > MAX_DEPTH {
+        warn!("skipping {}: nested too deeply", path.display());
+        return;
+    }
+
+    if path.is_dir() {
+        if let Err(err) = scan_dir(path, depth, paths) {
+            warn!("failed to scan {}: {}", path.display(), err);
+        }
+        return;
+    }
+
+    let entries = match extension(path).as_deref() {
+        Some("m3u") | Some("m3u8") => read_m3u(path),
+        Some("pls") => read_pls(path),
+        _ => {
+            paths.push(path.to_path_buf());
+            return;
+        }
+    };
+
+    match entries {
+        Ok(entries) => {
+            // Relative entries are relative to the directory containing the playlist.
+            let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+            for entry in entries {
+                if let Some(entry) = resolve(base, &entry) {
+                    expand_path(&entry, depth + 1, paths);
+                }
+            }
+        }
+        Err(err) => warn!("failed to read playlist {}: {}", path.display(), err),
+    }
+}
+
+/// Appends the audio files in a directory, and its sub-directories, in order of their names.
+fn scan_dir(dir: &Path, depth: usize, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries =
+        fs::read_dir(dir)?.map(|entry| entry.map(|e| e.path())).collect::<io::Result<Vec<_>>>()?;
+
+    entries.sort();
+
+    for entry in entries {
+        // Skip hidden files, such as the resource forks left behind by macOS.
+        let name = entry.file_name().and_then(|name| name.to_str());
+
+        if name.is_some_and(|name| name.starts_with('.')) {
+            continue;
+        }
+
+        if entry.is_dir() {
+            expand_path(&entry, depth + 1, paths);
+        }
+        else if extension(&entry).is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.as_str())) {
+            paths.push(entry);
+        }
+    }
+
+    Ok(())
+}
+
+/// Gets the lower-case extension of a path.
+fn extension(path: &Path) -> Option<String> {
+    path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase())
+}
+
+/// Reads a playlist file as text. Invalid UTF-8, which is common in legacy M3U files, is replaced
+/// rather than rejected.
+fn read_text(path: &Path) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    let text = String::from_utf8_lossy(&bytes);
+
+    Ok(text.trim_start_matches('\u{feff}').to_string())
+}
+
+/// Reads the entries of an M3U or M3U8 playlist. Lines starting with `#` are comments or extended
+/// M3U directives.
+fn read_m3u(path: &Path) -> io::Result<Vec<String>> {
+    let text = read_text(path)?;
+
+    let entries = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+
+    Ok(entries)
+}
+
+/// Reads the entries of a PLS playlist in the order of their `FileN` keys.
+fn read_pls(path: &Path) -> io::Result<Vec<String>> {
+    let text = read_text(path)?;
+
+    let mut entries = Vec::new();
+
+    for line in text.lines() {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+
+        let n = match key.get(..4) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("file") => key[4..].parse::<u32>().ok(),
+            _ => None,
+        };
+
+        if let Some(n) = n {
+            entries.push((n, value.to_string()));
+        }
+    }
+
+    entries.sort_by_key(|&(n, _)| n);
+
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// Resolves a playlist entry to a path. Local `file://` URLs are supported, but other URLs are
+/// skipped.
+fn resolve(base: &Path, entry: &str) -> Option<PathBuf> {
+    let path = if let Some(url) = entry.strip_prefix("file://") {
+        // Strip the host, usually empty or localhost, and decode the path.
+        let path = &url[url.find('/')?..];
+        PathBuf::from(percent_decode(path))
+    }
+    else if entry.contains("://") {
+        warn!("skipping {}: only local files are supported", entry);
+        return None;
+    }
+    else {
+        PathBuf::from(entry)
+    };
+
+    Some(if path.is_absolute() { path } else { base.join(path) })
+}
+
+/// Decodes the `%XX` escapes in a URL path.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_path;
+
+    /// A directory in the temporary directory that is removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = temp_path(name);
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        /// Creates a file, and its parent directories, and returns its path.
+        fn file(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn expand_one(path: &Path) -> Vec<PathBuf> {
+        expand(&[path.to_str().unwrap()])
+    }
+
+    #[test]
+    fn expand_scans_directories_in_order() {
+        let dir = TempDir::new("scan");
+        dir.file("b.WAV", "");
+        dir.file("a.flac", "");
+        dir.file("notes.txt", "");
+        dir.file(".hidden.mp3", "");
+        dir.file("sub/c.ogg", "");
+        dir.file(".git/d.mp3", "");
+
+        let expected: Vec<PathBuf> =
+            ["a.flac", "b.WAV", "sub/c.ogg"].iter().map(|name| dir.0.join(name)).collect();
+
+        assert_eq!(expand_one(&dir.0), expected);
+    }
+
+    #[test]
+    fn expand_reads_m3u() {
+        let dir = TempDir::new("m3u");
+        let abs = dir.file("abs.wav", "");
+
+        let playlist = dir.file(
+            "list.m3u8",
+            &format!(
+                "\u{feff}#EXTM3U\r\n\
+                 #EXTINF:123,Artist - Title\r\n\
+                 rel/one.flac\r\n\
+                 \r\n  two.mp3  \n\
+                 {}\n\
+                 file://{}\n\
+                 file://localhost{}/with%20space%2Fslash.ogg\n\
+                 http://example.com/stream.mp3\n",
+                abs.display(),
+                abs.display(),
+                dir.0.display(),
+            ),
+        );
+
+        let expected = vec![
+            dir.0.join("rel/one.flac"),
+            dir.0.join("two.mp3"),
+            abs.clone(),
+            abs,
+            dir.0.join("with space/slash.ogg"),
+        ];
+
+        assert_eq!(expand_one(&playlist), expected);
+    }
+
+    #[test]
+    fn expand_reads_pls_in_entry_order() {
+        let dir = TempDir::new("pls");
+
+        let playlist = dir.file(
+            "list.PLS",
+            "[playlist]\n\
+             File2=second.mp3\n\
+             Title2=Second\n\
+             file1 = first.flac\n\
+             File10=tenth.wav\n\
+             Length1=-1\n\
+             NumberOfEntries=3\n\
+             Version=2\n",
+        );
+
+        let expected: Vec<PathBuf> =
+            ["first.flac", "second.mp3", "tenth.wav"].iter().map(|name| dir.0.join(name)).collect();
+
+        assert_eq!(expand_one(&playlist), expected);
+    }
+
+    #[test]
+    fn expand_stops_at_max_depth() {
+        let dir = TempDir::new("depth");
+
+        // A playlist that includes itself is expanded until its entries pass the depth limit.
+        let playlist = dir.file("loop.m3u", "a.flac\nloop.m3u\n");
+
+        let paths = expand_one(&playlist);
+
+        assert_eq!(paths.len(), MAX_DEPTH);
+        assert!(paths.iter().all(|path| *path == dir.0.join("a.flac")));
+    }
+
+    #[test]
+    fn expand_keeps_other_inputs() {
+        assert_eq!(expand(&["-", "missing.mp3"]), vec![PathBuf::from("-"), "missing.mp3".into()]);
+    }
+
+    #[test]
+    fn resolve_entries() {
+        let base = Path::new("/music/lists");
+
+        assert_eq!(resolve(base, "a.mp3"), Some("/music/lists/a.mp3".into()));
+        assert_eq!(resolve(base, "../b.mp3"), Some("/music/lists/../b.mp3".into()));
+        assert_eq!(resolve(base, "/abs/c.mp3"), Some("/abs/c.mp3".into()));
+        assert_eq!(resolve(base, "file:///abs/d%20e.mp3"), Some("/abs/d e.mp3".into()));
+        assert_eq!(resolve(base, "file://localhost/abs/f.mp3"), Some("/abs/f.mp3".into()));
+        assert_eq!(resolve(base, "file://host-only"), None);
+        assert_eq!(resolve(base, "https://example.com/g.mp3"), None);
+    }
+
+    #[test]
+    fn percent_decode_escapes() {
+        assert_eq!(percent_decode("/a%20b/%C3%A9.mp3"), "/a b/\u{e9}.mp3");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("%2f%2F"), "//");
+    }
+}
@@ -0,0 +1,286 @@
+// Symphonia
+// Copyright (c) 2019-2022 The Project Symphonia Developers.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Playback Status Line
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use symphonia::core::formats::{Packet, Track};
+use symphonia::core::units::{Time, TimeBase};
+
+/// How often the status line is redrawn on a terminal.
+const TTY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a status line is printed when standard output is not a terminal.
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The length of the window the current bitrate is averaged over, in seconds.
+const BITRATE_WINDOW: f64 = 1.0;
+
+/// How the status line is written.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    /// The status line is drawn on the bottom row of the terminal, which visualisers leave free.
+    Reserved,
+    /// The status line is redrawn in place with carriage returns.
+    Inline,
+    /// Standard output is not a terminal, so plain lines are printed periodically.
+    Plain,
+}
+
+pub struct Progress {
+    mode: Mode,
+    codec: String,
+    rate: Option<u32>,
+    tb: Option<TimeBase>,
+    dur: Option<u64>,
+    ts: u64,
+    paused: bool,
+    /// The user volume in decibels.
+    volume: f64,
+    /// The sizes, in bytes, and durations, in seconds, of the packets in the bitrate window.
+    packets: VecDeque<(usize, f64)>,
+    window_bytes: usize,
+    window_secs: f64,
+    last_draw: Option<Instant>,
+    line: String,
+}
+
+impl Progress {
+    /// Creates a status line. If `reserve_row` is true, a visualiser is drawing to the terminal
+    /// and the status line is drawn on the bottom row that it leaves free.
+    pub fn new(reserve_row: bool) -> Self {
+        let mode = if !std::io::stdout().is_terminal() {
+            Mode::Plain
+        }
+        else if reserve_row {
+            Mode::Reserved
+        }
+        else {
+            Mode::Inline
+        };
+
+        Progress {
+            mode,
+            codec: String::new(),
+            rate: None,
+            tb: None,
+            dur: None,
+            ts: 0,
+            paused: false,
+            volume: 0.0,
+            packets: VecDeque::new(),
+            window_bytes: 0,
+            window_secs: 0.0,
+            last_draw: None,
+            line: String::new(),
+        }
+    }
+
+    /// Starts showing the progress of a new track.
+    pub fn set_track(&mut self, track: &Track) {
+        let params = &track.codec_params;
+
+        self.codec = match symphonia::default::get_codecs().get_codec(params.codec) {
+            Some(codec) => codec.short_name.to_uppercase(),
+            None => String::new(),
+        };
+
+        self.rate = params.sample_rate;
+        self.tb = params.time_base;
+        self.dur = params.n_frames.map(|frames| params.start_ts + frames);
+        self.ts = 0;
+
+        self.packets.clear();
+        self.window_bytes = 0;
+        self.window_secs = 0.0;
+    }
+
+    /// Updates the progress with a packet that is about to be played, and redraws the status
+    /// line if it is due.
+    pub fn update(&mut self, packet: &Packet) {
+        self.ts = packet.ts();
+
+        if let Some(tb) = self.tb {
+            let dur = tb.calc_time(packet.dur());
+            let secs = dur.seconds as f64 + dur.frac;
+
+            self.packets.push_back((packet.buf().len(), secs));
+            self.window_bytes += packet.buf().len();
+            self.window_secs += secs;
+
+            while self.window_secs > BITRATE_WINDOW && self.packets.len() > 1 {
+                if let Some((bytes, secs)) = self.packets.pop_front() {
+                    self.window_bytes -= bytes;
+                    self.window_secs -= secs;
+                }
+            }
+        }
+
+        let interval = if self.mode == Mode::Plain { PLAIN_INTERVAL } else { TTY_INTERVAL };
+
+        let now = Instant::now();
+
+        match self.last_draw {
+            Some(last) if now.duration_since(last) < interval => (),
+            _ => {
+                self.last_draw = Some(now);
+                self.draw();
+            }
+        }
+    }
+
+    /// Shows if playback is paused, and redraws the status line.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.redraw();
+    }
+
+    /// Shows the user volume in decibels, and redraws the status line.
+    pub fn set_volume(&mut self, volume: f64) {
+        self.volume = volume;
+        self.redraw();
+    }
+
+    /// Redraws the status line immediately. Plain lines are only printed periodically.
+    pub fn redraw(&mut self) {
+        if self.mode != Mode::Plain {
+            self.last_draw = Some(Instant::now());
+            self.draw();
+        }
+    }
+
+    /// Draws the final state of the status line, and moves on to the next line.
+    pub fn finish(&mut self) {
+        self.format();
+
+        let stdout = std::io::stdout();
+        let mut output = stdout.lock();
+
+        let _ = match self.mode {
+            Mode::Plain => writeln!(output, "{}", self.line),
+            _ => write!(output, "\r{}\x1b[K\r\n", self.line),
+        };
+        let _ = output.flush();
+    }
+
+    fn draw(&mut self) {
+        self.format();
+
+        let stdout = std::io::stdout();
+        let mut output = stdout.lock();
+
+        let _ = match self.mode {
+            Mode::Reserved => {
+                let rows = terminal_size::terminal_size().map_or(24, |(_, h)| h.0);
+
+                // Save the cursor, draw on the bottom row, and restore the cursor so that the
+                // visualiser is not disturbed.
+                write!(output, "\x1b7\x1b[{};1H\x1b[0m{}\x1b[K\x1b8", rows, self.line)
+            }
+            // This extra space is a workaround for Konsole to correctly erase the previous line.
+            Mode::Inline => write!(output, "\r{} ", self.line),
+            Mode::Plain => writeln!(output, "{}", self.line),
+        };
+
+        // Flush immediately since stdout is buffered.
+        let _ = output.flush();
+    }
+
+    /// Formats the status line, truncated to the width of the terminal.
+    fn format(&mut self) {
+        self.line.clear();
+
+        let ts = self.ts;
+
+        let state = if self.paused { "\u{23f8}\u{fe0f}" } else { "\u{25b6}\u{fe0f}" };
+
+        match self.tb {
+            Some(tb) => {
+                let _ = write!(self.line, "{}  {}", state, fmt_time(tb.calc_time(ts)));
+
+                // The duration is unknown for some streams, in which case only the elapsed time
+                // can be shown.
+                if let Some(dur) = self.dur {
+                    // The progress bar is left out when writing plain lines.
+                    if self.mode != Mode::Plain {
+                        let _ = write!(self.line, " {}", progress_bar(ts, dur));
+                    }
+
+                    let _ = write!(
+                        self.line,
+                        " -{} / {}",
+                        fmt_time(tb.calc_time(dur.saturating_sub(ts))),
+                        fmt_time(tb.calc_time(dur))
+                    );
+                }
+            }
+            None => {
+                let _ = write!(self.line, "{}  {}", state, ts);
+            }
+        }
+
+        if !self.codec.is_empty() {
+            let _ = write!(self.line, " | {}", self.codec);
+        }
+
+        if let Some(rate) = self.rate {
+            let _ = write!(self.line, " {} Hz", rate);
+        }
+
+        if self.window_secs > 0.0 {
+            let kbps = self.window_bytes as f64 * 8.0 / self.window_secs / 1000.0;
+            let _ = write!(self.line, " | {:.0} kbps", kbps);
+        }
+
+        if self.volume < 0.0 {
+            let _ = write!(self.line, " | vol {:.0} dB", self.volume);
+        }
+
+        // A status line wider than the terminal would wrap and scroll the visualiser.
+        if self.mode != Mode::Plain {
+            if let Some((terminal_size::Width(cols), _)) = terminal_size::terminal_size() {
+                let width = usize::from(cols).saturating_sub(1);
+
+                if let Some((end, _)) = self.line.char_indices().nth(width) {
+                    self.line.truncate(end);
+                }
+            }
+        }
+    }
+}
+
+fn fmt_time(t: Time) -> String {
+    let hours = t.seconds / (60 * 60);
+    let mins = (t.seconds % (60 * 60)) / 60;
+    let secs = f64::from((t.seconds % 60) as u32) + t.frac;
+
+    format!("{}:{:0>2}:{:0>4.1}", hours, mins, secs)
+}
+
+// Get a string slice containing a progress bar.
+fn progress_bar(ts: u64, dur: u64) -> &'static str {
+    const NUM_STEPS: usize = 60;
+
+    lazy_static! {
+        static ref PROGRESS_BAR: Vec<String> = {
+            (0..NUM_STEPS + 1).map(|i| format!("[{:<60}]", str::repeat("■", i))).collect()
+        };
+    }
+
+    let i = (NUM_STEPS as u64)
+        .saturating_mul(ts)
+        .checked_div(dur)
+        .unwrap_or(0)
+        .clamp(0, NUM_STEPS as u64);
+
+    &PROGRESS_BAR[i as usize]
+}
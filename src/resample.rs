@@ -0,0 +1,425 @@
+// Symphonia
+// Copyright (c) 2019-2022 The Project Symphonia Developers.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Sample Rate Conversion
+
+use std::f64::consts::PI;
+
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal, SignalSpec};
+
+/// The number of taps on either side of the centre of the filter when upsampling. The filter is
+/// lengthened by the decimation ratio when downsampling.
+const HALF_TAPS: usize = 72;
+
+/// The number of phases the filter is tabulated at between two input samples. The filter at
+/// positions between them is linearly interpolated.
+const PHASES: usize = 512;
+
+/// The upper edge of the passband as a fraction of the lower of the two Nyquist frequencies. The
+/// stopband starts at the Nyquist frequency.
+const PASSBAND: f64 = 0.91;
+
+/// The shape parameter of the Kaiser window, for a stopband attenuation of about 100 dB.
+const KAISER_BETA: f64 = 10.0;
+
+/// Converts the sample rate of a stream of audio buffers with a polyphase windowed-sinc filter.
+///
+/// Any pair of rates is supported. The position of each output sample is tracked exactly as a
+/// fraction of an input sample, and the filter at that position is interpolated from a table.
+pub struct Resampler {
+    in_rate: u64,
+    out_rate: u64,
+    /// The number of taps on either side of the centre of the filter.
+    half: usize,
+    /// The filter tabulated at `PHASES + 1` phases, `2 * half` taps each.
+    table: Vec<f32>,
+    /// The input samples not yet consumed, for each channel. Starts `half - 1` samples before the
+    /// position of the next output sample.
+    history: Vec<Vec<f32>>,
+    /// The fractional part of the position of the next output sample, in units of `1 / out_rate`
+    /// of an input sample.
+    frac: u64,
+    /// The input buffer converted to `f32`.
+    in_buf: Option<AudioBuffer<f32>>,
+    /// The resampled output.
+    out_buf: AudioBuffer<f32>,
+}
+
+impl Resampler {
+    /// Creates a resampler for audio with the given spec to the rate `out_rate`.
+    pub fn new(spec: SignalSpec, out_rate: u32) -> Self {
+        let in_rate = u64::from(spec.rate);
+        let out_rate = u64::from(out_rate);
+
+        // The cutoff is set by the lower of the two rates, in cycles per input sample.
+        let ratio = (out_rate as f64 / in_rate as f64).min(1.0);
+
+        let half = (HALF_TAPS as f64 / ratio).ceil() as usize;
+        let cutoff = 0.25 * ratio * (1.0 + PASSBAND);
+
+        let taps = 2 * half;
+        let mut table = Vec::with_capacity((PHASES + 1) * taps);
+
+        for phase in 0..=PHASES {
+            let offset = phase as f64 / PHASES as f64;
+
+            let row = (0..taps).map(|i| {
+                let t = i as f64 - (half - 1) as f64 - offset;
+                2.0 * cutoff * sinc(2.0 * cutoff * t) * kaiser(t / half as f64)
+            });
+
+            let start = table.len();
+            table.extend(row.map(|h| h as f32));
+
+            // Normalize each phase to unity gain at DC so that there is no ripple at the phase
+            // rate.
+            let sum: f32 = table[start..].iter().sum();
+            table[start..].iter_mut().for_each(|h| *h /= sum);
+        }
+
+        let out_spec = SignalSpec::new(out_rate as u32, spec.channels);
+
+        Resampler {
+            in_rate,
+            out_rate,
+            half,
+            table,
+            history: vec![vec![0.0; half - 1]; spec.channels.count()],
+            frac: 0,
+            in_buf: None,
+            out_buf: AudioBuffer::new(0, out_spec),
+        }
+    }
+
+    /// Resamples a buffer. Some output is held back until the next buffer is resampled, or the
+    /// resampler is flushed.
+    pub fn process(&mut self, input: &AudioBufferRef<'_>) -> &AudioBuffer<f32> {
+        let spec = *input.spec();
+        let duration = input.capacity() as u64;
+
+        if self.in_buf.as_ref().map_or(true, |buf| (buf.capacity() as u64) < duration) {
+            self.in_buf = Some(AudioBuffer::new(duration, spec));
+        }
+
+        let in_buf = self.in_buf.as_mut().unwrap();
+        input.convert(in_buf);
+
+        for (c, history) in self.history.iter_mut().enumerate() {
+            history.extend_from_slice(in_buf.chan(c));
+        }
+
+        let end = self.history[0].len();
+        self.render(end);
+        &self.out_buf
+    }
+
+    /// Resamples the input held back for the filter as if the stream ended, and resets the
+    /// resampler so that the next buffer starts a new stream.
+    pub fn flush(&mut self) -> &AudioBuffer<f32> {
+        let end = self.history[0].len();
+
+        // Pad the input with silence so that every held back input sample can be reached by the
+        // filter.
+        for history in self.history.iter_mut() {
+            history.resize(end + self.half, 0.0);
+        }
+
+        self.render(end);
+
+        for history in self.history.iter_mut() {
+            history.clear();
+            history.resize(self.half - 1, 0.0);
+        }
+
+        self.frac = 0;
+
+        &self.out_buf
+    }
+
+    /// Renders the output samples positioned before the input sample `end`, and that can be
+    /// reached by the filter, into the output buffer. The consumed input is discarded.
+    fn render(&mut self, end: usize) {
+        let taps = 2 * self.half;
+        let len = self.history[0].len();
+
+        // The index of the first input sample under the filter for the next output sample.
+        let mut pos = 0;
+        let mut frac = self.frac;
+
+        // Count the output samples to size the output buffer.
+        let mut n_frames = 0;
+
+        {
+            let (mut pos, mut frac) = (pos, frac);
+
+            while pos + taps <= len && pos + self.half - 1 < end {
+                n_frames += 1;
+                frac += self.in_rate;
+                pos += (frac / self.out_rate) as usize;
+                frac %= self.out_rate;
+            }
+        }
+
+        if self.out_buf.capacity() < n_frames {
+            self.out_buf = AudioBuffer::new(n_frames as u64, *self.out_buf.spec());
+        }
+
+        self.out_buf.clear();
+        self.out_buf.render_reserved(Some(n_frames));
+
+        for i in 0..n_frames {
+            // Find the two tabulated phases either side of the position of the output sample.
+            let x = frac as f64 / self.out_rate as f64 * PHASES as f64;
+            let phase = (x as usize).min(PHASES - 1);
+            let t = (x - phase as f64) as f32;
+
+            let row0 = &self.table[phase * taps..(phase + 1) * taps];
+            let row1 = &self.table[(phase + 1) * taps..(phase + 2) * taps];
+
+            for (c, history) in self.history.iter().enumerate() {
+                let window = &history[pos..pos + taps];
+
+                let (mut acc0, mut acc1) = (0.0, 0.0);
+
+                for ((&s, &h0), &h1) in window.iter().zip(row0).zip(row1) {
+                    acc0 += s * h0;
+                    acc1 += s * h1;
+                }
+
+                self.out_buf.chan_mut(c)[i] = acc0 + (acc1 - acc0) * t;
+            }
+
+            frac += self.in_rate;
+            pos += (frac / self.out_rate) as usize;
+            frac %= self.out_rate;
+        }
+
+        for history in self.history.iter_mut() {
+            history.drain(..pos.min(history.len()));
+        }
+
+        self.frac = frac;
+    }
+}
+
+/// The normalized sinc function.
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    }
+    else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The Kaiser window at `r`, from -1 to 1.
+fn kaiser(r: f64) -> f64 {
+    bessel_i0(KAISER_BETA * (1.0 - r * r).max(0.0).sqrt()) / bessel_i0(KAISER_BETA)
+}
+
+/// The zeroth-order modified Bessel function of the first kind, evaluated by its power series.
+fn bessel_i0(x: f64) -> f64 {
+    let half_x_sq = (x / 2.0).powi(2);
+
+    let mut sum = 1.0;
+    let mut term = 1.0;
+
+    for k in 1..64 {
+        term *= half_x_sq / f64::from(k * k);
+        sum += term;
+
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::borrow::Cow;
+
+    use symphonia::core::audio::Channels;
+
+    /// The rates tested, as pairs of input and output rates.
+    const RATES: [(u32, u32); 3] = [(44100, 48000), (48000, 44100), (96000, 44100)];
+
+    /// Resamples mono audio, fed in buffers of 1152 frames, and flushes the resampler.
+    fn resample(in_rate: u32, out_rate: u32, samples: &[f32]) -> Vec<f32> {
+        let spec = SignalSpec::new(in_rate, Channels::FRONT_LEFT);
+        let mut resampler = Resampler::new(spec, out_rate);
+        let mut output = Vec::new();
+
+        for packet in samples.chunks(1152) {
+            let mut buf = AudioBuffer::<f32>::new(1152, spec);
+            buf.render_reserved(Some(packet.len()));
+            buf.chan_mut(0).copy_from_slice(packet);
+
+            let out = resampler.process(&AudioBufferRef::F32(Cow::Borrowed(&buf)));
+            assert_eq!(out.spec().rate, out_rate);
+            output.extend_from_slice(out.chan(0));
+        }
+
+        output.extend_from_slice(resampler.flush().chan(0));
+        output
+    }
+
+    /// Generates a second of a sinusoid at `freq` Hz with the given amplitude.
+    fn sine(freq: f64, amplitude: f64, rate: u32) -> Vec<f32> {
+        let step = 2.0 * PI * freq / f64::from(rate);
+        (0..rate).map(|n| (amplitude * (step * f64::from(n)).sin()) as f32).collect()
+    }
+
+    /// Fits a sinusoid at `freq` Hz to the middle of the resampled audio, clear of the filter's
+    /// response to the start and end of the input. Returns its amplitude, and the RMS of the
+    /// residual.
+    fn fit(samples: &[f32], freq: f64, rate: u32) -> (f64, f64) {
+        let margin = rate as usize / 10;
+        let samples = &samples[margin..samples.len() - margin];
+
+        let step = 2.0 * PI * freq / f64::from(rate);
+
+        // Solve the normal equations of the least-squares fit of a cosine and a sine.
+        let (mut cc, mut cs, mut ss, mut yc, mut ys) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+        for (n, &y) in samples.iter().enumerate() {
+            let (s, c) = (step * (n + margin) as f64).sin_cos();
+            let y = f64::from(y);
+
+            cc += c * c;
+            cs += c * s;
+            ss += s * s;
+            yc += y * c;
+            ys += y * s;
+        }
+
+        let det = cc * ss - cs * cs;
+        let a = (yc * ss - ys * cs) / det;
+        let b = (ys * cc - yc * cs) / det;
+
+        let residual = samples.iter().enumerate().map(|(n, &y)| {
+            let (s, c) = (step * (n + margin) as f64).sin_cos();
+            (f64::from(y) - a * c - b * s).powi(2)
+        });
+
+        let rms = (residual.sum::<f64>() / samples.len() as f64).sqrt();
+
+        (a.hypot(b), rms)
+    }
+
+    fn to_db(x: f64) -> f64 {
+        20.0 * x.log10()
+    }
+
+    /// Gets the RMS of the middle of the resampled audio, relative to the RMS of a sinusoid with
+    /// the given amplitude, in decibels.
+    fn level(samples: &[f32], amplitude: f64, rate: u32) -> f64 {
+        let margin = rate as usize / 10;
+        let samples = &samples[margin..samples.len() - margin];
+
+        let sum: f64 = samples.iter().map(|&x| f64::from(x).powi(2)).sum();
+        to_db((sum / samples.len() as f64).sqrt() * 2f64.sqrt() / amplitude)
+    }
+
+    /// Gets `n` frequencies evenly spaced from `from` to `to` Hz.
+    fn sweep(from: f64, to: f64, n: u32) -> impl Iterator<Item = f64> {
+        (0..n).map(move |i| from + (to - from) * f64::from(i) / f64::from(n - 1))
+    }
+
+    #[test]
+    fn resample_passband_ripple() {
+        for &(in_rate, out_rate) in &RATES {
+            let nyquist = f64::from(in_rate.min(out_rate)) / 2.0;
+            let (mut min, mut max) = (f64::MAX, f64::MIN);
+
+            for freq in sweep(20.0, PASSBAND * nyquist, 41) {
+                let output = resample(in_rate, out_rate, &sine(freq, 0.5, in_rate));
+                let (amplitude, _) = fit(&output, freq, out_rate);
+
+                min = min.min(amplitude);
+                max = max.max(amplitude);
+            }
+
+            let ripple = to_db(max / min);
+            assert!(ripple <= 0.0002, "{} -> {} Hz: {} dB", in_rate, out_rate, ripple);
+            assert!(to_db(max / 0.5).abs() <= 0.0002, "{} -> {} Hz", in_rate, out_rate);
+        }
+    }
+
+    #[test]
+    fn resample_stopband_attenuation() {
+        for &(in_rate, out_rate) in &RATES {
+            let label = format!("{} -> {} Hz", in_rate, out_rate);
+
+            if in_rate > out_rate {
+                // Frequencies above the output Nyquist frequency must not alias into the output.
+                let nyquist = f64::from(out_rate) / 2.0;
+
+                for freq in sweep(nyquist, f64::from(in_rate) / 2.0, 41) {
+                    let output = resample(in_rate, out_rate, &sine(freq, 0.5, in_rate));
+                    let db = level(&output, 0.5, out_rate);
+
+                    assert!(db <= -100.0, "{} at {} Hz: {} dB", label, freq, db);
+                }
+            }
+            else {
+                // The images of the passband above the input Nyquist frequency must be removed,
+                // which leaves only the fitted sinusoid.
+                let nyquist = f64::from(in_rate) / 2.0;
+
+                for freq in sweep(0.5 * nyquist, PASSBAND * nyquist, 11) {
+                    let output = resample(in_rate, out_rate, &sine(freq, 0.5, in_rate));
+                    let (amplitude, residual) = fit(&output, freq, out_rate);
+                    let db = to_db(residual * 2f64.sqrt() / amplitude);
+
+                    assert!(db <= -100.0, "{} at {} Hz: {} dB", label, freq, db);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resample_thd_n() {
+        // A 997 Hz sinusoid at -1 dBFS.
+        let amplitude = 10f64.powf(-1.0 / 20.0);
+
+        for &(in_rate, out_rate) in &RATES {
+            let output = resample(in_rate, out_rate, &sine(997.0, amplitude, in_rate));
+            let (fitted, residual) = fit(&output, 997.0, out_rate);
+            let thd_n = to_db(residual / fitted * 2f64.sqrt());
+
+            assert!(thd_n <= -120.0, "{} -> {} Hz: {} dB", in_rate, out_rate, thd_n);
+        }
+    }
+
+    #[test]
+    fn resample_flush_completes_stream() {
+        for &(in_rate, out_rate) in &RATES {
+            let spec = SignalSpec::new(in_rate, Channels::FRONT_LEFT);
+            let mut resampler = Resampler::new(spec, out_rate);
+
+            // The resampler starts a new stream after a flush.
+            for &n in &[1, 1000, 1152, 12345, 44100] {
+                let mut buf = AudioBuffer::<f32>::new(n, spec);
+                buf.render_reserved(Some(n as usize));
+
+                let input = AudioBufferRef::F32(Cow::Borrowed(&buf));
+                let len = resampler.process(&input).frames() + resampler.flush().frames();
+
+                let expected = (n * u64::from(out_rate)).div_ceil(u64::from(in_rate));
+                assert_eq!(len as u64, expected, "{} -> {} Hz, {} frames", in_rate, out_rate, n);
+            }
+
+            // Feeding the stream in buffers does not change the length.
+            let output = resample(in_rate, out_rate, &sine(1000.0, 0.5, in_rate));
+            assert_eq!(output.len(), out_rate as usize);
+        }
+    }
+}